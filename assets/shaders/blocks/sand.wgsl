@@ -1,68 +1,71 @@
-fn handle_sand(index: vec3<i32>) {
-    if (out_of_bounds(index)) {
-        return;
-    }
+// Sand falls straight down, or into any free cell of the 3x3 below it.
+// Every cell is updated by gathering from the input grid only, so the output buffer
+// never has to start as a copy of the input and the two grids can swap roles each tick.
+
+const SAND_TARGET_COUNT: i32 = 9;
 
-    let current_voxel = voxel_grid.voxels[get_index(index)];
-    if (current_voxel == EMPTY_VOXEL) {
-        // voxel_grid_out.voxels[get_index(index)] = current_voxel;
-        // storageBarrier();
-        return;
+// Where sand tries to move, in order of preference: straight down, then the rest of the 3x3 below
+fn sand_target_offset(i: i32) -> vec3<i32> {
+    if (i == 0) {
+        return vec3<i32>(0, -1, 0);
     }
+    // Skip the centre of the 3x3, it was tried first
+    var j = i - 1;
+    if (j >= 4) {
+        j += 1;
+    }
+    return vec3<i32>(j / 3 - 1, -1, j % 3 - 1);
+}
 
-    let below_block_index = vec3<i32>(index.x, index.y - 1, index.z);
+fn is_empty(index: vec3<i32>) -> bool {
+    return !out_of_bounds(index) && voxel_grid.voxels[get_index(index)] == EMPTY_VOXEL;
+}
 
-    if (!out_of_bounds(below_block_index)) {
-        let below_block = voxel_grid.voxels[get_index(below_block_index)];
-        // Ensure we have no write conflicts
-        if (voxel_grid_out.voxels[get_index(below_block_index)] == EMPTY_VOXEL) {
-            voxel_grid_out.voxels[get_index(index)] = EMPTY_VOXEL;
-            voxel_grid_out.voxels[get_index(below_block_index)] = current_voxel;
-            // storageBarrier();
-            return;
+fn is_sand(index: vec3<i32>) -> bool {
+    if (out_of_bounds(index)) {
+        return false;
+    }
+    let voxel = voxel_grid.voxels[get_index(index)];
+    return voxel != EMPTY_VOXEL && get_voxel_type(voxel) == VOXEL_TYPE_SAND;
+}
+
+// The cell a sand voxel wants to move into this tick, or its own cell if it can't move
+fn sand_destination(index: vec3<i32>) -> vec3<i32> {
+    for (var i = 0; i < SAND_TARGET_COUNT; i++) {
+        let destination = index + sand_target_offset(i);
+        if (is_empty(destination)) {
+            return destination;
         }
-        // if (voxel_grid_out.voxels[get_index(below_block_index)] == EMPTY_VOXEL) {
-        //     voxel_grid_out.voxels[get_index(index)] = EMPTY_VOXEL;
-        //     voxel_grid_out.voxels[get_index(below_block_index)] = current_voxel;
-        //     // storageBarrier();
-        //     return;
-        // }
+    }
+    return index;
+}
 
-        // Let's try to move to a different spot in the xz plane at y-1
-        // use index.x and index.y as hash inputs for the randomizer
-        for (var i = -1; i <= 1; i++) {
-            for (var j = -1; j <= 1; j++) {
-                let x_offset = i;
-                let z_offset = j;
-                let side_block_index = vec3<i32>(index.x + x_offset, index.y - 1, index.z + z_offset);
-                if (!out_of_bounds(side_block_index)) {
-                    let side_block = voxel_grid.voxels[get_index(side_block_index)];
-                    // Ensure no conflicts
-                    if (voxel_grid_out.voxels[get_index(side_block_index)] == EMPTY_VOXEL) {
-                        voxel_grid_out.voxels[get_index(index)] = EMPTY_VOXEL;
-                        voxel_grid_out.voxels[get_index(side_block_index)] = current_voxel;
-                        storageBarrier();
-                        return;
-                    }
-                }
-            }
+// The sand voxel that moves into an empty cell this tick, or the cell itself if none does.
+// When several want the same cell, the one moving in the most preferred direction wins.
+fn sand_origin(index: vec3<i32>) -> vec3<i32> {
+    for (var i = 0; i < SAND_TARGET_COUNT; i++) {
+        let origin = index - sand_target_offset(i);
+        if (is_sand(origin) && all(sand_destination(origin) == index)) {
+            return origin;
         }
-        // let x_offset = random_int(u32(index.x), -1, 1);
-        // let z_offset = random_int(u32(index.y), -1, 1);
-        // let side_block_index = vec3<i32>(index.x + x_offset, index.y - 1, index.z + z_offset);
-        // if (!out_of_bounds(side_block_index)) {
-        //     let side_block = voxel_grid.voxels[get_index(side_block_index)];
-        //     // Ensure no conflicts
-        //     if (voxel_grid_out.voxels[get_index(side_block_index)] == EMPTY_VOXEL) {
-        //         voxel_grid_out.voxels[get_index(index)] = EMPTY_VOXEL;
-        //         voxel_grid_out.voxels[get_index(side_block_index)] = current_voxel;
-        //         storageBarrier();
-        //         return;
-        //     }
-        // }
     }
+    return index;
+}
+
+// Next state of a cell holding sand
+fn handle_sand(index: vec3<i32>, voxel: u32) -> u32 {
+    let destination = sand_destination(index);
+    if (all(destination == index) || any(sand_origin(destination) != index)) {
+        return voxel;
+    }
+    return EMPTY_VOXEL;
+}
 
-    // If we weren't able to move the block before, store its current state
-    voxel_grid_out.voxels[get_index(index)] = current_voxel;
-    storageBarrier();
+// Next state of an empty cell, which may receive falling sand
+fn handle_falling_sand(index: vec3<i32>) -> u32 {
+    let origin = sand_origin(index);
+    if (all(origin == index)) {
+        return EMPTY_VOXEL;
+    }
+    return voxel_grid.voxels[get_index(origin)];
 }
@@ -0,0 +1,87 @@
+#import "shaders/voxel.wgsl"
+#import "shaders/player.wgsl"
+#import "shaders/regions.wgsl"
+
+// The older buffer of the pair. Edits are written to both buffers, so voxels outside of awake regions stay identical.
+@group(1) @binding(0)
+var<storage, read_write> voxel_grid_out: VoxelGrid;
+
+// Every change made during the current stroke, read back for undo once the mouse button is released
+@group(3) @binding(0)
+var<storage, read_write> edit_log: EditLog;
+
+struct EditLogEntry {
+    index: u32,
+    old_voxel: u32,
+    new_voxel: u32,
+}
+
+struct EditLog {
+    // Keeps counting past the end of `entries`, so an overflowing stroke can be detected
+    count: atomic<u32>,
+    entries: array<EditLogEntry>,
+}
+
+fn brush_contains(offset: vec3<i32>, radius: i32) -> bool {
+    let shape = player_data.brush_shape;
+    if (shape == BRUSH_SPHERE) {
+        return offset.x * offset.x + offset.y * offset.y + offset.z * offset.z <= radius * radius;
+    }
+    if (shape == BRUSH_CYLINDER) {
+        return offset.x * offset.x + offset.z * offset.z <= radius * radius;
+    }
+    return true;
+}
+
+fn apply_brush(voxel: u32) -> u32 {
+    let operation = player_data.brush_operation;
+    let material = player_data.brush_material;
+    if (operation == BRUSH_ADD && voxel == EMPTY_VOXEL) {
+        return material;
+    }
+    if (operation == BRUSH_REMOVE) {
+        return EMPTY_VOXEL;
+    }
+    if (operation == BRUSH_PAINT && voxel != EMPTY_VOXEL) {
+        return (material & ~255u) | get_voxel_type(voxel);
+    }
+    if (operation == BRUSH_REPLACE && voxel != EMPTY_VOXEL && get_voxel_type(voxel) == player_data.brush_replace_type) {
+        return material;
+    }
+    return voxel;
+}
+
+// Dispatched over the (2 * radius + 1)³ box around the brush center, only while a mouse button is held
+@compute @workgroup_size(8, 8, 8)
+fn edit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let selected = vec3<i32>(voxel_grid.selected);
+    if (out_of_bounds(selected)) {
+        return;
+    }
+
+    // Adding builds out from the face being pointed at
+    var center = selected;
+    if (player_data.brush_operation == BRUSH_ADD) {
+        center += vec3<i32>(voxel_grid.normal);
+    }
+
+    let radius = i32(player_data.brush_size);
+    let offset = vec3<i32>(invocation_id) - vec3<i32>(radius);
+    let index = center + offset;
+    if (any(offset > vec3<i32>(radius)) || out_of_bounds(index) || !brush_contains(offset, radius)) {
+        return;
+    }
+
+    let voxel = voxel_grid.voxels[get_index(index)];
+    let next_voxel = apply_brush(voxel);
+    if (next_voxel != voxel) {
+        voxel_grid.voxels[get_index(index)] = next_voxel;
+        voxel_grid_out.voxels[get_index(index)] = next_voxel;
+        wake_regions(index);
+
+        let slot = atomicAdd(&edit_log.count, 1u);
+        if (slot < arrayLength(&edit_log.entries)) {
+            edit_log.entries[slot] = EditLogEntry(get_index(index), voxel, next_voxel);
+        }
+    }
+}
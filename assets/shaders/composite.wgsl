@@ -0,0 +1,47 @@
+#import bevy_pbr::mesh_view_bindings
+
+// Raytraced voxels drawn over the whole screen inside the 3D pass, at the depth of their hits
+
+@group(1) @binding(0)
+var color_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var depth_texture: texture_2d<f32>;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// The quad spans -1 to 1, straight in clip space whatever the camera
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(vertex.position.xy, 0.0, 1.0);
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    let uv = (in.clip_position.xy - view.viewport.xy) / view.viewport.zw;
+    let coords = vec2<i32>(uv * vec2<f32>(textureDimensions(color_texture)));
+
+    var out: FragmentOutput;
+    out.color = textureLoad(color_texture, coords, 0);
+
+    // Back from view distance to the camera's depth, misses end up on the far plane
+    let distance = textureLoad(depth_texture, coords, 0).r;
+    out.depth = 0.0;
+    if (distance > 0.0) {
+        let clip = view.projection * vec4<f32>(0.0, 0.0, -distance, 1.0);
+        out.depth = clamp(clip.z / clip.w, 0.0, 1.0);
+    }
+    return out;
+}
@@ -5,5 +5,20 @@ struct PlayerData {
     camera_matrix: mat4x4<f32>,
     inverse_projection_matrix: mat4x4<f32>,
     mouse_click: u32,
+    // Brush radius
     brush_size: u32,
+    brush_shape: u32,
+    brush_operation: u32,
+    brush_material: u32,
+    brush_replace_type: u32,
 }
+
+// Matches `BrushShape` and `BrushOperation`
+const BRUSH_SPHERE = 0u;
+const BRUSH_CUBE = 1u;
+const BRUSH_CYLINDER = 2u;
+
+const BRUSH_ADD = 0u;
+const BRUSH_REMOVE = 1u;
+const BRUSH_PAINT = 2u;
+const BRUSH_REPLACE = 3u;
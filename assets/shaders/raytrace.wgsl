@@ -1,8 +1,126 @@
 #import "shaders/voxel.wgsl"
 #import "shaders/player.wgsl"
 
+// Linear HDR colors, tonemapped by the camera
 @group(1) @binding(0)
-var output_texture: texture_storage_2d<rgba8unorm, read_write>;
+var output_texture: texture_storage_2d<rgba16float, read_write>;
+
+// Distance of the first hit along the camera's view axis, 0 where the ray misses, so meshes can be depth tested against the voxels
+@group(1) @binding(1)
+var depth_texture: texture_storage_2d<r32float, write>;
+
+// Log luminance summed over a sparse grid of pixels for auto exposure, matches `ExposureMeter`
+struct ExposureMeter {
+    // Fixed point, scaled by EXPOSURE_METER_SCALE
+    log_luminance: atomic<i32>,
+    count: atomic<u32>,
+}
+
+@group(1) @binding(2)
+var<storage, read_write> exposure_meter: ExposureMeter;
+
+const EXPOSURE_METER_SPACING = 16u;
+const EXPOSURE_METER_SCALE = 256.0;
+
+// Matches `LightingUniform`
+struct Lighting {
+    // Towards the sun, normalized
+    sun_direction: vec3<f32>,
+    // Angular radius in radians
+    sun_radius: f32,
+    sun_color: vec3<f32>,
+    // 0 when shadows are off, 1 for hard shadows
+    shadow_samples: u32,
+    ambient_color: vec3<f32>,
+    ao_mode: u32,
+    // Rays per pixel for ray traced ambient occlusion
+    ao_samples: u32,
+    // How far ray traced ambient occlusion looks for occluders
+    ao_radius: f32,
+    // 0 leaves the ambient light alone, 1 blacks out fully occluded corners
+    ao_strength: f32,
+    // Whether rays bend entering and leaving transparent materials
+    refraction: u32,
+    // Transparent materials a ray passes through before the next one is drawn opaque
+    max_transparent_layers: u32,
+    // Reflections of reflections followed, 0 turns reflections off
+    max_bounces: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> lighting: Lighting;
+
+// Matches `GpuMaterial`, indexed by voxel type
+struct Material {
+    emission: f32,
+    transparent: u32,
+    // Alpha of the surface, blended over what is seen through it
+    opacity: f32,
+    // Absorption per voxel travelled, of the light the voxel color doesn't let through
+    density: f32,
+    refractive_index: f32,
+    // 0 is a mirror, 1 reflects nothing
+    roughness: f32,
+    // Metals reflect in their own color and have no diffuse light
+    metalness: f32,
+}
+
+@group(2) @binding(1)
+var<storage, read> materials: array<Material>;
+
+// Matches `GpuPointLight`
+struct PointLight {
+    position: vec3<f32>,
+    range: f32,
+    // Color times intensity
+    color: vec3<f32>,
+    // 1 for lights standing in for emissive voxels, which the path tracer hits instead
+    from_voxels: u32,
+}
+
+struct PointLights {
+    count: u32,
+    lights: array<PointLight>,
+}
+
+@group(2) @binding(2)
+var<storage, read> point_lights: PointLights;
+
+// Matches `AtmosphereUniform`
+struct Atmosphere {
+    zenith_color: vec3<f32>,
+    sky_model: u32,
+    horizon_color: vec3<f32>,
+    turbidity: f32,
+    ground_color: vec3<f32>,
+    sky_brightness: f32,
+    fog_color: vec3<f32>,
+    // Fog per unit of distance at `fog_height`, 0 when fog is off
+    fog_density: f32,
+    fog_height: f32,
+    fog_falloff: f32,
+    fog_sun_scattering: f32,
+    sun_disk_brightness: f32,
+}
+
+@group(2) @binding(3)
+var<uniform> atmosphere: Atmosphere;
+
+// Matches `SkyModel`
+const SKY_GRADIENT = 0u;
+const SKY_PREETHAM = 1u;
+
+const NO_MAX_DISTANCE: f32 = 1.0e30;
+// Medium of rays outside of any transparent material
+const AIR: u32 = 0xffffffffu;
+
+// Matches `AmbientOcclusion`
+const AO_OFF = 0u;
+const AO_VERTEX = 1u;
+const AO_RAY_TRACED = 2u;
+
+// Offsets shadow rays off the face they start from, so they don't hit their own voxel
+const SHADOW_BIAS: f32 = 0.01;
 
 fn ray_grid_intersection(ray_origin: vec3<f32>, ray_direction: vec3<f32>, grid_position: vec3<f32>, grid_size: vec3<f32>) -> vec3<f32> {
     let t_min: vec3<f32> = (grid_position - ray_origin) / ray_direction;
@@ -19,42 +137,103 @@ fn ray_grid_intersection(ray_origin: vec3<f32>, ray_direction: vec3<f32>, grid_p
     return ray_origin + ray_direction * t_enter;
 }
 
-@compute @workgroup_size(8, 8, 1)
-fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let dim = f32(voxel_grid.dim);
-    let grid_pos = voxel_grid.pos;
-    let pixel_coords = invocation_id.xy;
+// Where a ray first hits a solid voxel
+struct Hit {
+    hit: bool,
+    index: vec3<i32>,
+    voxel: u32,
+    // Axis of the last step, the face the ray entered the voxel through
+    mask: vec3<bool>,
+    // Distance along the ray to the hit, only valid when `hit` is set
+    distance: f32,
+}
 
-    let camera_matrix = player_data.camera_matrix;
-    let inverse_projection_matrix = player_data.inverse_projection_matrix;
+// Entry and exit distance of a ray through an axis aligned box, the ray misses when entry > exit
+fn ray_box(ray_origin: vec3<f32>, ray_direction: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>) -> vec2<f32> {
+    let t_min: vec3<f32> = (box_min - ray_origin) / ray_direction;
+    let t_max: vec3<f32> = (box_max - ray_origin) / ray_direction;
 
-    let screen_size = vec2<f32>(textureDimensions(output_texture));
-    let ndc_space = ((vec2<f32>(f32(pixel_coords.x), screen_size.y - f32(pixel_coords.y)) / screen_size) * 2.0) - vec2<f32>(1.0);
+    let t_enter: f32 = max(max(min(t_min.x, t_max.x), min(t_min.y, t_max.y)), min(t_min.z, t_max.z));
+    let t_exit: f32 = min(min(max(t_min.x, t_max.x), max(t_min.y, t_max.y)), max(t_min.z, t_max.z));
+    return vec2<f32>(t_enter, t_exit);
+}
 
-    let ray_start = camera_matrix * inverse_projection_matrix * vec4<f32>(ndc_space, 0.0, 1.0);
-    let ray_end = camera_matrix * inverse_projection_matrix * vec4<f32>(ndc_space, 1.0, 1.0);
-    let ray_direction = normalize((ray_end.xyz / ray_end.w) - (ray_start.xyz / ray_start.w));
+fn ray_sphere(ray_origin: vec3<f32>, ray_direction: vec3<f32>, center: vec3<f32>, radius: f32) -> vec2<f32> {
+    let offset = ray_origin - center;
+    let b = dot(offset, ray_direction);
+    let c = dot(offset, offset) - radius * radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return vec2<f32>(1.0, -1.0);
+    }
+    let root = sqrt(discriminant);
+    return vec2<f32>(-b - root, -b + root);
+}
+
+// Upright cylinder, clipped to `half_height` above and below the center
+fn ray_cylinder(ray_origin: vec3<f32>, ray_direction: vec3<f32>, center: vec3<f32>, radius: f32, half_height: f32) -> vec2<f32> {
+    let offset = (ray_origin - center).xz;
+    let direction = ray_direction.xz;
+    let a = dot(direction, direction);
+    let b = dot(offset, direction);
+    let c = dot(offset, offset) - radius * radius;
+
+    var side = vec2<f32>(-1.0e30, 1.0e30);
+    if (a > 0.0) {
+        let discriminant = b * b - a * c;
+        if (discriminant < 0.0) {
+            return vec2<f32>(1.0, -1.0);
+        }
+        let root = sqrt(discriminant);
+        side = vec2<f32>((-b - root) / a, (-b + root) / a);
+    } else if (c > 0.0) {
+        // Parallel to the axis and outside of it
+        return vec2<f32>(1.0, -1.0);
+    }
+
+    let caps = ray_box(ray_origin, ray_direction, center - vec3<f32>(1.0e30, half_height, 1.0e30), center + vec3<f32>(1.0e30, half_height, 1.0e30));
+    return vec2<f32>(max(side.x, caps.x), min(side.y, caps.y));
+}
+
+// Whether a ray travelling through `medium` stops at a voxel: in air at any solid voxel,
+// inside a transparent material wherever that material ends, even into air
+fn stops_in(voxel: u32, medium: u32) -> bool {
+    if (medium == AIR) {
+        return voxel != EMPTY_VOXEL;
+    }
+    return voxel == EMPTY_VOXEL || get_voxel_type(voxel) != medium;
+}
 
+// Steps through the grid until the ray stops in its medium, or has gone further than `max_distance`.
+// `medium` is AIR or the voxel type of the transparent material the ray starts in.
+fn raymarch(ray_origin: vec3<f32>, ray_direction: vec3<f32>, max_distance: f32, medium: u32) -> Hit {
+    let dim = f32(voxel_grid.dim);
+    let grid_pos = voxel_grid.pos;
     let grid_size = vec3<f32>(dim);
     let boundary_bottom_left = vec3<i32>(grid_pos);
     let boundary_top_right = vec3<i32>(grid_pos + (grid_size * VOXEL_SIZE));
 
-    var voxel_position = vec3<i32>(ray_start.xyz);
+    var voxel_position = vec3<i32>(ray_origin);
     if (voxel_position.x < boundary_bottom_left.x || voxel_position.y < boundary_bottom_left.y || voxel_position.z < boundary_bottom_left.z ||
         voxel_position.x >= boundary_top_right.x || voxel_position.y >= boundary_top_right.y || voxel_position.z >= boundary_top_right.z) {
-        voxel_position = vec3<i32>(ray_grid_intersection(ray_start.xyz, ray_direction, grid_pos, grid_size));
+        voxel_position = vec3<i32>(ray_grid_intersection(ray_origin, ray_direction, grid_pos, grid_size));
     }
 
 
     let delta_dist = abs(1.0 / (ray_direction * VOXEL_SIZE));
     let step = vec3<i32>(sign(ray_direction) * VOXEL_SIZE);
-    var side_dist = (sign(ray_direction) * (vec3<f32>(voxel_position) - ray_start.xyz) + (sign(ray_direction) * 0.5) + 0.5) * delta_dist;
+    var side_dist = (sign(ray_direction) * (vec3<f32>(voxel_position) - ray_origin) + (sign(ray_direction) * 0.5) + 0.5) * delta_dist;
 
-    var color = vec4<f32>(0.0);
+    var hit: Hit;
+    hit.hit = false;
+    hit.mask = vec3<bool>(false);
     let maxSteps = u32(dim * 2.0);
-    var mask = vec3<bool>(false);
-    let center_pixel = ndc_space.x == 0.0 && ndc_space.y == 0.0;
+    // Distance along the ray to where it entered the current voxel
+    var travelled = 0.0;
     for (var i = 0u; i < maxSteps; i++) {
+        if (travelled > max_distance) {
+            break;
+        }
         if (voxel_position.x < boundary_bottom_left.x || voxel_position.x > boundary_top_right.x ||
             voxel_position.y < boundary_bottom_left.y || voxel_position.y > boundary_top_right.y ||
             voxel_position.z < boundary_bottom_left.z || voxel_position.z > boundary_top_right.z) {
@@ -62,80 +241,761 @@ fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         }
 
         // let index = vec3<f32>(vec3<f32>((voxel_position-vec3<i32>(grid_pos))) / VOXEL_SIZE);
-        let index = vec3<f32>(voxel_position);
-        var voxel = voxel_grid.voxels[get_index(vec3<i32>(index))];
-        if (out_of_bounds(vec3<i32>(index))) {
+        let index = voxel_position;
+        var voxel = voxel_grid.voxels[get_index(index)];
+        if (out_of_bounds(index)) {
             voxel = EMPTY_VOXEL;
             // break;
         }
-        if (voxel != EMPTY_VOXEL) {
-            color = vec4<f32>(get_voxel_color(voxel), 1.0);
-            // if (get_voxel_type(voxel) == 1u) {
-            //     color.w = 0.1;
-            // }
-
-            let center_voxel_already_selected = voxel_grid.selected.x == index.x && voxel_grid.selected.y == index.y && voxel_grid.selected.z == index.z;
-            if (center_pixel) {
-                voxel_grid.selected = index;
-            }
-
-            // TODO: Render brush as sphere with radius, in separate function
-            if (center_voxel_already_selected) {
-                color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
-            }
+        if (stops_in(voxel, medium)) {
+            hit.hit = true;
+            hit.index = index;
+            hit.voxel = voxel;
+            let voxel_min = vec3<f32>(index) * VOXEL_SIZE;
+            hit.distance = max(ray_box(ray_origin, ray_direction, voxel_min, voxel_min + VOXEL_SIZE).x, 0.0);
             break;
         }
 
+        travelled = min(side_dist.x, min(side_dist.y, side_dist.z));
         if (side_dist.x < side_dist.y) {
             if (side_dist.x < side_dist.z) {
                 side_dist.x += delta_dist.x;
                 voxel_position.x += step.x;
-                mask = vec3<bool>(true, false, false);
+                hit.mask = vec3<bool>(true, false, false);
             } else {
                 side_dist.z += delta_dist.z;
                 voxel_position.z += step.z;
-                mask = vec3<bool>(false, false, true);
+                hit.mask = vec3<bool>(false, false, true);
             }
         } else {
             if (side_dist.y < side_dist.z) {
                 side_dist.y += delta_dist.y;
                 voxel_position.y += step.y;
-                mask = vec3<bool>(false, true, false);
+                hit.mask = vec3<bool>(false, true, false);
             } else {
                 side_dist.z += delta_dist.z;
                 voxel_position.z += step.z;
-                mask = vec3<bool>(false, false, true);
+                hit.mask = vec3<bool>(false, false, true);
             }
         }
 
     }
-    if (mask.y) {
-        color *= 0.9;
+    return hit;
+}
+
+// Normal of the face a ray hit, from the axis of its last step
+fn face_normal(mask: vec3<bool>, ray_direction: vec3<f32>) -> vec3<f32> {
+    return -sign(ray_direction) * vec3<f32>(mask);
+}
+
+// A direction within `radius` radians of `direction`, spread evenly over the cone for uniform random inputs
+fn sample_cone(direction: vec3<f32>, radius: f32, random: vec2<f32>) -> vec3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(direction.y) > 0.99) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, direction));
+    let bitangent = cross(direction, tangent);
+    let angle = random.x * 6.28318530718;
+    let distance = sqrt(random.y) * tan(radius);
+    return normalize(direction + (tangent * cos(angle) + bitangent * sin(angle)) * distance);
+}
+
+// Light getting through along a shadow ray, per color channel. Transparent materials let through what
+// their opacity and absorption leave, without bending it, and anything opaque blocks the ray.
+fn shadow_transmittance(ray_origin: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    var transmittance = vec3<f32>(1.0);
+    var origin = ray_origin;
+    var hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+    for (var layer = 0u; hit.hit; layer++) {
+        let surface = material(hit.voxel);
+        if (surface.transparent == 0u || layer >= lighting.max_transparent_layers) {
+            return vec3<f32>(0.0);
+        }
+        transmittance *= 1.0 - surface.opacity;
+
+        let entry = origin + direction * (hit.distance + SHADOW_BIAS);
+        let exit = raymarch(entry, direction, NO_MAX_DISTANCE, get_voxel_type(hit.voxel));
+        if (!exit.hit) {
+            break;
+        }
+        transmittance *= exp(-surface.density * (1.0 - get_voxel_color(hit.voxel)) * exit.distance);
+        if (exit.voxel != EMPTY_VOXEL) {
+            origin = entry;
+            hit = exit;
+        } else {
+            origin = entry + direction * (exit.distance + SHADOW_BIAS);
+            hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+        }
+    }
+    return transmittance;
+}
+
+// Sunlight reaching a point as a fraction per color channel, 1 without shadows
+fn sun_visibility(position: vec3<f32>, normal: vec3<f32>, seed: u32) -> vec3<f32> {
+    let samples = lighting.shadow_samples;
+    if (samples == 0u) {
+        return vec3<f32>(1.0);
+    }
+    let origin = position + normal * SHADOW_BIAS;
+    if (samples == 1u) {
+        return shadow_transmittance(origin, lighting.sun_direction);
     }
-    if (mask.z) {
-        color *= 0.75;
+
+    var lit = vec3<f32>(0.0);
+    for (var i = 0u; i < samples; i++) {
+        let random = vec2<f32>(random_float(seed + i * 2u), random_float(seed + i * 2u + 1u));
+        let direction = sample_cone(lighting.sun_direction, lighting.sun_radius, random);
+        lit += shadow_transmittance(origin, direction);
     }
+    return lit / f32(samples);
+}
 
-    // if (color.x != 0.0 || color.y != 0.0 || color.z != 0.0) {
-    //     color.w = 1.0;
-    // }
+fn is_solid(index: vec3<i32>) -> bool {
+    return !out_of_bounds(index) && voxel_grid.voxels[get_index(index)] != EMPTY_VOXEL;
+}
 
-    if (center_pixel) {
-        voxel_grid.normal = vec3<f32>(0.0);
-        if (mask.x) {
-            voxel_grid.normal.x = -sign(ray_direction.x);
+// Ambient light reaching a corner of a face, from the voxels next to the corner in front of the face
+fn corner_occlusion(side1: bool, side2: bool, corner: bool) -> f32 {
+    if (side1 && side2) {
+        return 0.0;
+    }
+    return (3.0 - f32(side1) - f32(side2) - f32(corner)) / 3.0;
+}
+
+// Occlusion at the four corners of the hit face, interpolated across it by where the ray hit
+fn vertex_occlusion(index: vec3<i32>, position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    var u = vec3<i32>(1, 0, 0);
+    var v = vec3<i32>(0, 0, 1);
+    if (normal.x != 0.0) {
+        u = vec3<i32>(0, 1, 0);
+    } else if (normal.z != 0.0) {
+        v = vec3<i32>(0, 1, 0);
+    }
+    let front = index + vec3<i32>(normal);
+
+    var corners: array<f32, 4>;
+    for (var i = 0; i < 4; i++) {
+        let du = u * select(-1, 1, (i & 1) != 0);
+        let dv = v * select(-1, 1, (i & 2) != 0);
+        corners[i] = corner_occlusion(is_solid(front + du), is_solid(front + dv), is_solid(front + du + dv));
+    }
+
+    let local = clamp((position - vec3<f32>(index)) / VOXEL_SIZE, vec3<f32>(0.0), vec3<f32>(1.0));
+    let fu = dot(local, vec3<f32>(u));
+    let fv = dot(local, vec3<f32>(v));
+    return mix(mix(corners[0], corners[1], fu), mix(corners[2], corners[3], fu), fv);
+}
+
+// Fraction of short rays over the hemisphere of the face that escape, cosine weighted
+fn ray_traced_occlusion(position: vec3<f32>, normal: vec3<f32>, seed: u32) -> f32 {
+    let origin = position + normal * SHADOW_BIAS;
+    let samples = max(lighting.ao_samples, 1u);
+    var open = 0u;
+    for (var i = 0u; i < samples; i++) {
+        let z = random_float(seed + i * 2u) * 2.0 - 1.0;
+        let angle = random_float(seed + i * 2u + 1u) * 6.28318530718;
+        let r = sqrt(1.0 - z * z);
+        let direction = normalize(normal + vec3<f32>(r * cos(angle), r * sin(angle), z));
+        if (!raymarch(origin, direction, lighting.ao_radius, AIR).hit) {
+            open += 1u;
+        }
+    }
+    return f32(open) / f32(samples);
+}
+
+fn ambient_occlusion(index: vec3<i32>, position: vec3<f32>, normal: vec3<f32>, seed: u32) -> f32 {
+    var open = 1.0;
+    if (lighting.ao_mode == AO_VERTEX) {
+        open = vertex_occlusion(index, position, normal);
+    } else if (lighting.ao_mode == AO_RAY_TRACED) {
+        open = ray_traced_occlusion(position, normal, seed);
+    }
+    return mix(1.0, open, lighting.ao_strength);
+}
+
+// Brightness a voxel glows with, 0 unless its material is emissive
+fn material_emission(voxel: u32) -> f32 {
+    let voxel_type = get_voxel_type(voxel);
+    if (voxel_type >= arrayLength(&materials)) {
+        return 0.0;
+    }
+    return materials[voxel_type].emission;
+}
+
+// Light from the point lights, falling off with the square of the distance and fading out at their range.
+// Emissive voxels don't cast shadows, lights made from them sit inside them.
+// Unlike the sun's, these shadows are fully opaque even behind transparent materials.
+fn point_light(position: vec3<f32>, normal: vec3<f32>, include_voxel_lights: bool) -> vec3<f32> {
+    var light = vec3<f32>(0.0);
+    let origin = position + normal * SHADOW_BIAS;
+    for (var i = 0u; i < point_lights.count; i++) {
+        let point = point_lights.lights[i];
+        if (point.from_voxels != 0u && !include_voxel_lights) {
+            continue;
+        }
+        let offset = point.position - position;
+        let distance = length(offset);
+        let direction = offset / distance;
+        let facing = dot(normal, direction);
+        if (distance >= point.range || facing <= 0.0) {
+            continue;
+        }
+        let shadow = raymarch(origin, direction, distance, AIR);
+        if (shadow.hit && shadow.distance < distance - VOXEL_SIZE && material_emission(shadow.voxel) <= 0.0) {
+            continue;
+        }
+        let fade = 1.0 - pow(distance / point.range, 4.0);
+        light += point.color * facing * fade * fade / max(distance * distance, 1.0);
+    }
+    return light;
+}
+
+fn material(voxel: u32) -> Material {
+    let voxel_type = get_voxel_type(voxel);
+    if (voxel_type >= arrayLength(&materials)) {
+        return Material(0.0, 0u, 1.0, 0.0, 1.0, 1.0, 0.0);
+    }
+    return materials[voxel_type];
+}
+
+// Bends a ray crossing into a material with `eta` times the refractive index of the one it leaves,
+// unless refraction is off or the ray is reflected back instead
+fn bend(direction: vec3<f32>, normal: vec3<f32>, eta: f32) -> vec3<f32> {
+    if (lighting.refraction == 0u) {
+        return direction;
+    }
+    let refracted = refract(direction, normal, eta);
+    if (all(refracted == vec3<f32>(0.0))) {
+        return direction;
+    }
+    return normalize(refracted);
+}
+
+// Lambert shading by the sun and the point lights on top of the ambient light, plus the voxel's own glow
+fn shade(voxel: u32, index: vec3<i32>, position: vec3<f32>, normal: vec3<f32>, seed: u32) -> vec3<f32> {
+    let albedo = get_voxel_color(voxel);
+    var light = lighting.ambient_color * ambient_occlusion(index, position, normal, hash(seed + 1u));
+    let facing = max(dot(normal, lighting.sun_direction), 0.0);
+    if (facing > 0.0) {
+        light += lighting.sun_color * facing * sun_visibility(position, normal, seed);
+    }
+    light += point_light(position, normal, true);
+    return albedo * (light + material_emission(voxel));
+}
+
+const PREVIEW_COLOR: vec3<f32> = vec3<f32>(1.0, 1.0, 1.0);
+const PREVIEW_OPACITY: f32 = 0.2;
+const GHOST_OPACITY: f32 = 0.5;
+
+// Whether an interval from one of the ray intersections is in front of the camera and of the hit voxel
+fn in_view(interval: vec2<f32>, hit_distance: f32) -> bool {
+    return interval.x <= interval.y && interval.y > 0.0 && max(interval.x, 0.0) < hit_distance;
+}
+
+// Blends a translucent outline of the brush volume over the pixel, and a ghost of the voxel that adding would place
+fn brush_preview(color: vec4<f32>, ray_origin: vec3<f32>, ray_direction: vec3<f32>, hit_distance: f32) -> vec4<f32> {
+    let selected = vec3<i32>(voxel_grid.selected);
+    if (out_of_bounds(selected)) {
+        return color;
+    }
+    let placed = selected + vec3<i32>(voxel_grid.normal);
+
+    var center = selected;
+    if (player_data.brush_operation == BRUSH_ADD) {
+        center = placed;
+    }
+    // Cover the whole of the voxels at the edge of the brush
+    let center_position = (vec3<f32>(center) + 0.5) * VOXEL_SIZE;
+    let radius = (f32(player_data.brush_size) + 0.5) * VOXEL_SIZE;
+
+    var volume: vec2<f32>;
+    let shape = player_data.brush_shape;
+    if (shape == BRUSH_SPHERE) {
+        volume = ray_sphere(ray_origin, ray_direction, center_position, radius);
+    } else if (shape == BRUSH_CYLINDER) {
+        volume = ray_cylinder(ray_origin, ray_direction, center_position, radius, radius);
+    } else {
+        volume = ray_box(ray_origin, ray_direction, center_position - radius, center_position + radius);
+    }
+
+    var result = color;
+    if (in_view(volume, hit_distance)) {
+        result = vec4<f32>(mix(result.xyz, PREVIEW_COLOR, PREVIEW_OPACITY), 1.0);
+    }
+
+    if (player_data.brush_operation == BRUSH_ADD) {
+        let ghost_min = vec3<f32>(placed) * VOXEL_SIZE;
+        let ghost = ray_box(ray_origin, ray_direction, ghost_min, ghost_min + VOXEL_SIZE);
+        if (in_view(ghost, hit_distance)) {
+            result = vec4<f32>(mix(result.xyz, get_voxel_color(player_data.brush_material), GHOST_OPACITY), 1.0);
         }
-        else if (mask.y) {
-            voxel_grid.normal.y = -sign(ray_direction.y);
+    }
+    return result;
+}
+
+// Perez et al.'s sky luminance distribution, for the luminance and both chromaticities at once.
+// `theta` is the angle from the zenith, `gamma` the angle from the sun.
+fn perez(cos_theta: f32, gamma: f32, cos_gamma: f32, a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>, e: vec3<f32>) -> vec3<f32> {
+    return (1.0 + a * exp(b / cos_theta)) * (1.0 + c * exp(d * gamma) + e * cos_gamma * cos_gamma);
+}
+
+// Preetham, Shirley and Smits' analytic daylight model above the horizon, in linear RGB.
+// Only relative luminance is kept, the zenith is as bright as `sky_brightness`.
+fn preetham_sky(direction: vec3<f32>) -> vec3<f32> {
+    let t = atmosphere.turbidity;
+    // The model breaks down once the sun sets, the sky just fades out instead
+    let theta_sun = acos(clamp(lighting.sun_direction.y, 0.0, 1.0));
+    let daylight = max(smoothstep(-0.1, 0.1, lighting.sun_direction.y), 0.02);
+
+    // Coefficients for luminance Y and chromaticities x and y
+    let a = vec3<f32>(0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608);
+    let b = vec3<f32>(-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092);
+    let c = vec3<f32>(-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102);
+    let d = vec3<f32>(0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537);
+    let e = vec3<f32>(-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529);
+
+    let turbidity = vec3<f32>(t * t, t, 1.0);
+    let sun = vec4<f32>(theta_sun * theta_sun * theta_sun, theta_sun * theta_sun, theta_sun, 1.0);
+    let zenith_x = dot(turbidity, vec3<f32>(
+        dot(vec4<f32>(0.00166, -0.00375, 0.00209, 0.0), sun),
+        dot(vec4<f32>(-0.02903, 0.06377, -0.03202, 0.00394), sun),
+        dot(vec4<f32>(0.11693, -0.21196, 0.06052, 0.25886), sun),
+    ));
+    let zenith_y = dot(turbidity, vec3<f32>(
+        dot(vec4<f32>(0.00275, -0.00610, 0.00317, 0.0), sun),
+        dot(vec4<f32>(-0.04214, 0.08970, -0.04153, 0.00516), sun),
+        dot(vec4<f32>(0.15346, -0.26756, 0.06670, 0.26688), sun),
+    ));
+
+    let cos_gamma = clamp(dot(direction, lighting.sun_direction), -1.0, 1.0);
+    let sky = perez(max(direction.y, 0.01), acos(cos_gamma), cos_gamma, a, b, c, d, e);
+    let zenith = perez(1.0, theta_sun, cos(theta_sun), a, b, c, d, e);
+    let yxy = vec3<f32>(1.0, zenith_x, zenith_y) * sky / zenith;
+
+    // Yxy to XYZ to linear sRGB
+    let luminance = yxy.x * atmosphere.sky_brightness * daylight;
+    let xyz = vec3<f32>(yxy.y / yxy.z * luminance, luminance, (1.0 - yxy.y - yxy.z) / yxy.z * luminance);
+    return max(vec3<f32>(
+        dot(vec3<f32>(3.2406, -1.5372, -0.4986), xyz),
+        dot(vec3<f32>(-0.9689, 1.8758, 0.0415), xyz),
+        dot(vec3<f32>(0.0557, -0.2040, 1.0570), xyz),
+    ), vec3<f32>(0.0));
+}
+
+fn sky_above_horizon(direction: vec3<f32>) -> vec3<f32> {
+    if (atmosphere.sky_model == SKY_PREETHAM) {
+        return preetham_sky(direction);
+    }
+    return mix(atmosphere.horizon_color, atmosphere.zenith_color, sqrt(clamp(direction.y, 0.0, 1.0)));
+}
+
+// The sky without the sun disk, fading from the horizon into the ground color below it
+fn sky_dome(direction: vec3<f32>) -> vec3<f32> {
+    if (direction.y < 0.0) {
+        let horizon = sky_above_horizon(normalize(vec3<f32>(direction.x, 0.0, direction.z) + vec3<f32>(0.0, 0.0, 1.0e-6)));
+        return mix(horizon, atmosphere.ground_color, clamp(-direction.y * 4.0, 0.0, 1.0));
+    }
+    return sky_above_horizon(direction);
+}
+
+// The sky with the sun in it
+fn sky(direction: vec3<f32>) -> vec3<f32> {
+    var color = sky_dome(direction);
+    let sun = dot(direction, lighting.sun_direction);
+    if (sun > cos(max(lighting.sun_radius, 0.005))) {
+        color += lighting.sun_color * atmosphere.sun_disk_brightness;
+    }
+    return color;
+}
+
+// Share of what is seen along a ray that fog hides, from the fog density integrated over the heights the ray passes.
+// The density falls off exponentially above `fog_height`.
+fn fog_amount(origin: vec3<f32>, direction: vec3<f32>, distance: f32) -> f32 {
+    if (atmosphere.fog_density <= 0.0) {
+        return 0.0;
+    }
+    let falloff = atmosphere.fog_falloff;
+    var depth = atmosphere.fog_density * exp(min(-falloff * (origin.y - atmosphere.fog_height), 20.0)) * distance;
+    let climb = falloff * direction.y * distance;
+    if (abs(climb) > 1.0e-4) {
+        depth *= (1.0 - exp(-climb)) / climb;
+    }
+    return 1.0 - exp(-depth);
+}
+
+// Fog brightened by the sunlight it scatters towards the camera
+fn fog_color(direction: vec3<f32>) -> vec3<f32> {
+    let towards_sun = max(dot(direction, lighting.sun_direction), 0.0);
+    return atmosphere.fog_color + lighting.sun_color * atmosphere.fog_sun_scattering * pow(towards_sun, 8.0);
+}
+
+// Share of the light a surface reflects, by Schlick's approximation of Fresnel's equations.
+// Rough surfaces scatter their reflection into the diffuse light instead.
+fn reflectance(surface: Material, albedo: vec3<f32>, normal: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    var dielectric = 0.04;
+    if (surface.transparent != 0u) {
+        let ratio = (surface.refractive_index - 1.0) / (surface.refractive_index + 1.0);
+        dielectric = ratio * ratio;
+    }
+    let f0 = mix(vec3<f32>(dielectric), albedo, surface.metalness);
+    let facing = clamp(dot(-direction, normal), 0.0, 1.0);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - facing, 5.0);
+    return fresnel * (1.0 - clamp(surface.roughness, 0.0, 1.0));
+}
+
+// A mirror reflection, spread around randomly for rough surfaces
+fn reflection_direction(direction: vec3<f32>, normal: vec3<f32>, roughness: f32, seed: u32) -> vec3<f32> {
+    let mirrored = reflect(direction, normal);
+    let z = random_float(seed) * 2.0 - 1.0;
+    let angle = random_float(seed + 1u) * 6.28318530718;
+    let r = sqrt(1.0 - z * z);
+    let scattered = normalize(mirrored + vec3<f32>(r * cos(angle), r * sin(angle), z) * roughness);
+    // Keep it above the surface
+    if (dot(scattered, normal) <= 0.0) {
+        return mirrored;
+    }
+    return scattered;
+}
+
+// Diffuse light of a surface, metals have none
+fn surface_color(voxel: u32, surface: Material, index: vec3<i32>, position: vec3<f32>, normal: vec3<f32>, seed: u32) -> vec3<f32> {
+    return shade(voxel, index, position, normal, seed) * (1.0 - surface.metalness);
+}
+
+// Follows a reflected ray from bounce to bounce, everything it hits is drawn opaque
+fn reflections(ray_origin: vec3<f32>, ray_direction: vec3<f32>, seed: u32) -> vec3<f32> {
+    var origin = ray_origin;
+    var direction = ray_direction;
+    var color = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var bounce = 0u; bounce < lighting.max_bounces; bounce++) {
+        let hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+        if (!hit.hit) {
+            color += throughput * sky(direction);
+            break;
+        }
+        let position = origin + direction * hit.distance;
+        let normal = face_normal(hit.mask, direction);
+        let surface = material(hit.voxel);
+        let albedo = get_voxel_color(hit.voxel);
+        let reflected = reflectance(surface, albedo, normal, direction);
+        color += throughput * (1.0 - reflected) * surface_color(hit.voxel, surface, hit.index, position, normal, seed + bounce * 4u);
+        throughput *= reflected;
+        if (all(throughput < vec3<f32>(0.01))) {
+            break;
+        }
+        origin = position + normal * SHADOW_BIAS;
+        direction = reflection_direction(direction, normal, surface.roughness, seed + bounce * 4u + 2u);
+    }
+    return color;
+}
+
+// Color seen along a ray from its first hit on, continuing through transparent materials.
+// Each surface passed is blended over what lies behind it, and the light behind is absorbed along the way
+// by Beer-Lambert's law, so thicker layers tint it more.
+fn trace(first_hit: Hit, ray_origin: vec3<f32>, ray_direction: vec3<f32>, seed: u32) -> vec3<f32> {
+    var hit = first_hit;
+    var origin = ray_origin;
+    var direction = ray_direction;
+    var color = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var layer = 0u; hit.hit; layer++) {
+        let position = origin + direction * hit.distance;
+        let normal = face_normal(hit.mask, direction);
+        let surface = material(hit.voxel);
+        let diffuse = surface_color(hit.voxel, surface, hit.index, position, normal, seed + layer * 2u);
+
+        // Reflected off the surface, the rest is drawn or goes through it
+        let reflected = reflectance(surface, get_voxel_color(hit.voxel), normal, direction);
+        if (lighting.max_bounces > 0u && any(reflected > vec3<f32>(0.0))) {
+            let reflection = reflection_direction(direction, normal, surface.roughness, hash(seed + layer));
+            color += throughput * reflected * reflections(position + normal * SHADOW_BIAS, reflection, hash(seed + layer) + 8u);
+        }
+        throughput *= 1.0 - reflected;
+
+        if (surface.transparent == 0u || layer >= lighting.max_transparent_layers) {
+            color += throughput * diffuse;
+            break;
+        }
+        color += throughput * diffuse * surface.opacity;
+        throughput *= 1.0 - surface.opacity;
+
+        // Through the material, to wherever it ends
+        let medium = get_voxel_type(hit.voxel);
+        let inside = bend(direction, normal, 1.0 / surface.refractive_index);
+        let entry = position - normal * SHADOW_BIAS;
+        let exit = raymarch(entry, inside, NO_MAX_DISTANCE, medium);
+        let absorption = surface.density * (1.0 - get_voxel_color(hit.voxel));
+        if (!exit.hit) {
+            // Out of the grid
+            color += throughput * sky(inside);
+            break;
         }
-        else if (mask.z) {
-            voxel_grid.normal.z = -sign(ray_direction.z);
+        throughput *= exp(-absorption * exit.distance);
+
+        if (exit.voxel != EMPTY_VOXEL) {
+            // Straight into another material
+            origin = entry;
+            direction = inside;
+            hit = exit;
+        } else {
+            // Back out into the air
+            let exit_position = entry + inside * exit.distance;
+            direction = bend(inside, face_normal(exit.mask, inside), surface.refractive_index);
+            origin = exit_position + direction * SHADOW_BIAS;
+            hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+            if (!hit.hit) {
+                color += throughput * sky(direction);
+            }
         }
     }
+    return color;
+}
+
+struct Ray {
+    origin: vec3<f32>,
+    direction: vec3<f32>,
+}
+
+// World space ray through a point of the screen, in pixels from the top left
+fn camera_ray(pixel: vec2<f32>, screen_size: vec2<f32>) -> Ray {
+    let camera_matrix = player_data.camera_matrix;
+    let inverse_projection_matrix = player_data.inverse_projection_matrix;
+    let ndc_space = ((vec2<f32>(pixel.x, screen_size.y - pixel.y) / screen_size) * 2.0) - vec2<f32>(1.0);
+
+    // From the near plane, at depth 1, towards a point further in. Depth 0 is infinitely far away.
+    let ray_start = camera_matrix * inverse_projection_matrix * vec4<f32>(ndc_space, 1.0, 1.0);
+    let ray_end = camera_matrix * inverse_projection_matrix * vec4<f32>(ndc_space, 0.5, 1.0);
+    let ray_direction = normalize((ray_end.xyz / ray_end.w) - (ray_start.xyz / ray_start.w));
+    return Ray(ray_start.xyz, ray_direction);
+}
+
+// Writes what the crosshair is on into the grid header, for picking and the brush
+fn select_voxel(hit: Hit, ray_direction: vec3<f32>) {
+    if (hit.hit) {
+        voxel_grid.selected = vec3<f32>(hit.index);
+        voxel_grid.selected_voxel = hit.voxel;
+    }
+
+    let mask = hit.mask;
+    voxel_grid.normal = vec3<f32>(0.0);
+    if (mask.x) {
+        voxel_grid.normal.x = -sign(ray_direction.x);
+    }
+    else if (mask.y) {
+        voxel_grid.normal.y = -sign(ray_direction.y);
+    }
+    else if (mask.z) {
+        voxel_grid.normal.z = -sign(ray_direction.z);
+    }
+}
+
+fn write_depth(pixel_coords: vec2<u32>, ray: Ray, hit: Hit) {
+    var depth = 0.0;
+    if (hit.hit) {
+        let camera_matrix = player_data.camera_matrix;
+        let forward = -normalize(camera_matrix[2].xyz);
+        depth = dot(ray.origin + ray.direction * hit.distance - camera_matrix[3].xyz, forward);
+    }
+    textureStore(depth_texture, vec2<i32>(pixel_coords), vec4<f32>(depth, 0.0, 0.0, 0.0));
+}
+
+fn meter_exposure(pixel_coords: vec2<u32>, color: vec3<f32>) {
+    if (any(pixel_coords % EXPOSURE_METER_SPACING != vec2<u32>(0u))) {
+        return;
+    }
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let log_luminance = clamp(log2(max(luminance, 1.0e-5)), -16.0, 16.0);
+    atomicAdd(&exposure_meter.log_luminance, i32(log_luminance * EXPOSURE_METER_SCALE));
+    atomicAdd(&exposure_meter.count, 1u);
+}
 
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pixel_coords = invocation_id.xy;
+    let screen_size = vec2<f32>(textureDimensions(output_texture));
+    let ray = camera_ray(vec2<f32>(pixel_coords), screen_size);
+    let center_pixel = all(vec2<f32>(pixel_coords) * 2.0 == screen_size);
+
+    let hit = raymarch(ray.origin, ray.direction, NO_MAX_DISTANCE, AIR);
+    var color = vec4<f32>(sky(ray.direction), 1.0);
+    var hit_distance = 1.0e30;
+    if (hit.hit) {
+        hit_distance = hit.distance;
+        let seed = hash(pixel_coords.y * u32(screen_size.x) + pixel_coords.x) * 64u;
+        let fog = fog_amount(ray.origin, ray.direction, hit.distance);
+        color = vec4<f32>(mix(trace(hit, ray.origin, ray.direction, seed), fog_color(ray.direction), fog), 1.0);
+    }
+    if (center_pixel) {
+        select_voxel(hit, ray.direction);
+    }
+    write_depth(pixel_coords, ray, hit);
+    meter_exposure(pixel_coords, color.rgb);
+
+    color = brush_preview(color, ray.origin, ray.direction, hit_distance);
     textureStore(output_texture, invocation_id.xy, color);
 }
 
-// TODO:
-// Begrudgingly COPY and PASTE the above code and render the brush preview with raycasting
-// because for whatever reason, you can't just have a reference to a storage buffer. Why????
+// Sum of the samples taken so far, with their count in the alpha channel
+@group(3) @binding(0)
+var accumulation: texture_storage_2d<rgba32float, read_write>;
+
+// Matches `PathTraceUniform`
+struct PathTrace {
+    // Samples per pixel already in the accumulation, seeds this frame's samples
+    samples_taken: u32,
+    // Samples per pixel to take this frame
+    samples: u32,
+    max_bounces: u32,
+    // Set when the accumulation is out of date, the samples restart from this frame
+    reset: u32,
+}
+
+@group(3) @binding(1)
+var<uniform> path_trace: PathTrace;
+
+fn next_random(state: ptr<function, u32>) -> f32 {
+    *state = hash(*state);
+    return f32(*state) / 4294967296.0;
+}
+
+// Random direction over the hemisphere around `normal`, more likely towards it by the cosine of the angle
+fn cosine_direction(normal: vec3<f32>, random: vec2<f32>) -> vec3<f32> {
+    let z = random.x * 2.0 - 1.0;
+    let angle = random.y * 6.28318530718;
+    let r = sqrt(1.0 - z * z);
+    return normalize(normal + vec3<f32>(r * cos(angle), r * sin(angle), z) * 0.999);
+}
+
+// Light carried back along one random path from the camera.
+// Every hit randomly reflects, passes through or scatters the path, the chances weighted by the material.
+// The sun and the placed lights are sampled at each diffuse hit, the sky and emissive voxels light whatever paths find them.
+fn path_sample(ray_origin: vec3<f32>, ray_direction: vec3<f32>, seed: u32) -> vec3<f32> {
+    var rng = seed;
+    var origin = ray_origin;
+    var direction = ray_direction;
+    var radiance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    // The sun was sampled at the last diffuse hit, so the path only counts its disk when it got here otherwise
+    var sees_sun = true;
+    var hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+    for (var bounce = 0u; bounce <= path_trace.max_bounces; bounce++) {
+        if (!hit.hit) {
+            if (sees_sun) {
+                radiance += throughput * sky(direction);
+            } else {
+                radiance += throughput * sky_dome(direction);
+            }
+            break;
+        }
+        if (bounce == 0u) {
+            // Fog between the camera and the first hit
+            let fog = fog_amount(origin, direction, hit.distance);
+            radiance += fog_color(direction) * fog;
+            throughput *= 1.0 - fog;
+        }
+        let position = origin + direction * hit.distance;
+        let normal = face_normal(hit.mask, direction);
+        let surface = material(hit.voxel);
+        let albedo = get_voxel_color(hit.voxel);
+        radiance += throughput * albedo * material_emission(hit.voxel);
+
+        // Mirror-like reflection
+        let reflected = reflectance(surface, albedo, normal, direction);
+        let specular_chance = min((reflected.x + reflected.y + reflected.z) / 3.0, 0.95);
+        if (next_random(&rng) < specular_chance) {
+            throughput *= reflected / specular_chance;
+            origin = position + normal * SHADOW_BIAS;
+            direction = reflection_direction(direction, normal, surface.roughness, hash(rng));
+            rng = hash(rng + 1u);
+            sees_sun = true;
+            hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+            continue;
+        }
+        throughput *= (1.0 - reflected) / (1.0 - specular_chance);
+
+        // Through a transparent material, to wherever it ends
+        if (surface.transparent != 0u && next_random(&rng) >= surface.opacity) {
+            let medium = get_voxel_type(hit.voxel);
+            let inside = bend(direction, normal, 1.0 / surface.refractive_index);
+            let entry = position - normal * SHADOW_BIAS;
+            let exit = raymarch(entry, inside, NO_MAX_DISTANCE, medium);
+            if (!exit.hit) {
+                radiance += throughput * sky_dome(inside);
+                break;
+            }
+            throughput *= exp(-surface.density * (1.0 - albedo) * exit.distance);
+            sees_sun = true;
+            if (exit.voxel != EMPTY_VOXEL) {
+                origin = entry;
+                direction = inside;
+                hit = exit;
+            } else {
+                let exit_position = entry + inside * exit.distance;
+                direction = bend(inside, face_normal(exit.mask, inside), surface.refractive_index);
+                origin = exit_position + direction * SHADOW_BIAS;
+                hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+            }
+            continue;
+        }
+
+        // Diffuse: direct light from the sun and the placed lights, then scatter off in a random direction
+        let diffuse = albedo * (1.0 - surface.metalness);
+        let surface_origin = position + normal * SHADOW_BIAS;
+        let facing = dot(normal, lighting.sun_direction);
+        if (facing > 0.0) {
+            let towards_sun = sample_cone(lighting.sun_direction, lighting.sun_radius, vec2<f32>(next_random(&rng), next_random(&rng)));
+            radiance += throughput * diffuse * lighting.sun_color * facing * shadow_transmittance(surface_origin, towards_sun);
+        }
+        radiance += throughput * diffuse * point_light(position, normal, false);
+
+        throughput *= diffuse;
+        if (all(throughput < vec3<f32>(0.001))) {
+            break;
+        }
+        origin = surface_origin;
+        direction = cosine_direction(normal, vec2<f32>(next_random(&rng), next_random(&rng)));
+        sees_sun = false;
+        hit = raymarch(origin, direction, NO_MAX_DISTANCE, AIR);
+    }
+    return radiance;
+}
+
+// Reference renderer: adds path traced samples to the accumulation every frame and shows their average.
+// Picking and the brush preview work as in `update`.
+@compute @workgroup_size(8, 8, 1)
+fn path_trace_update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pixel_coords = invocation_id.xy;
+    let screen_size = vec2<f32>(textureDimensions(output_texture));
+    let pixel = hash(pixel_coords.y * u32(screen_size.x) + pixel_coords.x);
+
+    var sum = vec4<f32>(0.0);
+    if (path_trace.reset == 0u) {
+        sum = textureLoad(accumulation, vec2<i32>(pixel_coords));
+    }
+    for (var i = 0u; i < path_trace.samples; i++) {
+        var rng = hash(pixel ^ hash(path_trace.samples_taken + i));
+        // Jittered over the pixel, which smooths the edges as samples add up
+        let jitter = vec2<f32>(next_random(&rng), next_random(&rng));
+        let ray = camera_ray(vec2<f32>(pixel_coords) + jitter, screen_size);
+        sum += vec4<f32>(path_sample(ray.origin, ray.direction, rng), 1.0);
+    }
+    textureStore(accumulation, vec2<i32>(pixel_coords), sum);
+    var color = vec4<f32>(sum.rgb / max(sum.w, 1.0), 1.0);
+
+    let ray = camera_ray(vec2<f32>(pixel_coords), screen_size);
+    let hit = raymarch(ray.origin, ray.direction, NO_MAX_DISTANCE, AIR);
+    var hit_distance = 1.0e30;
+    if (hit.hit) {
+        hit_distance = hit.distance;
+    }
+    if (all(vec2<f32>(pixel_coords) * 2.0 == screen_size)) {
+        select_voxel(hit, ray.direction);
+    }
+    write_depth(pixel_coords, ray, hit);
+    meter_exposure(pixel_coords, color.rgb);
+
+    color = brush_preview(color, ray.origin, ray.direction, hit_distance);
+    textureStore(output_texture, invocation_id.xy, color);
+}
@@ -0,0 +1,41 @@
+#import "shaders/voxel.wgsl"
+#import "shaders/regions.wgsl"
+
+@group(1) @binding(0)
+var<storage, read_write> voxel_grid_out: VoxelGrid;
+
+// Gathers the awake regions into `region_list` and writes the indirect dispatch arguments.
+// `region_dispatch.x` is cleared before this pass runs.
+@compute @workgroup_size(8, 8, 8)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let region = vec3<i32>(invocation_id);
+    let dim = region_dim();
+    if (region.x >= dim || region.y >= dim || region.z >= dim) {
+        return;
+    }
+
+    let region_index = get_region_index(region);
+    if (region_index == 0u) {
+        region_dispatch.y = 1u;
+        region_dispatch.z = 1u;
+
+        // Carry the selection over to the buffer written this tick, so edits later in the frame use the same voxel
+        voxel_grid_out.selected = voxel_grid.selected;
+        voxel_grid_out.normal = voxel_grid.normal;
+        voxel_grid_out.selected_voxel = voxel_grid.selected_voxel;
+    }
+
+    let awake = region_flags[region_index] != 0u;
+    if (awake) {
+        let slot = atomicAdd(&region_dispatch.x, 1u);
+        region_list[slot] = region_index;
+        region_flags[region_index] = 0u;
+    }
+}
+
+// Dispatched indirectly with one workgroup per region in `region_list`.
+// Flags the listed regions again, so compacting after the edit pass keeps them alongside the newly woken ones.
+@compute @workgroup_size(1, 1, 1)
+fn restore(@builtin(workgroup_id) workgroup_id: vec3<u32>) {
+    region_flags[region_list[workgroup_id.x]] = 1u;
+}
@@ -0,0 +1,58 @@
+// Active region tracking, so physics only runs where something can still move.
+// Must be imported after "shaders/voxel.wgsl".
+
+@group(2) @binding(0)
+var<storage, read_write> region_dispatch: RegionDispatch;
+
+@group(2) @binding(1)
+var<storage, read_write> region_flags: array<u32>;
+
+@group(2) @binding(2)
+var<storage, read_write> region_list: array<u32>;
+
+// Laid out as indirect dispatch arguments
+struct RegionDispatch {
+    x: atomic<u32>,
+    y: u32,
+    z: u32,
+}
+
+// Matches the workgroup size of the physics passes
+const REGION_SIZE: i32 = 8;
+
+fn region_dim() -> i32 {
+    return (i32(voxel_grid.dim) + REGION_SIZE - 1) / REGION_SIZE;
+}
+
+fn get_region_index(region: vec3<i32>) -> u32 {
+    let dim = region_dim();
+    return u32((region.x * dim * dim) + (region.y * dim) + region.z);
+}
+
+fn get_region_position(region_index: u32) -> vec3<i32> {
+    let dim = u32(region_dim());
+    return vec3<i32>(vec3<u32>(region_index / (dim * dim), (region_index / dim) % dim, region_index % dim));
+}
+
+// Maps an invocation of an indirect dispatch over `region_list` to the voxel it handles
+fn get_region_voxel(workgroup_id: vec3<u32>, invocation_id_local: vec3<u32>) -> vec3<i32> {
+    let region = get_region_position(region_list[workgroup_id.x]);
+    return region * REGION_SIZE + vec3<i32>(invocation_id_local);
+}
+
+// How far a change can affect the next tick, sand looks at the neighbours of its neighbours
+const WAKE_RADIUS: i32 = 2;
+
+// Wakes the regions within `WAKE_RADIUS` of a voxel for the next tick
+fn wake_regions(index: vec3<i32>) {
+    let dim = i32(voxel_grid.dim);
+    let min_region = max(index - vec3<i32>(WAKE_RADIUS), vec3<i32>(0)) / REGION_SIZE;
+    let max_region = min(index + vec3<i32>(WAKE_RADIUS), vec3<i32>(dim - 1)) / REGION_SIZE;
+    for (var i = min_region.x; i <= max_region.x; i++) {
+        for (var j = min_region.y; j <= max_region.y; j++) {
+            for (var k = min_region.z; k <= max_region.z; k++) {
+                region_flags[get_region_index(vec3<i32>(i, j, k))] = 1u;
+            }
+        }
+    }
+}
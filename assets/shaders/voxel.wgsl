@@ -6,6 +6,8 @@ struct VoxelGrid {
     pos: vec3<f32>,
     selected: vec3<f32>,
     normal: vec3<f32>,
+    // Value of the selected voxel, written by the raycast
+    selected_voxel: u32,
     voxels: array<u32>
 }
 
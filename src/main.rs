@@ -5,6 +5,17 @@ use bevy::{
 use bevy_inspector_egui::{quick::WorldInspectorPlugin, bevy_egui::EguiContexts, egui::{self, Ui}};
 use util::flycam::{PlayerPlugin, MovementSettings, KeyBindings, FlyCam};
 use render::RenderComputePlugin;
+use render::clipboard::{Clipboard, ClipboardAction};
+use render::hotbar::{Hotbar, HOTBAR_SLOTS};
+use render::path_trace::PathTracer;
+use render::physics::PhysicsTimer;
+use render::picking::VoxelPick;
+use render::snapshot::RewindEvent;
+use voxel::{Voxel, VOXEL_TYPE_GLASS, VOXEL_TYPE_LAMP, VOXEL_TYPE_METAL, VOXEL_TYPE_SAND, VOXEL_TYPE_STONE, VOXEL_TYPE_WATER};
+use voxel::brush::{Brush, BrushOperation, BrushShape, MAX_BRUSH_RADIUS};
+use voxel::history::SnapshotHistory;
+use voxel::prefab::Prefab;
+use replay::{ReplayOptions, ReplayPlugin};
 
 // #[cfg(test)]
 // mod tests;
@@ -12,11 +23,27 @@ use render::RenderComputePlugin;
 mod util;
 mod voxel;
 mod render;
+mod replay;
 
 fn main() {
+    let options = match ReplayOptions::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(2);
+        }
+    };
+
+    if options.headless {
+        let path = options.replay.unwrap();
+        let replay = replay::Replay::load(&path).unwrap_or_else(|error| panic!("Failed to load replay {}: {error}", path.display()));
+        let grid = replay::run_headless(&replay);
+        let ticks: u64 = replay.frames.iter().map(|frame| frame.ticks as u64).sum();
+        println!("tick {}, checksum {:016x}", ticks, grid.checksum());
+        return;
+    }
+
     App::new()
-        // .insert_resource(ClearColor(Color::rgb(0.4, 0.75, 0.9)))
-        .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Voxel Engine".to_string(),
@@ -43,9 +70,15 @@ fn main() {
             ..Default::default()
         })
         .add_plugin(RenderComputePlugin)
+        .add_plugin(ReplayPlugin { options })
         .add_plugin(WorldInspectorPlugin::new())
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .add_system(diagnostic_ui)
+        .add_system(physics_ui)
+        .add_system(history_ui)
+        .add_system(brush_ui)
+        .add_system(hotbar_ui)
+        .add_system(clipboard_ui)
         .run();
 }
 /// Give our text a custom size
@@ -57,7 +90,9 @@ fn sized_text(ui: &mut Ui, text: impl Into<String>, size: f32) {
 pub fn diagnostic_ui(
     mut contexts: EguiContexts,
     diagnostics: Res<Diagnostics>,
-    transform_query: Query<&Transform, With<FlyCam>>
+    transform_query: Query<&Transform, With<FlyCam>>,
+    pick: Res<VoxelPick>,
+    path_tracer: Res<PathTracer>,
 ) {
     let ctx = contexts.ctx_mut();
     egui::Area::new("fps")
@@ -74,5 +109,229 @@ pub fn diagnostic_ui(
                            format!("x: {}, y: {}, z: {}", transform.translation.x, transform.translation.y, transform.translation.z),
                            size);
             }
+            if let Some(hit) = pick.hit {
+                sized_text(ui,
+                           format!("Looking at {} {} {}, {:.1} away", hit.position.x, hit.position.y, hit.position.z, hit.distance),
+                           size);
+            }
+            if path_tracer.enabled {
+                sized_text(ui, format!("Path tracing: {} samples", path_tracer.samples()), size);
+            }
+        });
+}
+
+/// System to generate the simulation controls with egui
+pub fn physics_ui(
+    mut contexts: EguiContexts,
+    mut physics_timer: ResMut<PhysicsTimer>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Simulation")
+        .default_pos(egui::pos2(10.0, 80.0))
+        .show(ctx, |ui| {
+            ui.label(format!("Tick: {}", physics_timer.tick()));
+            ui.horizontal(|ui| {
+                let pause_label = if physics_timer.paused { "Resume" } else { "Pause" };
+                if ui.button(pause_label).clicked() {
+                    physics_timer.toggle_pause();
+                }
+                if ui.button("Step").clicked() {
+                    physics_timer.step();
+                }
+            });
+
+            let mut time_scale = physics_timer.time_scale;
+            let slider = egui::Slider::new(&mut time_scale, PhysicsTimer::time_scale_range())
+                .logarithmic(true)
+                .text("Speed");
+            if ui.add(slider).changed() {
+                physics_timer.set_time_scale(time_scale);
+            }
+        });
+}
+
+/// System to generate the rewind timeline with egui
+pub fn history_ui(
+    mut contexts: EguiContexts,
+    history: Res<SnapshotHistory>,
+    mut rewind_events: EventWriter<RewindEvent>,
+    mut selected: Local<usize>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("History")
+        .default_pos(egui::pos2(10.0, 200.0))
+        .show(ctx, |ui| {
+            if history.is_empty() {
+                ui.label("No snapshots yet");
+                return;
+            }
+
+            let last = history.len() - 1;
+            *selected = (*selected).min(last);
+            let tick = history.get(*selected).unwrap().tick;
+            ui.add(egui::Slider::new(&mut *selected, 0..=last).show_value(false).text(format!("Tick {}", tick)));
+            if ui.button("Rewind").clicked() {
+                rewind_events.send(RewindEvent { index: *selected });
+            }
+            ui.label(format!("{} snapshots, {:.1} MiB", history.len(), history.size_in_bytes() as f32 / (1024.0 * 1024.0)));
+        });
+}
+
+/// System to generate the brush settings with egui
+pub fn brush_ui(
+    mut contexts: EguiContexts,
+    mut brush: ResMut<Brush>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Brush")
+        .default_pos(egui::pos2(10.0, 320.0))
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for shape in BrushShape::ALL {
+                    ui.selectable_value(&mut brush.shape, shape, format!("{:?}", shape));
+                }
+            });
+            ui.horizontal(|ui| {
+                for operation in BrushOperation::ALL {
+                    ui.selectable_value(&mut brush.operation, operation, format!("{:?}", operation));
+                }
+            });
+            ui.add(egui::Slider::new(&mut brush.radius, 0..=MAX_BRUSH_RADIUS).text("Radius"));
+            if brush.operation == BrushOperation::Replace {
+                ui.add(egui::Slider::new(&mut brush.replace_type, 0..=255).text("Replace type"));
+            }
+        });
+}
+
+/// System to generate the material hotbar with egui
+pub fn hotbar_ui(
+    mut contexts: EguiContexts,
+    mut hotbar: ResMut<Hotbar>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Hotbar")
+        .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -10.0))
+        .title_bar(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for slot in 0..HOTBAR_SLOTS {
+                    let color = hotbar.slots[slot].get_color();
+                    let fill = egui::Color32::from_rgb((color.x * 255.0) as u8, (color.y * 255.0) as u8, (color.z * 255.0) as u8);
+                    let mut button = egui::Button::new(format!("{}", slot + 1)).fill(fill).min_size(egui::vec2(32.0, 32.0));
+                    if slot == hotbar.selected {
+                        button = button.stroke(egui::Stroke::new(2.0, egui::Color32::WHITE));
+                    }
+                    if ui.add(button).clicked() {
+                        hotbar.selected = slot;
+                    }
+                }
+            });
+
+            // Edit the selected slot
+            let voxel = hotbar.material();
+            let mut color = voxel.get_color().to_array();
+            let mut voxel_type = voxel.get_voxel_type();
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(&mut color);
+                ui.radio_value(&mut voxel_type, VOXEL_TYPE_SAND, "Sand");
+                ui.radio_value(&mut voxel_type, VOXEL_TYPE_WATER, "Water");
+                ui.radio_value(&mut voxel_type, VOXEL_TYPE_STONE, "Stone");
+                ui.radio_value(&mut voxel_type, VOXEL_TYPE_LAMP, "Lamp");
+                ui.radio_value(&mut voxel_type, VOXEL_TYPE_GLASS, "Glass");
+                ui.radio_value(&mut voxel_type, VOXEL_TYPE_METAL, "Metal");
+            });
+            let edited = Voxel::material(Vec3::from_array(color), voxel_type);
+            if edited != Voxel::material(voxel.get_color(), voxel.get_voxel_type()) {
+                let selected = hotbar.selected;
+                hotbar.slots[selected] = edited;
+            }
+        });
+}
+
+/// System to generate the box selection, clipboard and editing tool controls with egui
+pub fn clipboard_ui(
+    mut contexts: EguiContexts,
+    mut clipboard: ResMut<Clipboard>,
+    mut path: Local<Option<String>>,
+    mut status: Local<String>,
+) {
+    let path = path.get_or_insert_with(|| "prefab.vox".to_string());
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Clipboard")
+        .default_pos(egui::pos2(10.0, 480.0))
+        .show(ctx, |ui| {
+            ui.checkbox(&mut clipboard.selecting, "Select box (B)");
+            let corner = |corner: Option<IVec3>| corner.map_or("-".to_string(), |corner| format!("{} {} {}", corner.x, corner.y, corner.z));
+            ui.label(format!("From {} to {}", corner(clipboard.corners[0]), corner(clipboard.corners[1])));
+
+            ui.horizontal(|ui| {
+                if ui.add_enabled(clipboard.selection().is_some(), egui::Button::new("Copy")).clicked() {
+                    clipboard.request(ClipboardAction::Copy);
+                }
+                ui.add_enabled_ui(clipboard.prefab.is_some(), |ui| {
+                    if ui.button("Paste").clicked() {
+                        clipboard.request(ClipboardAction::Paste);
+                    }
+                    if ui.button("Stamp").clicked() {
+                        clipboard.request(ClipboardAction::Stamp);
+                    }
+                    if ui.button("Rotate").clicked() {
+                        clipboard.rotate();
+                    }
+                    if ui.button("Mirror").clicked() {
+                        clipboard.mirror();
+                    }
+                });
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Fill").clicked() {
+                    clipboard.request(ClipboardAction::Fill);
+                }
+                ui.add(egui::DragValue::new(&mut clipboard.fill_limit).prefix("Limit "));
+            });
+            ui.add_enabled_ui(clipboard.selection().is_some(), |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Replace").clicked() {
+                        clipboard.request(ClipboardAction::Replace);
+                    }
+                    for shape in ClipboardAction::SHAPES {
+                        if ui.button(format!("{:?}", shape)).clicked() {
+                            clipboard.request(shape);
+                        }
+                    }
+                });
+            });
+            ui.separator();
+
+            if let Some(prefab) = &clipboard.prefab {
+                let size = prefab.size();
+                ui.label(format!("{}x{}x{}, {} voxels", size.x, size.y, size.z, prefab.solid_count()));
+            }
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(path);
+                if ui.add_enabled(clipboard.prefab.is_some(), egui::Button::new("Save")).clicked() {
+                    if let Some(prefab) = &clipboard.prefab {
+                        *status = match prefab.save(&*path) {
+                            Ok(()) => format!("Saved {path}"),
+                            Err(error) => format!("Failed to save {path}: {error}"),
+                        };
+                    }
+                }
+                if ui.button("Load").clicked() {
+                    *status = match Prefab::load(&*path) {
+                        Ok(prefab) => {
+                            clipboard.prefab = Some(prefab);
+                            format!("Loaded {path}")
+                        }
+                        Err(error) => format!("Failed to load {path}: {error}"),
+                    };
+                }
+            });
+            if !status.is_empty() {
+                ui.label(&*status);
+            }
         });
 }
@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_resource::ShaderType;
+
+/// How the sky behind the voxels is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, FromReflect)]
+pub enum SkyModel {
+    /// From the horizon color to the zenith color
+    Gradient,
+    /// Preetham's analytic daylight sky, colored by the sun direction and the haze in the air
+    #[default]
+    Preetham,
+}
+
+/// Sky seen by rays that miss the grid, and fog between the camera and the voxels, shown in the inspector.
+/// The sun itself is set in [`Lighting`](super::lighting::Lighting).
+#[derive(Resource, Clone, Debug, Reflect, ExtractResource)]
+#[reflect(Resource)]
+pub struct Atmosphere {
+    pub sky_model: SkyModel,
+    /// Sky straight up, for [`SkyModel::Gradient`]
+    pub zenith_color: Vec3,
+    /// Sky at the horizon for [`SkyModel::Gradient`], also the clear color around the raytraced image
+    pub horizon_color: Vec3,
+    /// Below the horizon, for either model
+    pub ground_color: Vec3,
+    /// Haze of the [`SkyModel::Preetham`] sky, 2 is a clear day and 10 a hazy one
+    pub turbidity: f32,
+    /// Brightness of the [`SkyModel::Preetham`] sky straight up
+    pub sky_brightness: f32,
+    /// Brightness of the sun disk, relative to the sunlight
+    pub sun_disk_brightness: f32,
+    pub fog: bool,
+    pub fog_color: Vec3,
+    /// Fog per unit of distance at `fog_height`
+    pub fog_density: f32,
+    /// Height the fog thins out above, and thickens below
+    pub fog_height: f32,
+    /// How quickly the fog thins out with height, 0 for fog that only depends on the distance
+    pub fog_falloff: f32,
+    /// Sunlight scattered towards the camera by fog in front of the sun
+    pub fog_sun_scattering: f32,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            sky_model: SkyModel::default(),
+            zenith_color: Vec3::new(0.25, 0.45, 0.85),
+            horizon_color: Vec3::new(0.7, 0.8, 0.9),
+            ground_color: Vec3::new(0.25, 0.24, 0.22),
+            turbidity: 3.0,
+            sky_brightness: 0.45,
+            sun_disk_brightness: 8.0,
+            fog: true,
+            fog_color: Vec3::new(0.7, 0.8, 0.9),
+            fog_density: 0.002,
+            fog_height: 0.0,
+            fog_falloff: 0.03,
+            fog_sun_scattering: 0.5,
+        }
+    }
+}
+
+/// Layout of the `Atmosphere` uniform in `raytrace.wgsl`
+#[derive(Clone, Default, ShaderType)]
+pub(super) struct AtmosphereUniform {
+    zenith_color: Vec3,
+    sky_model: u32,
+    horizon_color: Vec3,
+    turbidity: f32,
+    ground_color: Vec3,
+    sky_brightness: f32,
+    fog_color: Vec3,
+    // 0 when fog is off
+    fog_density: f32,
+    fog_height: f32,
+    fog_falloff: f32,
+    fog_sun_scattering: f32,
+    sun_disk_brightness: f32,
+}
+
+impl From<&Atmosphere> for AtmosphereUniform {
+    fn from(atmosphere: &Atmosphere) -> Self {
+        Self {
+            zenith_color: atmosphere.zenith_color,
+            sky_model: atmosphere.sky_model as u32,
+            horizon_color: atmosphere.horizon_color,
+            // Preetham's fit only holds for turbidities from about 2 to 10
+            turbidity: atmosphere.turbidity.clamp(1.7, 10.0),
+            ground_color: atmosphere.ground_color,
+            sky_brightness: atmosphere.sky_brightness.max(0.0),
+            fog_color: atmosphere.fog_color,
+            fog_density: if atmosphere.fog { atmosphere.fog_density.max(0.0) } else { 0.0 },
+            fog_height: atmosphere.fog_height,
+            fog_falloff: atmosphere.fog_falloff.max(0.0),
+            fog_sun_scattering: atmosphere.fog_sun_scattering.max(0.0),
+            sun_disk_brightness: atmosphere.sun_disk_brightness.max(0.0),
+        }
+    }
+}
+
+/// Keeps the window around the raytraced image the color of the horizon
+pub(super) fn update_clear_color(atmosphere: Res<Atmosphere>, mut clear_color: ResMut<ClearColor>) {
+    let color = atmosphere.horizon_color;
+    clear_color.0 = Color::rgb_linear(color.x, color.y, color.z);
+}
@@ -0,0 +1,272 @@
+use bevy::core::FrameCount;
+use bevy::prelude::*;
+
+use crate::voxel::{Voxel, VoxelGrid};
+use crate::voxel::brush::Brush;
+use crate::voxel::prefab::Prefab;
+use crate::voxel::tools::{self, DEFAULT_FILL_LIMIT};
+use crate::voxel::undo::{EditDiff, EditHistory, VoxelChange};
+use super::edits::{VoxelGridEdits, VoxelsEdited};
+use super::readback::{SelectionReadback, SelectionReadbackRequest, VoxelGridReadback, VoxelGridReadbackRequest};
+
+/// Things the clipboard and the editing tools do once the GPU has told them what is under the crosshair.
+/// The tools draw with the brush material.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardAction {
+    /// Sets the first or second corner of the selection to the voxel under the crosshair
+    Corner(usize),
+    /// Copies the selected box into the clipboard
+    Copy,
+    /// Writes the whole clipboard in front of the face under the crosshair
+    Paste,
+    /// Like paste, but leaves the voxels behind the clipboard's empty ones alone
+    Stamp,
+    /// Flood fills the voxels connected to the one under the crosshair
+    Fill,
+    /// Replaces the material under the crosshair everywhere in the selection
+    Replace,
+    /// Draws a line from the first corner to the second
+    Line,
+    /// Draws a plane between the corners
+    Plane,
+    /// Draws the shell of the selected box
+    HollowBox,
+}
+
+impl ClipboardAction {
+    /// The actions that draw between the two corners of the selection
+    pub const SHAPES: [Self; 3] = [Self::Line, Self::Plane, Self::HollowBox];
+}
+
+/// Box selection and the prefab copied out of it, the selection is shared by the editing tools.
+/// While selecting, left and right click pick the two corners instead of brushing.
+#[derive(Resource)]
+pub struct Clipboard {
+    pub selecting: bool,
+    pub corners: [Option<IVec3>; 2],
+    pub prefab: Option<Prefab>,
+    /// Flood fills that would change more voxels than this are refused
+    pub fill_limit: usize,
+    pending: Option<ClipboardAction>,
+    in_flight: Option<ClipboardAction>,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self {
+            selecting: false,
+            corners: [None; 2],
+            prefab: None,
+            fill_limit: DEFAULT_FILL_LIMIT,
+            pending: None,
+            in_flight: None,
+        }
+    }
+}
+
+impl Clipboard {
+    /// Queues an action, it runs when the readback it needs arrives a frame or two later
+    pub fn request(&mut self, action: ClipboardAction) {
+        self.pending = Some(action);
+    }
+
+    /// Both corners of the selection, if they have been picked
+    pub fn selection(&self) -> Option<(IVec3, IVec3)> {
+        Some((self.corners[0]?, self.corners[1]?))
+    }
+
+    pub fn rotate(&mut self) {
+        self.prefab = self.prefab.as_ref().map(Prefab::rotated);
+    }
+
+    pub fn mirror(&mut self) {
+        self.prefab = self.prefab.as_ref().map(Prefab::mirrored);
+    }
+
+    /// Runs an action on a readback of the grid, returning the edits it makes
+    fn run(&mut self, action: ClipboardAction, grid: &VoxelGrid, material: Voxel) -> Option<EditDiff> {
+        let selected = (grid.selected().min_element() >= 0.0).then(|| grid.selected().as_uvec3());
+        let selection = self.selection();
+        if selection.is_none() && !matches!(action, ClipboardAction::Paste | ClipboardAction::Stamp | ClipboardAction::Fill) {
+            warn!("Pick both corners of the selection first");
+            return None;
+        }
+
+        match action {
+            ClipboardAction::Corner(_) => None,
+            ClipboardAction::Copy => {
+                let (corner_a, corner_b) = selection?;
+                self.prefab = Some(Prefab::copy(grid, corner_a, corner_b));
+                None
+            }
+            ClipboardAction::Paste | ClipboardAction::Stamp => match &self.prefab {
+                Some(prefab) => Some(paste(prefab, grid, action == ClipboardAction::Stamp)),
+                None => {
+                    warn!("Nothing copied to paste");
+                    None
+                }
+            },
+            ClipboardAction::Fill => {
+                let diff = tools::flood_fill(grid, selected?, material, self.fill_limit);
+                if diff.is_none() {
+                    warn!("Flood fill would change more than {} voxels", self.fill_limit);
+                }
+                diff
+            }
+            ClipboardAction::Replace => {
+                let (corner_a, corner_b) = selection?;
+                let selected = selected?;
+                let from = *grid.get(selected.x, selected.y, selected.z)?;
+                Some(tools::replace(grid, corner_a, corner_b, from, material))
+            }
+            ClipboardAction::Line => {
+                let (corner_a, corner_b) = selection?;
+                Some(tools::line(grid, corner_a, corner_b, material))
+            }
+            ClipboardAction::Plane => {
+                let (corner_a, corner_b) = selection?;
+                Some(tools::plane(grid, corner_a, corner_b, material))
+            }
+            ClipboardAction::HollowBox => {
+                let (corner_a, corner_b) = selection?;
+                Some(tools::hollow_box(grid, corner_a, corner_b, material))
+            }
+        }
+    }
+}
+
+/// Key configuration for the clipboard, copy and paste are always Ctrl+C and Ctrl+V (Ctrl+Shift+V stamps)
+#[derive(Resource)]
+pub struct ClipboardKeyBindings {
+    pub toggle_selecting: KeyCode,
+    pub rotate: KeyCode,
+    pub mirror: KeyCode,
+}
+
+impl Default for ClipboardKeyBindings {
+    fn default() -> Self {
+        Self {
+            toggle_selecting: KeyCode::B,
+            rotate: KeyCode::R,
+            mirror: KeyCode::M,
+        }
+    }
+}
+
+pub(super) fn clipboard_controls(
+    keys: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    key_bindings: Res<ClipboardKeyBindings>,
+    mut clipboard: ResMut<Clipboard>,
+) {
+    if keys.just_pressed(key_bindings.toggle_selecting) {
+        clipboard.selecting = !clipboard.selecting;
+    }
+    if clipboard.selecting {
+        if mouse_input.just_pressed(MouseButton::Left) {
+            clipboard.request(ClipboardAction::Corner(0));
+        }
+        if mouse_input.just_pressed(MouseButton::Right) {
+            clipboard.request(ClipboardAction::Corner(1));
+        }
+    }
+    if keys.just_pressed(key_bindings.rotate) {
+        clipboard.rotate();
+    }
+    if keys.just_pressed(key_bindings.mirror) {
+        clipboard.mirror();
+    }
+
+    let control = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if control && keys.just_pressed(KeyCode::C) {
+        clipboard.request(ClipboardAction::Copy);
+    }
+    if control && keys.just_pressed(KeyCode::V) {
+        clipboard.request(if shift { ClipboardAction::Stamp } else { ClipboardAction::Paste });
+    }
+}
+
+/// Runs the action in flight once its readback arrives
+pub(crate) fn run_clipboard_actions(
+    mut clipboard: ResMut<Clipboard>,
+    mut selection_events: EventReader<SelectionReadback>,
+    mut readback_events: EventReader<VoxelGridReadback>,
+    brush: Res<Brush>,
+    mut edits: ResMut<VoxelGridEdits>,
+    mut history: ResMut<EditHistory>,
+    mut edited: EventWriter<VoxelsEdited>,
+) {
+    // Any readback will do, whoever asked for it
+    let selection = selection_events.iter().last();
+    let readback = readback_events.iter().last();
+    match clipboard.in_flight {
+        Some(ClipboardAction::Corner(corner)) => {
+            if let Some(selection) = selection {
+                clipboard.in_flight = None;
+                if selection.selected.min_element() >= 0.0 {
+                    clipboard.corners[corner] = Some(selection.selected.as_ivec3());
+                }
+            }
+        }
+        Some(action) => {
+            if let Some(readback) = readback {
+                clipboard.in_flight = None;
+                if let Some(diff) = clipboard.run(action, &readback.grid, Voxel::new(brush.material)) {
+                    for change in diff.changes() {
+                        edits.set(change.index, change.new);
+                    }
+                    edited.send(VoxelsEdited::from_diff(&diff));
+                    history.push(diff);
+                }
+            }
+        }
+        None => {}
+    }
+}
+
+/// Asks for the readback the next queued action needs, one action is in flight at a time.
+/// Corners only need the grid header, everything else reads back the whole grid.
+/// Edits need it for the old voxels, so they can be undone like a brush stroke.
+pub(super) fn request_clipboard_readbacks(
+    frame_count: Res<FrameCount>,
+    mut clipboard: ResMut<Clipboard>,
+    mut selection_request: ResMut<SelectionReadbackRequest>,
+    mut readback_request: ResMut<VoxelGridReadbackRequest>,
+) {
+    if clipboard.in_flight.is_some() {
+        return;
+    }
+    let Some(action) = clipboard.pending.take() else {
+        return;
+    };
+    match action {
+        ClipboardAction::Corner(_) => {
+            if !selection_request.is_requested() {
+                selection_request.request(frame_count.0 as u64);
+            }
+        }
+        _ => readback_request.request(),
+    }
+    clipboard.in_flight = Some(action);
+}
+
+/// The changes placing `prefab` on the face under the crosshair makes to `grid`
+fn paste(prefab: &Prefab, grid: &VoxelGrid, stamp: bool) -> EditDiff {
+    if grid.selected().min_element() < 0.0 {
+        return EditDiff::default();
+    }
+    let anchor = (grid.selected() + grid.normal()).as_ivec3();
+    let changes = prefab.placements(anchor, stamp)
+        .into_iter()
+        .filter(|(position, _)| grid.in_bounds(position.x, position.y, position.z))
+        .map(|(position, voxel)| {
+            let position = position.as_uvec3();
+            VoxelChange {
+                index: grid.index(position.x, position.y, position.z),
+                old: *grid.get(position.x, position.y, position.z).unwrap(),
+                new: voxel,
+            }
+        });
+    EditDiff::from_log(changes)
+}
@@ -0,0 +1,25 @@
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::render_resource::{AsBindGroup, ShaderRef};
+
+/// Draws the raytraced image over the whole screen inside the 3D pass of the camera,
+/// writing the depth of each voxel hit so meshes and voxels hide each other where they overlap
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "5b0e2c1d-8f43-4a7e-9d61-2f1c7a9e4b38"]
+pub struct VoxelCompositeMaterial {
+    #[texture(0)]
+    pub color: Handle<Image>,
+    /// View distance of the hits, as written by `raytrace.wgsl`
+    #[texture(1, sample_type = "float", filterable = false)]
+    pub depth: Handle<Image>,
+}
+
+impl Material for VoxelCompositeMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/composite.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/composite.wgsl".into()
+    }
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_resource::encase;
+use bevy::render::renderer::RenderQueue;
+
+use crate::voxel::{Voxel, VoxelGrid};
+use crate::voxel::regions::ActiveRegions;
+use crate::voxel::undo::EditDiff;
+use super::readback::VOXEL_GRID_HEADER_SIZE;
+use super::{ActiveRegionStorage, VoxelGridStorage, VOXEL_GRID_SIZE};
+
+/// Voxel edits made on the CPU this frame.
+/// They are written into both GPU voxel buffers before any pass runs, so the ping-pong pair stays in agreement.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct VoxelGridEdits {
+    replace: Option<Arc<VoxelGrid>>,
+    // (flat voxel index, new value), later edits to the same voxel win
+    voxels: Vec<(u32, Voxel)>,
+}
+
+impl VoxelGridEdits {
+    /// Replaces the whole grid and wakes every region
+    pub fn replace(&mut self, grid: VoxelGrid) {
+        self.replace = Some(Arc::new(grid));
+    }
+
+    /// Sets a single voxel by its flat index, only the changed voxels are uploaded
+    pub fn set(&mut self, index: u32, voxel: Voxel) {
+        self.voxels.push((index, voxel));
+    }
+
+    /// Single voxel edits so far this frame, in the order they were made
+    pub fn voxels(&self) -> &[(u32, Voxel)] {
+        &self.voxels
+    }
+
+    /// Drops the single voxel edits made so far this frame, a replacement grid is kept
+    pub fn clear_voxels(&mut self) {
+        self.voxels.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replace.is_none() && self.voxels.is_empty()
+    }
+
+    /// Whether single voxel edits are waiting to be uploaded, their regions then need merging into the dispatch list
+    pub fn has_voxels(&self) -> bool {
+        !self.voxels.is_empty()
+    }
+}
+
+/// Sent when a finished edit has changed voxels, with their flat indices.
+/// Brush strokes are sent once they end, as they are only read back then.
+pub struct VoxelsEdited(pub Vec<u32>);
+
+impl VoxelsEdited {
+    pub fn from_diff(diff: &EditDiff) -> Self {
+        Self(diff.changes().iter().map(|change| change.index).collect())
+    }
+}
+
+/// Groups voxel edits into runs of consecutive indices, so each run is a single buffer write
+fn voxel_runs(voxels: &[(u32, Voxel)]) -> Vec<(u32, Vec<u32>)> {
+    let mut sorted = voxels.to_vec();
+    // Stable, so the last edit of a voxel stays last
+    sorted.sort_by_key(|&(index, _)| index);
+
+    let mut runs: Vec<(u32, Vec<u32>)> = Vec::new();
+    for (index, voxel) in sorted {
+        match runs.last_mut() {
+            Some((start, values)) if *start + values.len() as u32 - 1 == index => {
+                *values.last_mut().unwrap() = voxel.value();
+            }
+            Some((start, values)) if *start + values.len() as u32 == index => values.push(voxel.value()),
+            _ => runs.push((index, vec![voxel.value()])),
+        }
+    }
+    runs
+}
+
+/// Serializes a grid with the same layout as the GPU storage buffers
+pub(super) fn encode_voxel_grid(grid: &VoxelGrid) -> Vec<u8> {
+    let mut buffer = encase::StorageBuffer::new(Vec::new());
+    buffer.write(grid).unwrap();
+    buffer.into_inner()
+}
+
+pub(super) fn clear_voxel_grid_edits(mut edits: ResMut<VoxelGridEdits>) {
+    *edits = VoxelGridEdits::default();
+}
+
+pub(super) fn write_voxel_grid_edits(
+    edits: Res<VoxelGridEdits>,
+    voxel_grid: Res<VoxelGridStorage>,
+    regions: Res<ActiveRegionStorage>,
+    render_queue: Res<RenderQueue>,
+) {
+    if let Some(grid) = &edits.replace {
+        let bytes = encode_voxel_grid(grid);
+        let buffers: Vec<_> = voxel_grid.0.iter().filter_map(|buffer| buffer.buffer()).collect();
+        if buffers.iter().all(|buffer| buffer.size() == bytes.len() as u64) {
+            for buffer in buffers {
+                render_queue.write_buffer(buffer, 0, &bytes);
+            }
+            regions.wake_all(&render_queue);
+        } else {
+            warn!("Replacement voxel grid does not match the size of the GPU buffer");
+        }
+    }
+
+    if edits.has_voxels() {
+        for (start, values) in voxel_runs(&edits.voxels) {
+            let offset = VOXEL_GRID_HEADER_SIZE + start as u64 * 4;
+            for buffer in voxel_grid.0.iter().filter_map(|buffer| buffer.buffer()) {
+                render_queue.write_buffer(buffer, offset, bytemuck::cast_slice(&values));
+            }
+        }
+
+        let dim = VOXEL_GRID_SIZE;
+        let mut woken = ActiveRegions::asleep(dim);
+        for &(index, _) in &edits.voxels {
+            let (x, y, z) = (index / (dim * dim), (index / dim) % dim, index % dim);
+            woken.wake(x as i32, y as i32, z as i32);
+        }
+        regions.wake(&render_queue, &woken.active());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_merge_consecutive_voxels() {
+        let voxels = [5, 3, 4, 9, 4].map(|index| (index, Voxel::new(index)));
+        let mut edits = voxels.to_vec();
+        edits.push((4, Voxel::new(40)));
+        assert_eq!(voxel_runs(&edits), vec![(3, vec![3, 40, 5]), (9, vec![9])]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_resource::{Buffer, BufferDescriptor, BufferUsages};
+use bevy::render::renderer::RenderDevice;
+use bevy::render::view::ColorGrading;
+
+use crate::util::flycam::FlyCam;
+use super::readback::StagingReadbacks;
+
+/// Matches `EXPOSURE_METER_SCALE` in `raytrace.wgsl`
+const EXPOSURE_METER_SCALE: f32 = 256.0;
+// Fixed point log luminance sum and sample count
+const EXPOSURE_METER_SIZE: u64 = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, FromReflect)]
+pub enum ExposureMode {
+    /// Exposed by `ev` alone
+    Manual,
+    /// Exposed so the average brightness of the voxels lands on `target_luminance`, then by `ev` on top
+    #[default]
+    Auto,
+}
+
+/// Exposure of the HDR image before the camera's [`Tonemapping`](bevy::core_pipeline::tonemapping::Tonemapping),
+/// shown in the inspector. It is applied through the [`ColorGrading`] of the fly camera.
+#[derive(Resource, Clone, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct Exposure {
+    pub mode: ExposureMode,
+    /// Stops of exposure for [`ExposureMode::Manual`], exposure compensation for [`ExposureMode::Auto`]
+    pub ev: f32,
+    /// Average luminance auto exposure aims for
+    pub target_luminance: f32,
+    /// Range auto exposure stays within, in stops
+    pub min_ev: f32,
+    pub max_ev: f32,
+    /// How quickly auto exposure follows the scene, in stops per second
+    pub adaptation_speed: f32,
+    /// Seconds between luminance readbacks
+    pub metering_interval: f32,
+    // Average log2 luminance of the last readback
+    #[reflect(ignore)]
+    measured: Option<f32>,
+    #[reflect(ignore)]
+    since_metered: f32,
+    // Exposure applied to the camera, in stops
+    #[reflect(ignore)]
+    current: f32,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            mode: ExposureMode::default(),
+            ev: 0.0,
+            target_luminance: 0.18,
+            min_ev: -4.0,
+            max_ev: 4.0,
+            adaptation_speed: 2.0,
+            metering_interval: 0.25,
+            measured: None,
+            since_metered: 0.0,
+            current: 0.0,
+        }
+    }
+}
+
+impl Exposure {
+    /// Moves the exposure towards where it should be after `delta_seconds`.
+    /// Auto exposure holds still until the first luminance is measured.
+    fn adapt(&mut self, delta_seconds: f32) {
+        match self.mode {
+            ExposureMode::Manual => self.current = self.ev,
+            ExposureMode::Auto => {
+                let Some(measured) = self.measured else {
+                    return;
+                };
+                let target = (self.target_luminance.max(1e-5).log2() - measured + self.ev).clamp(self.min_ev, self.max_ev.max(self.min_ev));
+                let step = self.adaptation_speed.max(0.0) * delta_seconds;
+                self.current += (target - self.current).clamp(-step, step);
+            }
+        }
+    }
+}
+
+/// GPU buffer `raytrace.wgsl` sums the luminance of the frame into
+#[derive(Resource, Clone, ExtractResource)]
+pub(super) struct ExposureMeter {
+    pub buffer: Buffer,
+}
+
+/// Staging buffers the [`ExposureMeter`] is read back through, in the render world
+#[derive(Resource)]
+pub(super) struct ExposureMeterReadback(pub StagingReadbacks<()>);
+
+impl Default for ExposureMeterReadback {
+    fn default() -> Self {
+        Self(StagingReadbacks::new("exposure_meter_readback", EXPOSURE_METER_SIZE))
+    }
+}
+
+/// Reads the [`ExposureMeter`] back at the end of the frame, the average log luminance arrives a frame or two later
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub(super) struct ExposureMeterRequest {
+    pub requested: bool,
+    result: Arc<Mutex<Option<f32>>>,
+}
+
+pub(super) fn setup_exposure_meter(mut commands: Commands, render_device: Res<RenderDevice>) {
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("exposure_meter"),
+        size: EXPOSURE_METER_SIZE,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    commands.insert_resource(ExposureMeter { buffer });
+}
+
+/// Meters the luminance every `metering_interval` for auto exposure, and exposes the fly camera
+pub(super) fn update_exposure(
+    time: Res<Time>,
+    mut exposure: ResMut<Exposure>,
+    mut request: ResMut<ExposureMeterRequest>,
+    mut cameras: Query<&mut ColorGrading, With<FlyCam>>,
+) {
+    if let Some(measured) = request.result.lock().unwrap().take() {
+        exposure.measured = Some(measured);
+    }
+
+    request.requested = false;
+    if exposure.mode == ExposureMode::Auto {
+        exposure.since_metered += time.delta_seconds();
+        if exposure.since_metered >= exposure.metering_interval {
+            exposure.since_metered = 0.0;
+            request.requested = true;
+        }
+    }
+
+    exposure.adapt(time.delta_seconds());
+    for mut color_grading in cameras.iter_mut() {
+        color_grading.exposure = exposure.current;
+    }
+}
+
+pub(super) fn prepare_exposure_meter_readback(
+    request: Res<ExposureMeterRequest>,
+    mut readback: ResMut<ExposureMeterReadback>,
+    render_device: Res<RenderDevice>,
+) {
+    if request.requested {
+        readback.0.take(&render_device, ());
+    }
+}
+
+/// Runs after the frame was submitted, the luminance is picked up once wgpu has mapped it on a later frame
+pub(super) fn map_exposure_meter(
+    request: Res<ExposureMeterRequest>,
+    mut readback: ResMut<ExposureMeterReadback>,
+    render_device: Res<RenderDevice>,
+) {
+    readback.0.map(&render_device);
+    for (_, data) in readback.0.finished() {
+        let sum = i32::from_le_bytes(data[0..4].try_into().unwrap());
+        let count = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        if count > 0 {
+            *request.result.lock().unwrap() = Some(sum as f32 / EXPOSURE_METER_SCALE / count as f32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_exposure_adapts_gradually() {
+        let mut exposure = Exposure::default();
+        exposure.adapt(1.0);
+        assert_eq!(exposure.current, 0.0);
+
+        // A scene at a quarter of the target luminance wants two more stops
+        exposure.measured = Some((0.18f32 / 4.0).log2());
+        exposure.adapt(0.5);
+        assert!((exposure.current - 1.0).abs() < 1e-4);
+        exposure.adapt(10.0);
+        assert!((exposure.current - 2.0).abs() < 1e-4);
+
+        exposure.measured = Some(-20.0);
+        exposure.adapt(10.0);
+        assert_eq!(exposure.current, exposure.max_ev);
+
+        exposure.mode = ExposureMode::Manual;
+        exposure.ev = -1.5;
+        exposure.adapt(0.0);
+        assert_eq!(exposure.current, -1.5);
+    }
+}
@@ -0,0 +1,132 @@
+use bevy::core::FrameCount;
+use bevy::prelude::*;
+
+use crate::voxel::brush::Brush;
+use crate::voxel::{Voxel, VOXEL_TYPE_LAMP, VOXEL_TYPE_SAND, VOXEL_TYPE_STONE, VOXEL_TYPE_WATER};
+use super::readback::{SelectionReadback, SelectionReadbackRequest};
+
+pub const HOTBAR_SLOTS: usize = 9;
+
+const SLOT_KEYS: [KeyCode; HOTBAR_SLOTS] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// Materials the brush can place, picked with the number keys, the hotbar window or the middle mouse button
+#[derive(Resource, Clone, Debug)]
+pub struct Hotbar {
+    pub slots: [Voxel; HOTBAR_SLOTS],
+    pub selected: usize,
+}
+
+impl Default for Hotbar {
+    fn default() -> Self {
+        let colors = [
+            (Vec3::new(0.5, 0.3, 0.1), VOXEL_TYPE_SAND),
+            (Vec3::ZERO, VOXEL_TYPE_SAND),
+            (Vec3::new(0.9, 0.85, 0.7), VOXEL_TYPE_SAND),
+            (Vec3::new(0.6, 0.2, 0.1), VOXEL_TYPE_SAND),
+            (Vec3::new(0.3, 0.3, 0.3), VOXEL_TYPE_STONE),
+            (Vec3::new(0.2, 0.5, 0.2), VOXEL_TYPE_SAND),
+            (Vec3::new(0.9, 0.8, 0.2), VOXEL_TYPE_LAMP),
+            (Vec3::new(0.4, 0.2, 0.6), VOXEL_TYPE_SAND),
+            (Vec3::new(0.3, 0.7, 0.9), VOXEL_TYPE_WATER),
+        ];
+        Self {
+            slots: colors.map(|(color, voxel_type)| Voxel::material(color, voxel_type)),
+            // Black sand, what right click used to place
+            selected: 1,
+        }
+    }
+}
+
+impl Hotbar {
+    pub fn material(&self) -> Voxel {
+        self.slots[self.selected]
+    }
+
+    /// Selects the slot holding `voxel`, or puts it in the selected slot if there is none
+    pub fn pick(&mut self, voxel: Voxel) {
+        if voxel.is_empty() {
+            return;
+        }
+        match self.slots.iter().position(|&slot| slot == voxel) {
+            Some(slot) => self.selected = slot,
+            None => self.slots[self.selected] = voxel,
+        }
+    }
+}
+
+/// Number keys select a hotbar slot, and the brush places whatever is in the selected slot
+pub(super) fn hotbar_controls(
+    keys: Res<Input<KeyCode>>,
+    mut hotbar: ResMut<Hotbar>,
+    mut brush: ResMut<Brush>,
+) {
+    if let Some(slot) = SLOT_KEYS.iter().position(|&key| keys.just_pressed(key)) {
+        hotbar.selected = slot;
+    }
+    let material = hotbar.material().value();
+    if brush.material != material {
+        brush.material = material;
+    }
+}
+
+/// Middle click picks the material under the crosshair into the hotbar.
+/// The voxel comes from a readback of the grid header, so it lands a frame later.
+pub(super) fn eyedropper(
+    mouse_input: Res<Input<MouseButton>>,
+    frame_count: Res<FrameCount>,
+    mut hotbar: ResMut<Hotbar>,
+    mut selection_request: ResMut<SelectionReadbackRequest>,
+    mut selection_events: EventReader<SelectionReadback>,
+    mut waiting: Local<bool>,
+) {
+    // Any readback will do, a recording asks for one every frame
+    if let Some(selection) = selection_events.iter().last() {
+        if *waiting {
+            *waiting = false;
+            hotbar.pick(selection.voxel);
+        }
+    }
+
+    if mouse_input.just_pressed(MouseButton::Middle) {
+        *waiting = true;
+        if !selection_request.is_requested() {
+            selection_request.request(frame_count.0 as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_reuses_matching_slot() {
+        let mut hotbar = Hotbar::default();
+        let water = hotbar.slots[8];
+        hotbar.pick(water);
+        assert_eq!(hotbar.selected, 8);
+
+        let red = Voxel::material(Vec3::new(1.0, 0.0, 0.0), VOXEL_TYPE_SAND);
+        hotbar.pick(red);
+        assert_eq!(hotbar.selected, 8);
+        assert_eq!(hotbar.material(), red);
+
+        hotbar.pick(Voxel::EMPTY);
+        assert_eq!(hotbar.material(), red);
+    }
+
+    #[test]
+    fn black_is_not_empty() {
+        assert!(!Voxel::material(Vec3::ZERO, VOXEL_TYPE_SAND).is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, ShaderType, StorageBuffer, UniformBuffer};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+
+use crate::voxel::material::{emissive_clusters, VoxelMaterials};
+use super::atmosphere::{Atmosphere, AtmosphereUniform};
+use super::mirror::VoxelGridMirror;
+use super::ComputePipeline;
+
+/// How the ambient light is darkened in crevices and corners
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, FromReflect)]
+pub enum AmbientOcclusion {
+    Off,
+    /// From the voxels around the corners of the hit face, smoothed across it. Cheap, but only sees direct neighbours.
+    #[default]
+    Vertex,
+    /// Short rays over the hemisphere above the hit point, noisy at low sample counts
+    RayTraced,
+}
+
+/// Sunlight and ambient light for `raytrace.wgsl`, shown in the inspector.
+/// Faces are lit by the angle they make with the sun, and a shadow ray towards the sun decides how much of it reaches them.
+/// Point lights are added on top, see [`PointLights`].
+#[derive(Resource, Clone, Debug, Reflect, ExtractResource)]
+#[reflect(Resource)]
+pub struct Lighting {
+    /// Direction the sunlight comes from, it doesn't need to be normalized
+    pub sun_direction: Vec3,
+    pub sun_color: Vec3,
+    pub sun_intensity: f32,
+    /// Light reaching every face, including the ones in shadow
+    pub ambient_color: Vec3,
+    pub shadows: bool,
+    /// Averages several shadow rays spread over the disk of the sun, instead of a single one towards its middle
+    pub soft_shadows: bool,
+    /// Shadow rays per pixel when shadows are soft
+    pub shadow_samples: u32,
+    /// Angular radius of the sun in degrees, the bigger the sun the blurrier soft shadows get
+    pub sun_radius: f32,
+    pub ambient_occlusion: AmbientOcclusion,
+    /// Rays per pixel for [`AmbientOcclusion::RayTraced`]
+    pub ao_samples: u32,
+    /// How far away voxels still occlude, for [`AmbientOcclusion::RayTraced`]
+    pub ao_radius: f32,
+    /// 0 leaves the ambient light alone, 1 blacks out fully occluded corners
+    pub ao_strength: f32,
+    /// Bends rays through transparent materials by their refractive index
+    pub refraction: bool,
+    /// Transparent voxels a ray passes through before the next one is drawn opaque, or casts an opaque shadow
+    pub max_transparent_layers: u32,
+    /// Reflections of reflections followed, 0 turns reflections off
+    pub max_bounces: u32,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vec3::new(0.4, 1.0, 0.3),
+            sun_color: Vec3::new(1.0, 0.95, 0.85),
+            sun_intensity: 0.8,
+            ambient_color: Vec3::new(0.3, 0.32, 0.38),
+            shadows: true,
+            soft_shadows: false,
+            shadow_samples: 4,
+            sun_radius: 2.0,
+            ambient_occlusion: AmbientOcclusion::default(),
+            ao_samples: 8,
+            ao_radius: 4.0,
+            ao_strength: 0.8,
+            refraction: true,
+            max_transparent_layers: 4,
+            max_bounces: 2,
+        }
+    }
+}
+
+/// Layout of the `Lighting` uniform in `raytrace.wgsl`
+#[derive(Clone, Default, ShaderType)]
+struct LightingUniform {
+    sun_direction: Vec3,
+    sun_radius: f32,
+    sun_color: Vec3,
+    // 0 when shadows are off
+    shadow_samples: u32,
+    ambient_color: Vec3,
+    ao_mode: u32,
+    ao_samples: u32,
+    ao_radius: f32,
+    ao_strength: f32,
+    refraction: u32,
+    max_transparent_layers: u32,
+    max_bounces: u32,
+}
+
+impl From<&Lighting> for LightingUniform {
+    fn from(lighting: &Lighting) -> Self {
+        let shadow_samples = match (lighting.shadows, lighting.soft_shadows) {
+            (false, _) => 0,
+            (true, false) => 1,
+            (true, true) => lighting.shadow_samples.max(1),
+        };
+        Self {
+            sun_direction: lighting.sun_direction.normalize_or_zero(),
+            sun_radius: lighting.sun_radius.to_radians(),
+            sun_color: lighting.sun_color * lighting.sun_intensity,
+            shadow_samples,
+            ambient_color: lighting.ambient_color,
+            ao_mode: lighting.ambient_occlusion as u32,
+            ao_samples: lighting.ao_samples,
+            ao_radius: lighting.ao_radius,
+            ao_strength: lighting.ao_strength.clamp(0.0, 1.0),
+            refraction: lighting.refraction as u32,
+            max_transparent_layers: lighting.max_transparent_layers,
+            max_bounces: lighting.max_bounces,
+        }
+    }
+}
+
+/// A point light lighting the voxels around it, placed by its [`Transform`] and shown in the inspector
+#[derive(Component, Clone, Debug, Reflect, FromReflect)]
+#[reflect(Component)]
+pub struct VoxelLight {
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Voxels further away than this aren't lit
+    pub range: f32,
+}
+
+impl Default for VoxelLight {
+    fn default() -> Self {
+        Self {
+            color: Vec3::ONE,
+            intensity: 20.0,
+            range: 24.0,
+        }
+    }
+}
+
+/// Most point lights traced per pixel, the brightest ones win
+const MAX_POINT_LIGHTS: usize = 32;
+/// Width of the cells emissive voxels are grouped into, each group lights the world as one point light
+const EMISSIVE_CLUSTER_SIZE: u32 = 8;
+/// Range of the lights made from emissive voxels, per unit of their brightness
+const EMISSIVE_RANGE_SCALE: f32 = 4.0;
+const MAX_EMISSIVE_RANGE: f32 = 48.0;
+
+/// Layout of `PointLight` in `raytrace.wgsl`
+#[derive(Clone, Copy, Debug, Default, ShaderType)]
+pub(super) struct GpuPointLight {
+    position: Vec3,
+    range: f32,
+    // Color times intensity
+    color: Vec3,
+    // 1 for lights standing in for emissive voxels, which the path tracer hits instead
+    from_voxels: u32,
+}
+
+/// Layout of `Material` in `raytrace.wgsl`
+#[derive(Clone, Copy, Default, ShaderType)]
+struct GpuMaterial {
+    emission: f32,
+    transparent: u32,
+    opacity: f32,
+    density: f32,
+    refractive_index: f32,
+    roughness: f32,
+    metalness: f32,
+}
+
+/// The point lights traced this frame: [`VoxelLight`] entities and the clusters of emissive voxels in the [`VoxelGridMirror`]
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct PointLights {
+    lights: Vec<GpuPointLight>,
+}
+
+/// Emissive clusters are only looked for again when the mirror has a new grid or the materials changed
+pub(super) fn update_point_lights(
+    mirror: Res<VoxelGridMirror>,
+    materials: Res<VoxelMaterials>,
+    lights: Query<(&VoxelLight, &Transform)>,
+    mut clusters: Local<(u64, Vec<GpuPointLight>)>,
+    mut point_lights: ResMut<PointLights>,
+) {
+    if let Some(grid) = mirror.grid() {
+        if clusters.0 != mirror.generation() || materials.is_changed() {
+            clusters.0 = mirror.generation();
+            clusters.1 = emissive_clusters(grid, &materials, EMISSIVE_CLUSTER_SIZE)
+                .into_iter()
+                .map(|cluster| GpuPointLight {
+                    position: cluster.position,
+                    range: (cluster.color.max_element().sqrt() * EMISSIVE_RANGE_SCALE).min(MAX_EMISSIVE_RANGE),
+                    color: cluster.color,
+                    from_voxels: 1,
+                })
+                .collect();
+        }
+    }
+
+    let mut all: Vec<GpuPointLight> = lights
+        .iter()
+        .map(|(light, transform)| GpuPointLight {
+            position: transform.translation,
+            range: light.range,
+            color: light.color * light.intensity,
+            from_voxels: 0,
+        })
+        .chain(clusters.1.iter().copied())
+        .collect();
+    all.sort_by(|a, b| b.color.length_squared().total_cmp(&a.color.length_squared()));
+    all.truncate(MAX_POINT_LIGHTS);
+    point_lights.lights = all;
+}
+
+/// Layout of `PointLights` in `raytrace.wgsl`
+#[derive(Clone, Default, ShaderType)]
+struct GpuPointLights {
+    count: u32,
+    #[size(runtime)]
+    lights: Vec<GpuPointLight>,
+}
+
+#[derive(Resource)]
+pub(super) struct LightingBuffers {
+    uniform: UniformBuffer<LightingUniform>,
+    atmosphere: UniformBuffer<AtmosphereUniform>,
+    materials: StorageBuffer<Vec<GpuMaterial>>,
+    lights: StorageBuffer<GpuPointLights>,
+}
+
+#[derive(Resource)]
+pub(super) struct LightingBindGroup(pub BindGroup);
+
+pub(super) fn write_lighting_buffers(
+    mut commands: Commands,
+    lighting: Res<Lighting>,
+    atmosphere: Res<Atmosphere>,
+    materials: Res<VoxelMaterials>,
+    point_lights: Res<PointLights>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let mut uniform = UniformBuffer::from(LightingUniform::from(&*lighting));
+    uniform.write_buffer(&render_device, &render_queue);
+    let mut atmosphere = UniformBuffer::from(AtmosphereUniform::from(&*atmosphere));
+    atmosphere.write_buffer(&render_device, &render_queue);
+
+    // Storage buffers can't be empty, the shader goes by the light count and the array length
+    let mut materials: Vec<GpuMaterial> = materials.materials
+        .iter()
+        .map(|material| GpuMaterial {
+            emission: if material.emissive { material.emission } else { 0.0 },
+            transparent: material.transparent as u32,
+            opacity: material.opacity.clamp(0.0, 1.0),
+            density: material.density.max(0.0),
+            refractive_index: material.refractive_index.max(1.0),
+            roughness: material.roughness.clamp(0.0, 1.0),
+            metalness: material.metalness.clamp(0.0, 1.0),
+        })
+        .collect();
+    if materials.is_empty() {
+        materials.push(GpuMaterial::default());
+    }
+    let mut materials = StorageBuffer::from(materials);
+    materials.write_buffer(&render_device, &render_queue);
+
+    let mut lights = point_lights.lights.clone();
+    let count = lights.len() as u32;
+    if lights.is_empty() {
+        lights.push(GpuPointLight::default());
+    }
+    let mut lights = StorageBuffer::from(GpuPointLights { count, lights });
+    lights.write_buffer(&render_device, &render_queue);
+
+    commands.insert_resource(LightingBuffers { uniform, atmosphere, materials, lights });
+}
+
+pub(super) fn queue_lighting_bind_group(
+    mut commands: Commands,
+    pipeline: Res<ComputePipeline>,
+    buffers: Res<LightingBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.lighting_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: buffers.uniform.binding().unwrap(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffers.materials.binding().unwrap(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: buffers.lights.binding().unwrap(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: buffers.atmosphere.binding().unwrap(),
+            },
+        ],
+    });
+    commands.insert_resource(LightingBindGroup(bind_group));
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+use crate::voxel::VoxelGrid;
+use super::edits::VoxelGridEdits;
+use super::physics::PhysicsTimer;
+use super::readback::{VoxelGridReadback, VoxelGridReadbackRequest};
+use super::PlayerData;
+
+/// CPU copy of the voxel grid for gameplay queries such as [`super::picking::VoxelPick`].
+/// It is refreshed from grid readbacks, at most every `interval` seconds while the grid may be changing,
+/// so it lags the GPU by up to that long.
+#[derive(Resource)]
+pub struct VoxelGridMirror {
+    pub interval: f32,
+    grid: Option<Arc<VoxelGrid>>,
+    generation: u64,
+    since_refresh: f32,
+    dirty: bool,
+}
+
+impl Default for VoxelGridMirror {
+    fn default() -> Self {
+        Self {
+            interval: 0.25,
+            grid: None,
+            generation: 0,
+            since_refresh: 0.0,
+            dirty: true,
+        }
+    }
+}
+
+impl VoxelGridMirror {
+    /// The latest grid read back, `None` until the first readback arrives
+    pub fn grid(&self) -> Option<&VoxelGrid> {
+        self.grid.as_deref()
+    }
+
+    /// Counts the grids taken, so users can tell when there is a new one
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// Takes every readback that arrives, whoever asked for it, and asks for another once the grid may have changed
+pub(super) fn refresh_voxel_grid_mirror(
+    time: Res<Time>,
+    physics_timer: Res<PhysicsTimer>,
+    player_data: Res<PlayerData>,
+    edits: Res<VoxelGridEdits>,
+    mut mirror: ResMut<VoxelGridMirror>,
+    mut readback_request: ResMut<VoxelGridReadbackRequest>,
+    mut readback_events: EventReader<VoxelGridReadback>,
+) {
+    if let Some(readback) = readback_events.iter().last() {
+        mirror.grid = Some(readback.grid.clone());
+        mirror.generation += 1;
+    }
+
+    let brushing = player_data.mouse_click & 0b101 != 0;
+    if physics_timer.ticks_this_frame() > 0 || brushing || !edits.is_empty() {
+        mirror.dirty = true;
+    }
+    mirror.since_refresh += time.delta_seconds();
+    if mirror.dirty && mirror.since_refresh >= mirror.interval {
+        readback_request.request();
+        mirror.dirty = false;
+        mirror.since_refresh = 0.0;
+    }
+}
@@ -2,67 +2,149 @@ use std::borrow::Cow;
 use std::sync::Arc;
 
 use bevy::input::mouse::MouseWheel;
+use bevy::pbr::NotShadowCaster;
 use bevy::prelude::*;
+use bevy::render::camera::CameraProjection;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_graph::{RenderGraph, self};
-use bevy::render::render_resource::{StorageBuffer, ShaderType, UniformBuffer, BindGroup, BindGroupLayout, CachedComputePipelineId, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BufferBindingType, BindingType, StorageTextureAccess, TextureFormat, TextureViewDimension, PipelineCache, ComputePipelineDescriptor, BindGroupEntry, BindGroupDescriptor, BufferBinding, BindingResource, ComputePassDescriptor, Extent3d, TextureDimension, TextureUsages};
+use bevy::render::render_resource::{StorageBuffer, ShaderType, UniformBuffer, BufferUsages, BindGroup, BindGroupLayout, CachedComputePipelineId, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BufferBindingType, BindingType, StorageTextureAccess, TextureFormat, TextureViewDimension, PipelineCache, ComputePipelineDescriptor, BindGroupEntry, BindGroupDescriptor, BufferBinding, BindingResource, ComputePassDescriptor, Extent3d, TextureDimension, TextureUsages};
 use bevy::render::renderer::{RenderDevice, RenderQueue, RenderContext};
+use bevy::render::view::NoFrustumCulling;
 use bevy::render::{RenderApp, RenderSet};
 use bevy::render::extract_resource::{ExtractResourcePlugin, ExtractResource};
 
-use rand::Rng;
-
-use crate::util::flycam::FlyCam;
-use crate::util::*;
+use crate::util::flycam::{FlyCam, MovementMode};
 use crate::voxel::VoxelGrid;
+use crate::voxel::material::{VoxelMaterial, VoxelMaterials};
+use crate::voxel::character::{CharacterBody, WalkSettings};
+use crate::voxel::brush::{Brush, BrushOperation, MAX_BRUSH_RADIUS};
+use crate::voxel::regions::{ActiveRegions, REGION_SIZE};
+use crate::voxel::generation::{WorldSeed, generate_world};
+use crate::voxel::history::SnapshotHistory;
+use crate::voxel::undo::EditHistory;
+use atmosphere::{Atmosphere, SkyModel, update_clear_color};
+use composite::VoxelCompositeMaterial;
+use clipboard::{Clipboard, ClipboardKeyBindings, clipboard_controls, run_clipboard_actions, request_clipboard_readbacks};
+use edits::{VoxelGridEdits, VoxelsEdited, clear_voxel_grid_edits, write_voxel_grid_edits};
+use exposure::{Exposure, ExposureMode, ExposureMeter, ExposureMeterReadback, ExposureMeterRequest, setup_exposure_meter, update_exposure, prepare_exposure_meter_readback, map_exposure_meter};
+use hotbar::{Hotbar, hotbar_controls, eyedropper};
+use lighting::{AmbientOcclusion, Lighting, LightingBindGroup, PointLights, VoxelLight, update_point_lights, write_lighting_buffers, queue_lighting_bind_group};
+use mirror::{VoxelGridMirror, refresh_voxel_grid_mirror};
+use path_trace::{PathTracer, PathTraceImage, PathTraceBindGroup, update_path_tracer, write_path_trace_buffer, queue_path_trace_bind_group};
+use picking::{VoxelPick, update_voxel_pick};
+use physics::{PhysicsTimer, PhysicsKeyBindings, physics_controls};
+pub(crate) use physics::update_physics_timer;
+use readback::{VoxelGridReadback, VoxelGridReadbackRequest, SelectionReadback, SelectionReadbackRequest, ReadbackBuffer, SelectionReadbackBuffer, VOXEL_GRID_HEADER_SIZE, clear_voxel_grid_readback_request, receive_voxel_grid_readback, receive_selection_readback, prepare_readback_buffer, prepare_selection_readback_buffer, map_readback_buffer, map_selection_readback_buffer};
+use undo::{EditLog, EditLogReadback, EditLogRequest, EditLogBindGroup, setup_edit_log, clear_edit_log_request, track_brush_strokes, receive_brush_strokes, undo_controls, queue_edit_log_bind_group, prepare_edit_log_readback, map_edit_log};
+use rigid::{RigidBodies, detach_rigid_islands, step_rigid_bodies};
+use walk::{start_walking, walk_player};
+use snapshot::{RewindEvent, store_snapshots, rewind_simulation};
+pub(crate) use snapshot::request_snapshots;
+
+pub mod atmosphere;
+pub mod clipboard;
+pub mod composite;
+pub mod edits;
+pub mod exposure;
+pub mod hotbar;
+pub mod lighting;
+pub mod mirror;
+pub mod path_trace;
+pub mod physics;
+pub mod picking;
+pub mod readback;
+pub mod rigid;
+pub mod snapshot;
+pub mod undo;
+pub mod walk;
 
 #[derive(Resource, Default, Clone, ShaderType, ExtractResource)]
-struct PlayerData {
-    camera_matrix: Mat4,
-    inverse_perspective_matrix: Mat4,
-    mouse_click: u32,
-    brush_size: u32,
+pub(crate) struct PlayerData {
+    pub camera_matrix: Mat4,
+    pub inverse_projection_matrix: Mat4,
+    pub mouse_click: u32,
+    /// Brush radius
+    pub brush_size: u32,
+    pub brush_shape: u32,
+    pub brush_operation: u32,
+    pub brush_material: u32,
+    pub brush_replace_type: u32,
 }
 
+/// Double buffered voxel data for the cellular automata, the buffers swap roles after every physics tick
 #[derive(Resource, Clone, ExtractResource)]
-struct VoxelGridStorage(Arc<StorageBuffer<VoxelGrid>>);
+struct VoxelGridStorage([Arc<StorageBuffer<VoxelGrid>>; 2]);
 
-#[derive(Resource, Clone, ExtractResource)]
-struct VoxelGridStorageDouble(Arc<StorageBuffer<VoxelGrid>>);
+/// Which of the [`VoxelGridStorage`] buffers holds the most recent physics tick
+#[derive(Resource, Clone, Copy, Default, ExtractResource)]
+pub(crate) struct VoxelGridIndex(usize);
 
-#[derive(Resource)]
-struct PlayerDataUniform(UniformBuffer<PlayerData>);
+impl VoxelGridIndex {
+    fn swap(&mut self) {
+        self.0 = 1 - self.0;
+    }
+}
+
+/// Indirect dispatch arguments for the physics passes, one workgroup per awake region
+#[derive(Default, Clone, ShaderType)]
+struct RegionDispatch {
+    x: u32,
+    y: u32,
+    z: u32,
+}
 
+/// GPU side of [`ActiveRegions`]: the dispatch arguments, per-region wake flags and the compacted list of awake regions
 #[derive(Resource, Clone, ExtractResource)]
-struct PhysicsTimer {
-    elapsed_time: f32,
-    trigger_time: f32,
+struct ActiveRegionStorage {
+    dispatch: Arc<StorageBuffer<RegionDispatch>>,
+    flags: Arc<StorageBuffer<Vec<u32>>>,
+    list: Arc<StorageBuffer<Vec<u32>>>,
 }
 
-impl PhysicsTimer {
-    fn triggered(&self) -> bool {
-        self.elapsed_time >= self.trigger_time
+impl ActiveRegionStorage {
+    /// Queues every region to run on the next physics tick
+    fn wake_all(&self, render_queue: &RenderQueue) {
+        let regions = ActiveRegions::new(VOXEL_GRID_SIZE);
+        let list = regions.active();
+        let dispatch = [list.len() as u32, 1, 1];
+        render_queue.write_buffer(self.dispatch.buffer().unwrap(), 0, bytemuck::cast_slice(&dispatch));
+        render_queue.write_buffer(self.flags.buffer().unwrap(), 0, bytemuck::cast_slice(&regions.flags()));
+        render_queue.write_buffer(self.list.buffer().unwrap(), 0, bytemuck::cast_slice(&list));
     }
-    fn reset(&mut self) {
-        self.elapsed_time = 0.0;
-    }
-    fn tick(&mut self, amount: f32) {
-        self.elapsed_time += amount;
+
+    /// Flags regions to be merged into the dispatch list by the next compaction
+    fn wake(&self, render_queue: &RenderQueue, regions: &[u32]) {
+        let flags = self.flags.buffer().unwrap();
+        for &region in regions {
+            render_queue.write_buffer(flags, region as u64 * 4, bytemuck::bytes_of(&1u32));
+        }
     }
 }
 
-#[derive(Resource, Clone, Deref, ExtractResource)]
-struct RaycastOutputImage(Handle<Image>);
+#[derive(Resource)]
+struct PlayerDataUniform(UniformBuffer<PlayerData>);
+
+/// Linear HDR colors written by the raycast, and the view distance of their hits
+#[derive(Resource, Clone, ExtractResource)]
+struct RaycastOutputImage {
+    color: Handle<Image>,
+    depth: Handle<Image>,
+}
 
 // Bind groups
 #[derive(Resource)]
 struct PhysicsUniformBindGroup(BindGroup);
 
+/// One bind group per buffer of [`VoxelGridStorage`], read by the raycast and physics passes
 #[derive(Resource)]
-struct VoxelGridStorageBindGroup(BindGroup);
+struct VoxelGridStorageBindGroups([BindGroup; 2]);
 
+/// One bind group per buffer of [`VoxelGridStorage`], written by the physics pass
 #[derive(Resource)]
-struct VoxelGridStorageDoubleBindGroup(BindGroup);
+struct VoxelGridOutputBindGroups([BindGroup; 2]);
+
+#[derive(Resource)]
+struct ActiveRegionBindGroup(BindGroup);
 
 #[derive(Resource)]
 struct RaycastImageBindGroup(BindGroup);
@@ -71,10 +153,17 @@ struct RaycastImageBindGroup(BindGroup);
 pub struct ComputePipeline {
     voxel_data_bind_group_layout: BindGroupLayout,
     physics_data_bind_group_layout: BindGroupLayout,
+    region_bind_group_layout: BindGroupLayout,
+    edit_log_bind_group_layout: BindGroupLayout,
     texture_bind_group_layout: BindGroupLayout,
+    lighting_bind_group_layout: BindGroupLayout,
+    path_trace_bind_group_layout: BindGroupLayout,
     compute_physics: CachedComputePipelineId,
-    compute_buffer_swap: CachedComputePipelineId,
+    compute_region_compact: CachedComputePipelineId,
+    compute_region_restore: CachedComputePipelineId,
+    compute_brush: CachedComputePipelineId,
     compute_raycast: CachedComputePipelineId,
+    compute_path_trace: CachedComputePipelineId,
 }
 
 pub struct RenderComputePlugin;
@@ -82,21 +171,133 @@ pub struct RenderComputePlugin;
 impl Plugin for RenderComputePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(ExtractResourcePlugin::<VoxelGridStorage>::default());
-        app.add_plugin(ExtractResourcePlugin::<VoxelGridStorageDouble>::default());
+        app.add_plugin(ExtractResourcePlugin::<VoxelGridIndex>::default());
+        app.add_plugin(ExtractResourcePlugin::<ActiveRegionStorage>::default());
         app.add_plugin(ExtractResourcePlugin::<PlayerData>::default());
         app.add_plugin(ExtractResourcePlugin::<PhysicsTimer>::default());
         app.add_plugin(ExtractResourcePlugin::<RaycastOutputImage>::default());
-
+        app.add_plugin(ExtractResourcePlugin::<VoxelGridEdits>::default());
+        app.add_plugin(ExtractResourcePlugin::<VoxelGridReadbackRequest>::default());
+        app.add_plugin(ExtractResourcePlugin::<SelectionReadbackRequest>::default());
+        app.add_plugin(ExtractResourcePlugin::<EditLog>::default());
+        app.add_plugin(ExtractResourcePlugin::<EditLogRequest>::default());
+        app.add_plugin(ExtractResourcePlugin::<Lighting>::default());
+        app.add_plugin(ExtractResourcePlugin::<Atmosphere>::default());
+        app.add_plugin(ExtractResourcePlugin::<VoxelMaterials>::default());
+        app.add_plugin(ExtractResourcePlugin::<PointLights>::default());
+        app.add_plugin(ExtractResourcePlugin::<PathTracer>::default());
+        app.add_plugin(ExtractResourcePlugin::<PathTraceImage>::default());
+        app.add_plugin(ExtractResourcePlugin::<ExposureMeter>::default());
+        app.add_plugin(ExtractResourcePlugin::<ExposureMeterRequest>::default());
+
+        app.add_plugin(MaterialPlugin::<VoxelCompositeMaterial>::default());
+
+        app.init_resource::<WorldSeed>();
         app.add_startup_system(setup);
+        app.init_resource::<Lighting>();
+        app.register_type::<Lighting>();
+        app.register_type::<AmbientOcclusion>();
+        app.init_resource::<Atmosphere>();
+        app.register_type::<Atmosphere>();
+        app.register_type::<SkyModel>();
+        app.add_system(update_clear_color.run_if(resource_changed::<Atmosphere>()));
+        app.init_resource::<VoxelMaterials>();
+        app.register_type::<VoxelMaterials>();
+        app.register_type::<VoxelMaterial>();
+        app.init_resource::<PointLights>();
+        app.register_type::<VoxelLight>();
+        app.add_system(update_point_lights.after(refresh_voxel_grid_mirror).in_base_set(CoreSet::PostUpdate));
+        app.init_resource::<PathTracer>();
+        app.register_type::<PathTracer>();
+        app.add_system(update_path_tracer.in_base_set(CoreSet::PostUpdate));
+        app.init_resource::<Exposure>();
+        app.register_type::<Exposure>();
+        app.register_type::<ExposureMode>();
+        app.init_resource::<ExposureMeterRequest>();
+        app.add_startup_system(setup_exposure_meter);
+        app.add_system(update_exposure);
+        app.init_resource::<Brush>();
+        app.add_system(brush_controls.before(update_player_uniform));
+        app.init_resource::<Hotbar>();
+        app.add_system(eyedropper.before(hotbar_controls));
+        app.add_system(hotbar_controls.before(update_player_uniform));
         app.add_system(update_player_uniform);
+        app.init_resource::<PhysicsKeyBindings>();
+        app.add_system(physics_controls.before(update_physics_timer));
         app.add_system(update_physics_timer);
+
+        // CPU edits and readbacks only live for a single frame
+        app.init_resource::<VoxelGridEdits>();
+        app.add_event::<VoxelsEdited>();
+        app.init_resource::<VoxelGridReadbackRequest>();
+        app.init_resource::<SelectionReadbackRequest>();
+        app.add_event::<VoxelGridReadback>();
+        app.add_event::<SelectionReadback>();
+        app.add_system(clear_voxel_grid_edits.in_base_set(CoreSet::First));
+        app.add_system(clear_voxel_grid_readback_request.in_base_set(CoreSet::First));
+        app.add_system(receive_voxel_grid_readback.in_base_set(CoreSet::PreUpdate));
+        app.add_system(receive_selection_readback.in_base_set(CoreSet::PreUpdate));
+
+        // Snapshot history for rewinding the simulation
+        app.init_resource::<SnapshotHistory>();
+        app.add_event::<RewindEvent>();
+        app.add_system(store_snapshots);
+        app.add_system(rewind_simulation.after(update_physics_timer).before(request_snapshots));
+        app.add_system(request_snapshots.after(update_physics_timer));
+
+        // Undo history of brush strokes, read back from the GPU edit log
+        app.init_resource::<EditHistory>();
+        app.init_resource::<EditLogRequest>();
+        app.add_startup_system(setup_edit_log);
+        app.add_system(clear_edit_log_request.in_base_set(CoreSet::First));
+        app.add_system(receive_brush_strokes.in_base_set(CoreSet::PreUpdate));
+        app.add_system(track_brush_strokes.in_base_set(CoreSet::PostUpdate));
+        app.add_system(undo_controls.after(update_player_uniform));
+
+        app.init_resource::<Clipboard>();
+        app.init_resource::<ClipboardKeyBindings>();
+        app.add_system(clipboard_controls.before(request_clipboard_readbacks).before(update_player_uniform));
+        app.add_system(run_clipboard_actions.before(request_clipboard_readbacks));
+        app.add_system(request_clipboard_readbacks);
+
+        app.init_resource::<VoxelGridMirror>();
+        app.init_resource::<VoxelPick>();
+        app.add_system(refresh_voxel_grid_mirror.in_base_set(CoreSet::PostUpdate));
+        app.add_system(update_voxel_pick.after(update_player_uniform));
+
+        app.init_resource::<RigidBodies>();
+        app.add_system(detach_rigid_islands.after(undo_controls).after(run_clipboard_actions));
+        app.add_system(step_rigid_bodies.after(update_physics_timer));
+
+        app.init_resource::<WalkSettings>();
+        app.init_resource::<CharacterBody>();
+        app.add_system(start_walking.run_if(resource_changed::<MovementMode>()).run_if(resource_equals(MovementMode::Walk)));
+        app.add_system(walk_player.after(start_walking).before(update_player_uniform).run_if(resource_equals(MovementMode::Walk)));
         // app.register_type::<VoxelGrid>();
         let render_app = app.sub_app_mut(RenderApp);
         render_app
             .init_resource::<ComputePipeline>()
+            .init_resource::<SelectionReadbackBuffer>()
+            .init_resource::<EditLogReadback>()
+            .init_resource::<ExposureMeterReadback>()
             .add_system(write_uniform_buffers.in_set(RenderSet::Prepare))
+            .add_system(write_voxel_grid_edits.in_set(RenderSet::Prepare))
+            .add_system(prepare_readback_buffer.in_set(RenderSet::Prepare))
+            .add_system(prepare_selection_readback_buffer.in_set(RenderSet::Prepare))
+            .add_system(prepare_edit_log_readback.in_set(RenderSet::Prepare))
+            .add_system(map_readback_buffer.in_set(RenderSet::Cleanup))
+            .add_system(map_selection_readback_buffer.in_set(RenderSet::Cleanup))
+            .add_system(map_edit_log.in_set(RenderSet::Cleanup))
+            .add_system(prepare_exposure_meter_readback.in_set(RenderSet::Prepare))
+            .add_system(map_exposure_meter.in_set(RenderSet::Cleanup))
+            .add_system(queue_edit_log_bind_group.in_set(RenderSet::Queue))
+            .add_system(write_lighting_buffers.in_set(RenderSet::Prepare))
+            .add_system(queue_lighting_bind_group.in_set(RenderSet::Queue))
+            .add_system(write_path_trace_buffer.in_set(RenderSet::Prepare))
+            .add_system(queue_path_trace_bind_group.in_set(RenderSet::Queue))
             // .add_system(update_physics_timer.in_set(RenderSet::Prepare))
-            .add_system(queue_bind_group.in_set(RenderSet::Queue));
+            .add_system(queue_bind_group.in_set(RenderSet::Queue))
+            .add_system(queue_raycast_image_bind_group.in_set(RenderSet::Queue));
 
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
         render_graph.add_node("raycast", RayCastRenderNode::default());
@@ -104,69 +305,54 @@ impl Plugin for RenderComputePlugin {
     }
 }
 const SCREEN_SIZE: (u32, u32) = (1920, 1080);
-const VOXEL_GRID_SIZE: u32 = 128u32;
+pub(crate) const VOXEL_GRID_SIZE: u32 = 128u32;
 const WORKGROUP_SIZE: u32 = 8;
 
 fn setup(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut composite_materials: ResMut<Assets<VoxelCompositeMaterial>>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
+    world_seed: Res<WorldSeed>,
 ) {
-
-    let pos = Vec3 {
-        x: 0.0,
-        y: 0.0,
-        z: 0.0,
-    };
-
     let n = VOXEL_GRID_SIZE;
-    let r = (n - 1) as f32;
-    let mut voxels = VoxelGrid::new(n, pos);
-
-    let mut rng = rand::thread_rng();
-
-    // Create a sphere for testing purposes
-    for i in 0..n {
-        for j in 0..n {
-            for k in 0..n {
-                let pos = (Vec3::new(i as f32, j as f32, k as f32) * 2.0) - Vec3::splat((n) as f32);
-                let in_sphere = pos.length_squared() <= r * r;
-                if let Some(mut voxel) = voxels.get_mut(i, j, k) {
-                    // if in_sphere {
-                        // For now just use sand and add nice variance
-                        // TODO: Add model loading
-                        // let is_sand = rng.gen_bool(0.5);
-
-                        // if is_sand {
-                            let variance = rng.gen_range(-0.02..0.02);
-                            let sand_color = Vec3::new(0.5, 0.3,  0.1);
-                            let varied_sand = vary_color(sand_color, variance);
-                            voxel.set_color(varied_sand);
-                        // }
-                        // else {
-                            // let water_color = Vec3::new(0.3, 0.7, 0.9);
-                            // voxel.set_color(water_color);
-                            // voxel.set_voxel_type(1);
-                        // }
-                    // }
-                }
-            }
-        }
-    }
+    let voxels = generate_world(n, world_seed.0);
 
-    // Create a storage buffer containing our voxel data
-    let mut buffer = StorageBuffer::<VoxelGrid>::from(voxels.clone());
-    buffer.write_buffer(&render_device, &render_queue);
+    // Create a pair of storage buffers containing our voxel data, for cellular automata
+    let buffers = [0, 1].map(|_| {
+        let mut buffer = StorageBuffer::<VoxelGrid>::from(voxels.clone());
+        buffer.write_buffer(&render_device, &render_queue);
+        Arc::new(buffer)
+    });
 
-    commands.insert_resource(VoxelGridStorage(Arc::new(buffer)));
+    commands.insert_resource(VoxelGridStorage(buffers));
+    commands.insert_resource(VoxelGridIndex::default());
 
+    // Start with every region awake, so the first physics tick visits the whole grid
     {
-        // Create a double buffer for voxel data, for cellular automata
-        let mut buffer = StorageBuffer::<VoxelGrid>::from(voxels);
-        buffer.write_buffer(&render_device, &render_queue);
-
-        commands.insert_resource(VoxelGridStorageDouble(Arc::new(buffer)));
+        let regions = ActiveRegions::new(n);
+        let list = regions.active();
+        let dispatch = RegionDispatch {
+            x: list.len() as u32,
+            y: 1,
+            z: 1,
+        };
+
+        let mut dispatch = StorageBuffer::from(dispatch);
+        dispatch.add_usages(BufferUsages::INDIRECT);
+        dispatch.write_buffer(&render_device, &render_queue);
+        let mut flags = StorageBuffer::from(regions.flags());
+        flags.write_buffer(&render_device, &render_queue);
+        let mut list = StorageBuffer::from(list);
+        list.write_buffer(&render_device, &render_queue);
+
+        commands.insert_resource(ActiveRegionStorage {
+            dispatch: Arc::new(dispatch),
+            flags: Arc::new(flags),
+            list: Arc::new(list),
+        });
     }
 
     // Create a uniform buffer for dynamic data like camera position, brush size, and mouse clicking
@@ -174,13 +360,9 @@ fn setup(
     commands.insert_resource(uniform);
 
     // Set up a timer to compute physics at a fixed interval
-    let physics_timer = PhysicsTimer {
-        elapsed_time: 0.0,
-        trigger_time: 1.0 / 30.0
-    };
-    commands.insert_resource(physics_timer);
+    commands.insert_resource(PhysicsTimer::new(1.0 / 30.0));
 
-    // Create the 2D texture buffer to render the results of the raycast
+    // Create the 2D texture buffer to render the results of the raycast, in HDR for the camera to tonemap
     let mut image = Image::new_fill(
         Extent3d {
             width: SCREEN_SIZE.0,
@@ -188,60 +370,70 @@ fn setup(
             depth_or_array_layers: 1,
         },
         TextureDimension::D2,
-        &[0, 0, 0, 255],
-        TextureFormat::Rgba8Unorm,
+        // Opaque black, alpha is a half float 1
+        &[0, 0, 0, 0, 0, 0, 0x00, 0x3c],
+        TextureFormat::Rgba16Float,
     );
     image.texture_descriptor.usage =
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
     let image = images.add(image);
 
-    commands.spawn(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(SCREEN_SIZE.0 as f32, SCREEN_SIZE.1 as f32)),
-            ..default()
+    // And the depth of every pixel, to composite the voxels with meshes
+    let mut depth = Image::new_fill(
+        Extent3d {
+            width: SCREEN_SIZE.0,
+            height: SCREEN_SIZE.1,
+            depth_or_array_layers: 1,
         },
-        texture: image.clone(),
-        ..default()
-    });
-
-    commands.spawn(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(SCREEN_SIZE.0 as f32, SCREEN_SIZE.1 as f32)),
-
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::R32Float,
+    );
+    depth.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    let depth = images.add(depth);
+
+    // Fullscreen quad drawing both in the 3D pass, its shader ignores the transform
+    commands.spawn((
+        MaterialMeshBundle {
+            mesh: meshes.add(shape::Quad::new(Vec2::splat(2.0)).into()),
+            material: composite_materials.add(VoxelCompositeMaterial {
+                color: image.clone(),
+                depth: depth.clone(),
+            }),
             ..default()
         },
-        texture: image.clone(),
-
-        ..default()
-    });
+        NoFrustumCulling,
+        NotShadowCaster,
+    ));
 
-    commands.insert_resource(RaycastOutputImage(image));
+    commands.insert_resource(RaycastOutputImage { color: image, depth });
 
-    commands.spawn(Camera2dBundle::default());
-}
-fn create_perspective_projection_matrix(aspect_ratio : f32, fov : f32, near : f32, far : f32) -> Mat4 {
-    let tan_half_fov = f32::tan(fov * 0.5 * 3.14159265 / 180.0);
-    let sx = 1.0 / (aspect_ratio * tan_half_fov);
-    let sy = 1.0 / tan_half_fov;
-    let sz = -(far + near) / (far - near);
-    let pz = -(2.0 * far * near) / (far - near);
-    return Mat4::from_cols(
-        Vec4::new(sx, 0.0, 0.0, 0.0),
-        Vec4::new(0.0, sy, 0.0, 0.0),
-        Vec4::new(0.0, 0.0, sz, -1.0),
-        Vec4::new(0.0, 0.0, pz, 0.0),
+    // Sums of the path traced samples, kept across frames
+    let mut accumulation = Image::new_fill(
+        Extent3d {
+            width: SCREEN_SIZE.0,
+            height: SCREEN_SIZE.1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 16],
+        TextureFormat::Rgba32Float,
     );
+    accumulation.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING;
+    commands.insert_resource(PathTraceImage(images.add(accumulation)));
 }
-
-fn update_player_uniform(
+pub(crate) fn update_player_uniform(
     mut uniform_data: ResMut<PlayerData>,
-    transform_query: Query<&Transform, With<FlyCam>>,
+    camera_query: Query<(&Transform, &Projection), With<FlyCam>>,
     mouse_input: Res<Input<MouseButton>>,
+    brush: Res<Brush>,
+    clipboard: Res<Clipboard>,
 ) {
-    if let Ok(transform) = transform_query.get_single() {
+    // The voxels are raytraced through the fly camera, its aspect ratio follows the window it renders to
+    if let Ok((transform, projection)) = camera_query.get_single() {
         uniform_data.camera_matrix = transform.compute_matrix();
-        let perspective_matrix = create_perspective_projection_matrix(16.0 / 9.0, 60.0, 0.1, 1000.0);
-        uniform_data.inverse_perspective_matrix = perspective_matrix.inverse();
+        uniform_data.inverse_projection_matrix = projection.get_projection_matrix().inverse();
     }
     let mut mouse_buttons = 0u32;
     if mouse_input.pressed(MouseButton::Left) {
@@ -253,22 +445,35 @@ fn update_player_uniform(
     if mouse_input.pressed(MouseButton::Right) {
         mouse_buttons |= 1 << 2;
     }
+    // Clicks pick the selection's corners instead of brushing
+    if clipboard.selecting {
+        mouse_buttons &= 1 << 1;
+    }
     uniform_data.mouse_click = mouse_buttons;
 
-    uniform_data.brush_size = 3;
-
+    // The left button always removes, the right one applies the selected operation
+    let operation = if mouse_buttons & 1 != 0 {
+        BrushOperation::Remove
+    } else {
+        brush.operation
+    };
+    uniform_data.brush_size = brush.radius;
+    uniform_data.brush_shape = brush.shape as u32;
+    uniform_data.brush_operation = operation as u32;
+    uniform_data.brush_material = brush.material;
+    uniform_data.brush_replace_type = brush.replace_type;
 }
 
-fn update_physics_timer(
-    mut physics_timer: ResMut<PhysicsTimer>,
-    time: Res<Time>
+/// Grows and shrinks the brush with the mouse wheel
+fn brush_controls(
+    mut brush: ResMut<Brush>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
 ) {
-    // If the timer was triggered, reset it
-    if physics_timer.triggered() {
-        physics_timer.reset();
+    let scroll: f32 = mouse_wheel_events.iter().map(|event| event.y).sum();
+    if scroll != 0.0 {
+        let radius = brush.radius as i32 + scroll.signum() as i32;
+        brush.radius = radius.clamp(0, MAX_BRUSH_RADIUS as i32) as u32;
     }
-
-    physics_timer.tick(time.delta_seconds());
 }
 
 fn write_uniform_buffers(
@@ -342,22 +547,151 @@ impl FromWorld for ComputePipeline {
                     // },
                 ],
             });
+        let region_bind_group_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[0, 1, 2].map(|binding| BindGroupLayoutEntry {
+                    binding,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }),
+            });
+        let edit_log_bind_group_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
         let texture_bind_group_layout =
             world
                 .resource::<RenderDevice>()
                 .create_bind_group_layout(&BindGroupLayoutDescriptor {
                     label: None,
-                    entries: &[BindGroupLayoutEntry {
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::ReadWrite,
+                                format: TextureFormat::Rgba16Float,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: TextureFormat::R32Float,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        // Exposure meter
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let lighting_bind_group_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Materials and point lights
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Sky and fog
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let path_trace_bind_group_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    BindGroupLayoutEntry {
                         binding: 0,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
                             access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
+                            format: TextureFormat::Rgba32Float,
                             view_dimension: TextureViewDimension::D2,
                         },
                         count: None,
-                    }],
-                });
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
 
         {
 
@@ -368,51 +702,102 @@ impl FromWorld for ComputePipeline {
         let physics_shader = world
             .resource::<AssetServer>()
             .load("shaders/physics.wgsl");
-        let buffer_swap_shader = world
+        let region_compact_shader = world
+            .resource::<AssetServer>()
+            .load("shaders/region_compact.wgsl");
+        let brush_shader = world
             .resource::<AssetServer>()
-            .load("shaders/buffer_swap.wgsl");
+            .load("shaders/brush.wgsl");
         let pipeline_cache = world.resource::<PipelineCache>();
         let compute_physics = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
             label: None,
             layout: vec![
                 voxel_data_bind_group_layout.clone(),
                 physics_data_bind_group_layout.clone(),
+                region_bind_group_layout.clone(),
             ],
             push_constant_ranges: Vec::new(),
             shader: physics_shader,
             shader_defs: vec![],
             entry_point: Cow::from("update"),
         });
-        let compute_buffer_swap = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        let compute_region_compact = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
             label: None,
             layout: vec![
                 voxel_data_bind_group_layout.clone(),
                 physics_data_bind_group_layout.clone(),
+                region_bind_group_layout.clone(),
             ],
             push_constant_ranges: Vec::new(),
-            shader: buffer_swap_shader,
+            shader: region_compact_shader.clone(),
             shader_defs: vec![],
             entry_point: Cow::from("update"),
         });
+        let compute_region_restore = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: vec![
+                voxel_data_bind_group_layout.clone(),
+                physics_data_bind_group_layout.clone(),
+                region_bind_group_layout.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            shader: region_compact_shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("restore"),
+        });
+        let compute_brush = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: vec![
+                voxel_data_bind_group_layout.clone(),
+                physics_data_bind_group_layout.clone(),
+                region_bind_group_layout.clone(),
+                edit_log_bind_group_layout.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            shader: brush_shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("edit"),
+        });
         let compute_raycast = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
             label: None,
             layout: vec![
                 voxel_data_bind_group_layout.clone(),
                 texture_bind_group_layout.clone(),
+                lighting_bind_group_layout.clone(),
             ],
             push_constant_ranges: Vec::new(),
-            shader: raycast_shader,
+            shader: raycast_shader.clone(),
             shader_defs: vec![],
             entry_point: Cow::from("update"),
         });
+        let compute_path_trace = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: vec![
+                voxel_data_bind_group_layout.clone(),
+                texture_bind_group_layout.clone(),
+                lighting_bind_group_layout.clone(),
+                path_trace_bind_group_layout.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            shader: raycast_shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("path_trace_update"),
+        });
 
         ComputePipeline {
             voxel_data_bind_group_layout,
             physics_data_bind_group_layout,
+            region_bind_group_layout,
+            edit_log_bind_group_layout,
             texture_bind_group_layout,
+            lighting_bind_group_layout,
+            path_trace_bind_group_layout,
             compute_raycast,
+            compute_path_trace,
             compute_physics,
-            compute_buffer_swap,
+            compute_region_compact,
+            compute_region_restore,
+            compute_brush,
         }
     }
 }
@@ -420,73 +805,114 @@ impl FromWorld for ComputePipeline {
 fn queue_bind_group(
     mut commands: Commands,
     pipeline: Res<ComputePipeline>,
-    gpu_images: Res<RenderAssets<Image>>,
     voxel_grid: Res<VoxelGridStorage>,
-    double_buffer: Res<VoxelGridStorageDouble>,
+    regions: Res<ActiveRegionStorage>,
     camera_data: Res<PlayerDataUniform>,
-    raycast_image: Res<RaycastOutputImage>,
     render_device: Res<RenderDevice>,
 ) {
-    // Bind the voxel data as a storage buffer
+    // Bind each voxel data buffer as a storage buffer, with the player data alongside it
     {
-        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &pipeline.voxel_data_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::Buffer(BufferBinding {
-                    buffer: &voxel_grid.0.buffer().unwrap(),
-                    offset: 0,
-                    size: None,
-                }),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::Buffer(BufferBinding {
-                    buffer: &camera_data.0.buffer().unwrap(),
-                    offset: 0,
-                    // size: NonZeroU64::new(VOXEL_BUFFER_SIZE as u64),
-                    size: None,
-                }),
-            },
+        let bind_groups = voxel_grid.0.each_ref().map(|buffer| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.voxel_data_bind_group_layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: buffer.buffer().unwrap(),
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: camera_data.0.buffer().unwrap(),
+                        offset: 0,
+                        // size: NonZeroU64::new(VOXEL_BUFFER_SIZE as u64),
+                        size: None,
+                    }),
+                },
 
-            ],
+                ],
+            })
         });
-        commands.insert_resource(VoxelGridStorageBindGroup(bind_group));
+        commands.insert_resource(VoxelGridStorageBindGroups(bind_groups));
     }
-    // Bind the double buffer of the voxel grid
+    // Bind each voxel data buffer again as the output of the physics pass
     {
-        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &pipeline.physics_data_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::Buffer(BufferBinding {
-                    buffer: &double_buffer.0.buffer().unwrap(),
-                    offset: 0,
-                    size: None,
-                }),
-            },
+        let bind_groups = voxel_grid.0.each_ref().map(|buffer| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.physics_data_bind_group_layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: buffer.buffer().unwrap(),
+                        offset: 0,
+                        size: None,
+                    }),
+                },
 
-            ],
+                ],
+            })
         });
-        commands.insert_resource(VoxelGridStorageDoubleBindGroup(bind_group));
+        commands.insert_resource(VoxelGridOutputBindGroups(bind_groups));
     }
-
-    // Bind the raycast result image as a texture
+    // Bind the active region tracking buffers
     {
-        let view = &gpu_images[&raycast_image.0];
         let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
             label: None,
-            layout: &pipeline.texture_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(&view.texture_view),
-            }],
+            layout: &pipeline.region_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: regions.dispatch.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: regions.flags.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: regions.list.binding().unwrap(),
+                },
+            ],
         });
-        commands.insert_resource(RaycastImageBindGroup(bind_group));
+        commands.insert_resource(ActiveRegionBindGroup(bind_group));
     }
+}
 
+/// Binds the raycast result images as textures, with the exposure meter the raycast writes alongside them
+fn queue_raycast_image_bind_group(
+    mut commands: Commands,
+    pipeline: Res<ComputePipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    raycast_image: Res<RaycastOutputImage>,
+    exposure_meter: Res<ExposureMeter>,
+    render_device: Res<RenderDevice>,
+) {
+    let color = &gpu_images[&raycast_image.color];
+    let depth = &gpu_images[&raycast_image.depth];
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.texture_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&color.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&depth.texture_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: exposure_meter.buffer.as_entire_binding(),
+            },
+        ],
+    });
+    commands.insert_resource(RaycastImageBindGroup(bind_group));
 }
 
 #[derive(Default)]
@@ -499,16 +925,39 @@ impl render_graph::Node for RayCastRenderNode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        let voxel_data_bind_group = &world.resource::<VoxelGridStorageBindGroup>().0;
-        let physics_data_bind_group = &world.resource::<VoxelGridStorageDoubleBindGroup>().0;
+        let voxel_data_bind_groups = &world.resource::<VoxelGridStorageBindGroups>().0;
+        let physics_data_bind_groups = &world.resource::<VoxelGridOutputBindGroups>().0;
+        let mut voxel_grid_index = *world.resource::<VoxelGridIndex>();
+        let region_bind_group = &world.resource::<ActiveRegionBindGroup>().0;
+        let region_dispatch = world.resource::<ActiveRegionStorage>().dispatch.buffer().unwrap();
         let texture_bind_group = &world.resource::<RaycastImageBindGroup>().0;
+        let lighting_bind_group = &world.resource::<LightingBindGroup>().0;
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<ComputePipeline>();
         let physics_timer = world.resource::<PhysicsTimer>();
 
-        // physics pass
-        if physics_timer.triggered() {
-            // First pass
+        // edit passes, before the physics so this frame's ticks already simulate the edited voxels
+        let player_data = world.resource::<PlayerData>();
+        let edit_log = world.resource::<EditLog>();
+        let edit_log_request = world.resource::<EditLogRequest>();
+        let brushing = player_data.mouse_click & 0b101 != 0;
+        let cpu_edits = world.resource::<VoxelGridEdits>().has_voxels();
+        let edit_pipelines = (
+            pipeline_cache.get_compute_pipeline(pipeline.compute_region_restore),
+            pipeline_cache.get_compute_pipeline(pipeline.compute_brush),
+        );
+        if let (true, (Some(compute_region_restore), Some(compute_brush))) = (brushing || cpu_edits, edit_pipelines) {
+            let voxel_data_bind_group = &voxel_data_bind_groups[voxel_grid_index.0];
+            let physics_data_bind_group = &physics_data_bind_groups[1 - voxel_grid_index.0];
+
+            if edit_log_request.clear {
+                render_context
+                    .command_encoder()
+                    .clear_buffer(&edit_log.buffer, 0, std::num::NonZeroU64::new(4));
+            }
+
+            // Flag the regions already listed for the next tick, apply the brush while a button is held,
+            // then compact the listed regions together with the ones woken by the brush or CPU edits
             {
                 let mut pass = render_context
                     .command_encoder()
@@ -516,48 +965,142 @@ impl render_graph::Node for RayCastRenderNode {
 
                 pass.set_bind_group(0, voxel_data_bind_group, &[]);
                 pass.set_bind_group(1, physics_data_bind_group, &[]);
+                pass.set_bind_group(2, region_bind_group, &[]);
+
+                pass.set_pipeline(compute_region_restore);
+                pass.dispatch_workgroups_indirect(region_dispatch, 0);
+
+                if brushing {
+                    pass.set_bind_group(3, &world.resource::<EditLogBindGroup>().0, &[]);
+                    pass.set_pipeline(compute_brush);
+                    let brush_workgroups = (2 * player_data.brush_size + 1).div_ceil(WORKGROUP_SIZE);
+                    pass.dispatch_workgroups(brush_workgroups, brush_workgroups, brush_workgroups);
+                }
+            }
+            {
+                render_context
+                    .command_encoder()
+                    .clear_buffer(region_dispatch, 0, None);
+
+                let mut pass = render_context
+                    .command_encoder()
+                    .begin_compute_pass(&ComputePassDescriptor::default());
+
+                pass.set_bind_group(0, voxel_data_bind_group, &[]);
+                pass.set_bind_group(1, physics_data_bind_group, &[]);
+                pass.set_bind_group(2, region_bind_group, &[]);
+
+                let compute_region_compact = pipeline_cache
+                    .get_compute_pipeline(pipeline.compute_region_compact)
+                    .unwrap();
+                pass.set_pipeline(compute_region_compact);
+                let region_workgroups = (VOXEL_GRID_SIZE / REGION_SIZE).div_ceil(WORKGROUP_SIZE);
+                pass.dispatch_workgroups(region_workgroups, region_workgroups, region_workgroups);
+            }
+        }
+        // Read the finished stroke back for the undo history
+        if let Some(staging) = world.resource::<EditLogReadback>().0.target() {
+            render_context
+                .command_encoder()
+                .copy_buffer_to_buffer(&edit_log.buffer, 0, staging, 0, edit_log.buffer.size());
+        }
+
+        // physics passes, as many ticks as the fixed timestep asks for this frame
+        for _ in 0..physics_timer.ticks_this_frame() {
+            // Read the newest buffer and write the other one
+            let voxel_data_bind_group = &voxel_data_bind_groups[voxel_grid_index.0];
+            let physics_data_bind_group = &physics_data_bind_groups[1 - voxel_grid_index.0];
+
+            // First pass, over the regions woken by the previous tick
+            {
+                let mut pass = render_context
+                    .command_encoder()
+                    .begin_compute_pass(&ComputePassDescriptor::default());
+
+                pass.set_bind_group(0, voxel_data_bind_group, &[]);
+                pass.set_bind_group(1, physics_data_bind_group, &[]);
+                pass.set_bind_group(2, region_bind_group, &[]);
 
                 let compute_physics = pipeline_cache
                     .get_compute_pipeline(pipeline.compute_physics)
                     .unwrap();
                 pass.set_pipeline(compute_physics);
-                pass.dispatch_workgroups(VOXEL_GRID_SIZE / WORKGROUP_SIZE, VOXEL_GRID_SIZE / WORKGROUP_SIZE, VOXEL_GRID_SIZE / WORKGROUP_SIZE);
-                // pass.dispatch_workgroups(1, 1, 1);
+                pass.dispatch_workgroups_indirect(region_dispatch, 0);
             }
-            // Swap pass
+            // Compact the regions woken by this tick into the dispatch list
             {
+                render_context
+                    .command_encoder()
+                    .clear_buffer(region_dispatch, 0, None);
+
                 let mut pass = render_context
                     .command_encoder()
                     .begin_compute_pass(&ComputePassDescriptor::default());
 
                 pass.set_bind_group(0, voxel_data_bind_group, &[]);
                 pass.set_bind_group(1, physics_data_bind_group, &[]);
+                pass.set_bind_group(2, region_bind_group, &[]);
 
-                let compute_buffer_swap = pipeline_cache
-                    .get_compute_pipeline(pipeline.compute_buffer_swap)
+                let compute_region_compact = pipeline_cache
+                    .get_compute_pipeline(pipeline.compute_region_compact)
                     .unwrap();
-                pass.set_pipeline(compute_buffer_swap);
-                pass.dispatch_workgroups(VOXEL_GRID_SIZE / WORKGROUP_SIZE, VOXEL_GRID_SIZE / WORKGROUP_SIZE, VOXEL_GRID_SIZE / WORKGROUP_SIZE);
+                pass.set_pipeline(compute_region_compact);
+                let region_workgroups = (VOXEL_GRID_SIZE / REGION_SIZE).div_ceil(WORKGROUP_SIZE);
+                pass.dispatch_workgroups(region_workgroups, region_workgroups, region_workgroups);
             }
 
+            voxel_grid_index.swap();
         }
 
-        // raycast pass
+        // raycast pass, or the path tracer in its place
+        let exposure_meter = world.resource::<ExposureMeter>();
+        render_context
+            .command_encoder()
+            .clear_buffer(&exposure_meter.buffer, 0, None);
         {
             let mut pass = render_context
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor::default());
 
-            pass.set_bind_group(0, voxel_data_bind_group, &[]);
+            // Always trace against the most recent tick
+            pass.set_bind_group(0, &voxel_data_bind_groups[voxel_grid_index.0], &[]);
             pass.set_bind_group(1, texture_bind_group, &[]);
-
-            let compute_raycast = pipeline_cache
-                .get_compute_pipeline(pipeline.compute_raycast)
-                .unwrap();
-            pass.set_pipeline(compute_raycast);
+            pass.set_bind_group(2, lighting_bind_group, &[]);
+
+            let path_tracing = world.resource::<PathTracer>().enabled;
+            if let (true, Some(compute_path_trace)) = (path_tracing, pipeline_cache.get_compute_pipeline(pipeline.compute_path_trace)) {
+                pass.set_bind_group(3, &world.resource::<PathTraceBindGroup>().0, &[]);
+                pass.set_pipeline(compute_path_trace);
+            } else {
+                let compute_raycast = pipeline_cache
+                    .get_compute_pipeline(pipeline.compute_raycast)
+                    .unwrap();
+                pass.set_pipeline(compute_raycast);
+            }
             pass.dispatch_workgroups(SCREEN_SIZE.0 / WORKGROUP_SIZE, SCREEN_SIZE.1 / WORKGROUP_SIZE, 1);
         }
 
+        if let Some(staging) = world.resource::<ExposureMeterReadback>().0.target() {
+            render_context
+                .command_encoder()
+                .copy_buffer_to_buffer(&exposure_meter.buffer, 0, staging, 0, exposure_meter.buffer.size());
+        }
+
+        // Copy the newest grid out for the CPU, it is mapped once the frame has been submitted
+        if let Some(staging) = world.get_resource::<ReadbackBuffer>().and_then(|readback_buffer| readback_buffer.0.target()) {
+            let voxel_grid = &world.resource::<VoxelGridStorage>().0[voxel_grid_index.0];
+            let buffer = voxel_grid.buffer().unwrap();
+            render_context
+                .command_encoder()
+                .copy_buffer_to_buffer(buffer, 0, staging, 0, buffer.size());
+        }
+        if let Some(staging) = world.resource::<SelectionReadbackBuffer>().0.target() {
+            let voxel_grid = &world.resource::<VoxelGridStorage>().0[voxel_grid_index.0];
+            render_context
+                .command_encoder()
+                .copy_buffer_to_buffer(voxel_grid.buffer().unwrap(), 0, staging, 0, VOXEL_GRID_HEADER_SIZE);
+        }
+
         Ok(())
     }
 }
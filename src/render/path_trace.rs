@@ -0,0 +1,210 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, ShaderType, UniformBuffer};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+
+use crate::voxel::material::VoxelMaterials;
+use super::atmosphere::Atmosphere;
+use super::edits::VoxelGridEdits;
+use super::lighting::{Lighting, VoxelLight};
+use super::physics::PhysicsTimer;
+use super::{ComputePipeline, PlayerData};
+
+/// Reference renderer, shown in the inspector.
+/// Instead of the raycast, every frame adds path traced samples to an HDR accumulation and shows their average,
+/// lighting the voxels with light bounced off the sky, emissive voxels and each other.
+/// The accumulation starts over whenever the view or the grid changes, so pause the simulation to let it converge.
+#[derive(Resource, Clone, Debug, Reflect, ExtractResource)]
+#[reflect(Resource)]
+pub struct PathTracer {
+    pub enabled: bool,
+    /// Samples per pixel added every frame
+    pub samples_per_frame: u32,
+    /// Diffuse and mirror bounces followed per sample
+    pub max_bounces: u32,
+    /// Accumulation stops once every pixel has this many samples, 0 never stops
+    pub max_samples: u32,
+    // Samples per pixel in the accumulation before this frame's
+    #[reflect(ignore)]
+    samples: u32,
+    #[reflect(ignore)]
+    frame_samples: u32,
+    #[reflect(ignore)]
+    camera_matrix: Mat4,
+    #[reflect(ignore)]
+    inverse_projection_matrix: Mat4,
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            samples_per_frame: 1,
+            max_bounces: 4,
+            max_samples: 4096,
+            samples: 0,
+            frame_samples: 0,
+            camera_matrix: Mat4::ZERO,
+            inverse_projection_matrix: Mat4::ZERO,
+        }
+    }
+}
+
+impl PathTracer {
+    /// Samples per pixel accumulated so far, including this frame's
+    pub fn samples(&self) -> u32 {
+        self.samples + self.frame_samples
+    }
+
+    /// Moves on to the next frame, throwing the accumulation away when it no longer matches the view
+    fn advance(&mut self, stale: bool) {
+        if !self.enabled {
+            // Nothing is traced, the first frame once enabled starts from scratch
+            self.samples = 0;
+            self.frame_samples = 0;
+            return;
+        }
+        if stale {
+            self.samples = 0;
+        } else {
+            self.samples += self.frame_samples;
+        }
+        self.frame_samples = match self.max_samples {
+            0 => self.samples_per_frame,
+            max => self.samples_per_frame.min(max.saturating_sub(self.samples)),
+        };
+    }
+}
+
+/// [`VoxelLight`] entities added, changed or moved this frame
+type MovedLights<'w, 's> = Query<'w, 's, (), (With<VoxelLight>, Or<(Changed<VoxelLight>, Changed<Transform>)>)>;
+
+/// Settings the traced image depends on besides the camera and the grid
+#[derive(SystemParam)]
+pub(super) struct SceneSettings<'w> {
+    lighting: Res<'w, Lighting>,
+    atmosphere: Res<'w, Atmosphere>,
+    materials: Res<'w, VoxelMaterials>,
+}
+
+impl SceneSettings<'_> {
+    fn is_changed(&self) -> bool {
+        self.lighting.is_changed() || self.atmosphere.is_changed() || self.materials.is_changed()
+    }
+}
+
+/// Resets the accumulation when the camera moves or its projection changes, the grid is simulated or edited, or the lighting or sky change
+pub(super) fn update_path_tracer(
+    mut path_tracer: ResMut<PathTracer>,
+    player_data: Res<PlayerData>,
+    physics_timer: Res<PhysicsTimer>,
+    edits: Res<VoxelGridEdits>,
+    settings: SceneSettings,
+    moved_lights: MovedLights,
+) {
+    let brushing = player_data.mouse_click & 0b101 != 0;
+    let stale = path_tracer.camera_matrix != player_data.camera_matrix
+        || path_tracer.inverse_projection_matrix != player_data.inverse_projection_matrix
+        || physics_timer.ticks_this_frame() > 0
+        || brushing
+        || !edits.is_empty()
+        || settings.is_changed()
+        || !moved_lights.is_empty();
+    path_tracer.camera_matrix = player_data.camera_matrix;
+    path_tracer.inverse_projection_matrix = player_data.inverse_projection_matrix;
+    path_tracer.advance(stale);
+}
+
+/// Sums of the path traced samples, with their count in the alpha channel
+#[derive(Resource, Clone, Deref, ExtractResource)]
+pub(super) struct PathTraceImage(pub Handle<Image>);
+
+/// Layout of the `PathTrace` uniform in `raytrace.wgsl`
+#[derive(Clone, Default, ShaderType)]
+struct PathTraceUniform {
+    samples_taken: u32,
+    samples: u32,
+    max_bounces: u32,
+    reset: u32,
+}
+
+#[derive(Resource)]
+pub(super) struct PathTraceBuffer(UniformBuffer<PathTraceUniform>);
+
+#[derive(Resource)]
+pub(super) struct PathTraceBindGroup(pub BindGroup);
+
+pub(super) fn write_path_trace_buffer(
+    mut commands: Commands,
+    path_tracer: Res<PathTracer>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let mut buffer = UniformBuffer::from(PathTraceUniform {
+        samples_taken: path_tracer.samples,
+        samples: path_tracer.frame_samples,
+        max_bounces: path_tracer.max_bounces,
+        reset: (path_tracer.samples == 0) as u32,
+    });
+    buffer.write_buffer(&render_device, &render_queue);
+    commands.insert_resource(PathTraceBuffer(buffer));
+}
+
+pub(super) fn queue_path_trace_bind_group(
+    mut commands: Commands,
+    pipeline: Res<ComputePipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    image: Res<PathTraceImage>,
+    buffer: Res<PathTraceBuffer>,
+    render_device: Res<RenderDevice>,
+) {
+    let view = &gpu_images[&image.0];
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.path_trace_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffer.0.binding().unwrap(),
+            },
+        ],
+    });
+    commands.insert_resource(PathTraceBindGroup(bind_group));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_until_stale_or_full() {
+        let mut path_tracer = PathTracer {
+            enabled: true,
+            samples_per_frame: 2,
+            max_samples: 5,
+            ..default()
+        };
+        path_tracer.advance(true);
+        assert_eq!(path_tracer.samples, 0);
+        path_tracer.advance(false);
+        path_tracer.advance(false);
+        assert_eq!((path_tracer.samples, path_tracer.frame_samples), (4, 1));
+        path_tracer.advance(false);
+        assert_eq!((path_tracer.samples(), path_tracer.frame_samples), (5, 0));
+
+        path_tracer.advance(true);
+        assert_eq!((path_tracer.samples, path_tracer.frame_samples), (0, 2));
+
+        path_tracer.enabled = false;
+        path_tracer.advance(false);
+        path_tracer.enabled = true;
+        path_tracer.advance(false);
+        assert_eq!(path_tracer.samples, 0);
+    }
+}
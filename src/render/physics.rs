@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+
+use super::VoxelGridIndex;
+
+const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+const MAX_TIME_SCALE: f32 = 8.0;
+
+/// Fixed timestep accumulator driving the physics passes.
+/// Frame time is accumulated and spent in whole ticks, so the simulation runs at the same rate regardless of FPS.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct PhysicsTimer {
+    /// Simulated seconds per tick
+    pub timestep: f32,
+    /// Multiplier applied to frame time before it is accumulated
+    pub time_scale: f32,
+    /// Ticks run in a single frame are capped, anything beyond that is dropped
+    pub max_ticks_per_frame: u32,
+    pub paused: bool,
+    accumulator: f32,
+    step_requested: bool,
+    ticks_this_frame: u32,
+    tick: u64,
+}
+
+impl PhysicsTimer {
+    pub fn new(timestep: f32) -> Self {
+        Self {
+            timestep,
+            time_scale: 1.0,
+            max_ticks_per_frame: 8,
+            paused: false,
+            accumulator: 0.0,
+            step_requested: false,
+            ticks_this_frame: 0,
+            tick: 0,
+        }
+    }
+
+    /// Number of physics ticks run since startup
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Number of physics ticks the render graph runs this frame
+    pub fn ticks_this_frame(&self) -> u32 {
+        self.ticks_this_frame
+    }
+
+    /// Pauses the simulation and runs exactly one tick on the next frame
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.step_requested = true;
+    }
+
+    /// Moves the tick counter back to a restored snapshot, dropping any ticks planned for this frame
+    pub fn rewind_to(&mut self, tick: u64) {
+        self.tick = tick;
+        self.accumulator = 0.0;
+        self.ticks_this_frame = 0;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Replaces the number of ticks planned for this frame, for replaying a recording tick for tick
+    pub fn override_ticks(&mut self, ticks: u32) {
+        self.tick = self.tick - self.ticks_this_frame as u64 + ticks as u64;
+        self.ticks_this_frame = ticks;
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    }
+
+    pub fn time_scale_range() -> std::ops::RangeInclusive<f32> {
+        MIN_TIME_SCALE..=MAX_TIME_SCALE
+    }
+
+    /// Accumulates a frame's worth of time and works out how many ticks to run for it
+    pub fn advance(&mut self, delta_seconds: f32) {
+        let mut ticks = 0;
+        if self.paused {
+            self.accumulator = 0.0;
+            if self.step_requested {
+                ticks = 1;
+            }
+        } else {
+            self.accumulator += delta_seconds * self.time_scale;
+            ticks = (self.accumulator / self.timestep) as u32;
+            self.accumulator -= ticks as f32 * self.timestep;
+            ticks = ticks.min(self.max_ticks_per_frame);
+        }
+
+        self.step_requested = false;
+        self.ticks_this_frame = ticks;
+        self.tick += ticks as u64;
+    }
+}
+
+/// Key configuration for the simulation controls
+#[derive(Resource)]
+pub struct PhysicsKeyBindings {
+    pub toggle_pause: KeyCode,
+    pub step: KeyCode,
+    pub speed_up: KeyCode,
+    pub slow_down: KeyCode,
+}
+
+impl Default for PhysicsKeyBindings {
+    fn default() -> Self {
+        Self {
+            toggle_pause: KeyCode::P,
+            step: KeyCode::Period,
+            speed_up: KeyCode::RBracket,
+            slow_down: KeyCode::LBracket,
+        }
+    }
+}
+
+pub(super) fn physics_controls(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<PhysicsKeyBindings>,
+    mut physics_timer: ResMut<PhysicsTimer>,
+) {
+    if keys.just_pressed(key_bindings.toggle_pause) {
+        physics_timer.toggle_pause();
+    }
+    if keys.just_pressed(key_bindings.step) {
+        physics_timer.step();
+    }
+    if keys.just_pressed(key_bindings.speed_up) {
+        let time_scale = physics_timer.time_scale * 2.0;
+        physics_timer.set_time_scale(time_scale);
+    }
+    if keys.just_pressed(key_bindings.slow_down) {
+        let time_scale = physics_timer.time_scale * 0.5;
+        physics_timer.set_time_scale(time_scale);
+    }
+}
+
+pub(crate) fn update_physics_timer(
+    mut physics_timer: ResMut<PhysicsTimer>,
+    mut voxel_grid_index: ResMut<VoxelGridIndex>,
+    time: Res<Time>
+) {
+    // Every tick of the last frame swapped the buffers, so an odd count leaves the other one as the newest
+    if physics_timer.ticks_this_frame() % 2 == 1 {
+        voxel_grid_index.swap();
+    }
+
+    physics_timer.advance(time.delta_seconds());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catches_up_at_low_fps() {
+        let mut timer = PhysicsTimer::new(0.1);
+        timer.advance(0.35);
+        assert_eq!(timer.ticks_this_frame(), 3);
+        timer.advance(0.06);
+        assert_eq!(timer.ticks_this_frame(), 1);
+        assert_eq!(timer.tick(), 4);
+    }
+
+    #[test]
+    fn drops_ticks_beyond_cap() {
+        let mut timer = PhysicsTimer::new(0.1);
+        timer.max_ticks_per_frame = 2;
+        timer.advance(1.05);
+        assert_eq!(timer.ticks_this_frame(), 2);
+        timer.advance(0.0);
+        assert_eq!(timer.ticks_this_frame(), 0);
+    }
+
+    #[test]
+    fn steps_while_paused() {
+        let mut timer = PhysicsTimer::new(0.1);
+        timer.toggle_pause();
+        timer.advance(1.0);
+        assert_eq!(timer.ticks_this_frame(), 0);
+
+        timer.step();
+        timer.advance(1.0);
+        assert_eq!(timer.ticks_this_frame(), 1);
+        timer.advance(1.0);
+        assert_eq!(timer.ticks_this_frame(), 0);
+        assert_eq!(timer.tick(), 1);
+    }
+
+    #[test]
+    fn time_scale_speeds_up_ticks() {
+        let mut timer = PhysicsTimer::new(0.1);
+        timer.set_time_scale(2.0);
+        timer.advance(0.26);
+        assert_eq!(timer.ticks_this_frame(), 5);
+    }
+}
@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+use crate::voxel::VoxelGrid;
+use crate::voxel::query::{raycast, Hit};
+use super::mirror::VoxelGridMirror;
+use super::PlayerData;
+
+/// Picks further away than this are misses. Bevy's perspective projection has no far plane,
+/// so this only bounds the raycast, well past the far side of the grid.
+const MAX_PICK_DISTANCE: f32 = 1000.0;
+
+/// What the player is pointing at: the crosshair while the cursor is grabbed, the mouse cursor otherwise.
+/// The ray is cast on the CPU against the [`VoxelGridMirror`], so the hit can lag the screen slightly.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct VoxelPick {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    /// Hit position, face normal, distance and material of the voxel picked
+    pub hit: Option<Hit>,
+}
+
+/// The world space ray through a point on the screen in normalized device coordinates, like the one `raytrace.wgsl` casts.
+/// It starts on the near plane; Bevy's projections have depth 1 there and 0 at infinity, so it aims at a depth in between.
+pub(crate) fn screen_ray(player_data: &PlayerData, ndc: Vec2) -> (Vec3, Vec3) {
+    let unproject = player_data.camera_matrix * player_data.inverse_projection_matrix;
+    let start = unproject * ndc.extend(1.0).extend(1.0);
+    let end = unproject * ndc.extend(0.5).extend(1.0);
+    let start = start.truncate() / start.w;
+    let end = end.truncate() / end.w;
+    (start, (end - start).normalize_or_zero())
+}
+
+pub(super) fn update_voxel_pick(
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    player_data: Res<PlayerData>,
+    mirror: Res<VoxelGridMirror>,
+    mut pick: ResMut<VoxelPick>,
+) {
+    let Ok(window) = primary_window.get_single() else {
+        return;
+    };
+    let ndc = match window.cursor.grab_mode {
+        CursorGrabMode::None => window.cursor_position().map(|position| {
+            position / Vec2::new(window.width(), window.height()) * 2.0 - Vec2::ONE
+        }),
+        _ => Some(Vec2::ZERO),
+    };
+
+    let Some(ndc) = ndc else {
+        pick.hit = None;
+        return;
+    };
+    let (origin, direction) = screen_ray(&player_data, ndc);
+    pick.origin = origin;
+    pick.direction = direction;
+    pick.hit = mirror.grid().and_then(|grid| pick_voxel(grid, origin, direction));
+}
+
+/// Casts a world space ray into the grid, wherever it is placed. The hit is in grid positions, like the raycast selection.
+fn pick_voxel(grid: &VoxelGrid, origin: Vec3, direction: Vec3) -> Option<Hit> {
+    raycast(grid, origin - grid.pos, direction, MAX_PICK_DISTANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::camera::CameraProjection;
+
+    use crate::voxel::{Voxel, VOXEL_TYPE_STONE};
+
+    use super::*;
+
+    #[test]
+    fn screen_rays_follow_the_camera() {
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0).looking_at(Vec3::new(1.0, 2.0, -10.0), Vec3::Y);
+        let projection = PerspectiveProjection { near: 0.5, ..default() };
+        let player_data = PlayerData {
+            camera_matrix: transform.compute_matrix(),
+            inverse_projection_matrix: projection.get_projection_matrix().inverse(),
+            ..default()
+        };
+
+        let (origin, direction) = screen_ray(&player_data, Vec2::ZERO);
+        assert!(origin.abs_diff_eq(Vec3::new(1.0, 2.0, 2.5), 1e-4));
+        assert!(direction.abs_diff_eq(Vec3::NEG_Z, 1e-4));
+
+        let (_, right) = screen_ray(&player_data, Vec2::X);
+        assert!(right.x > 0.0 && right.z < 0.0);
+    }
+
+    #[test]
+    fn picks_in_a_moved_grid() {
+        let mut grid = VoxelGrid::new(8, Vec3::new(100.0, -20.0, 4.0));
+        *grid.get_mut(3, 2, 5).unwrap() = Voxel::material(Vec3::splat(0.5), VOXEL_TYPE_STONE);
+
+        let hit = pick_voxel(&grid, Vec3::new(96.0, -17.5, 9.5), Vec3::X).unwrap();
+        assert_eq!(hit.position, IVec3::new(3, 2, 5));
+        assert_eq!(hit.normal, IVec3::NEG_X);
+        assert!((hit.distance - 7.0).abs() < 1e-5);
+        assert!(pick_voxel(&grid, Vec3::new(-4.0, 2.5, 5.5), Vec3::X).is_none());
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_resource::{encase, Buffer, BufferDescriptor, BufferUsages, MapMode};
+use bevy::render::renderer::RenderDevice;
+
+use crate::voxel::{Voxel, VoxelGrid};
+use super::physics::PhysicsTimer;
+use super::VoxelGridStorage;
+
+/// Asks for the newest GPU voxel grid to be copied back to the CPU at the end of this frame.
+/// The result arrives a frame or two later as a [`VoxelGridReadback`] event.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct VoxelGridReadbackRequest {
+    requested: bool,
+    result: Arc<Mutex<Option<VoxelGridReadback>>>,
+}
+
+impl VoxelGridReadbackRequest {
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested
+    }
+}
+
+/// A copy of the GPU voxel grid, as it was after the given physics tick
+#[derive(Clone, Debug)]
+pub struct VoxelGridReadback {
+    pub tick: u64,
+    pub grid: Arc<VoxelGrid>,
+}
+
+/// Asks for the selected voxel, its value and face normal in the grid header, as they were at the end of the given frame.
+/// Results arrive as [`SelectionReadback`] events, in frame order.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct SelectionReadbackRequest {
+    frame: Option<u64>,
+    results: Arc<Mutex<Vec<SelectionReadback>>>,
+}
+
+impl SelectionReadbackRequest {
+    pub fn request(&mut self, frame: u64) {
+        self.frame = Some(frame);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.frame.is_some()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SelectionReadback {
+    pub frame: u64,
+    pub selected: Vec3,
+    pub normal: Vec3,
+    pub voxel: Voxel,
+}
+
+/// Size of the `VoxelGrid` fields laid out before the voxel array
+pub(super) const VOXEL_GRID_HEADER_SIZE: u64 = 64;
+
+/// Staging buffers one kind of readback is copied through, mapped without stalling the frame.
+/// The node copies into the buffer taken for this frame, which is mapped once the frame has been submitted;
+/// wgpu finishes mapping it during a later submit, so its contents are picked up a frame or more later.
+/// Every readback in flight holds its own buffer, finished ones are reused.
+pub(super) struct StagingReadbacks<T> {
+    label: &'static str,
+    size: u64,
+    idle: Vec<Buffer>,
+    // Taken for this frame's copy, with what is being read back
+    current: Option<(Buffer, T)>,
+    // Waiting for wgpu to map them, oldest first
+    in_flight: VecDeque<Mapping<T>>,
+}
+
+struct Mapping<T> {
+    buffer: Buffer,
+    tag: T,
+    // Whether mapping succeeded, once it is done
+    done: Arc<Mutex<Option<bool>>>,
+}
+
+impl<T> StagingReadbacks<T> {
+    pub fn new(label: &'static str, size: u64) -> Self {
+        Self {
+            label,
+            size,
+            idle: Vec::new(),
+            current: None,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    /// Takes a buffer for the node to copy into this frame
+    pub fn take(&mut self, render_device: &RenderDevice, tag: T) {
+        let buffer = self.idle.pop().unwrap_or_else(|| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some(self.label),
+                size: self.size,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        self.current = Some((buffer, tag));
+    }
+
+    /// The buffer to copy into this frame, if a readback was asked for
+    pub fn target(&self) -> Option<&Buffer> {
+        self.current.as_ref().map(|(buffer, _)| buffer)
+    }
+
+    /// Starts mapping this frame's buffer, once the copy into it has been submitted
+    pub fn map(&mut self, render_device: &RenderDevice) {
+        let Some((buffer, tag)) = self.current.take() else {
+            return;
+        };
+        let done = Arc::new(Mutex::new(None));
+        let callback_done = done.clone();
+        render_device.map_buffer(&buffer.slice(..), MapMode::Read, move |result| {
+            *callback_done.lock().unwrap() = Some(result.is_ok());
+        });
+        self.in_flight.push_back(Mapping { buffer, tag, done });
+    }
+
+    /// Contents of the buffers wgpu has finished mapping, in the order they were copied into
+    pub fn finished(&mut self) -> Vec<(T, Vec<u8>)> {
+        let mut finished = Vec::new();
+        while let Some(mapping) = self.in_flight.front() {
+            let Some(mapped) = *mapping.done.lock().unwrap() else {
+                break;
+            };
+            let Mapping { buffer, tag, .. } = self.in_flight.pop_front().unwrap();
+            if mapped {
+                finished.push((tag, buffer.slice(..).get_mapped_range().to_vec()));
+                buffer.unmap();
+            }
+            self.idle.push(buffer);
+        }
+        finished
+    }
+}
+
+/// Staging buffers the node copies the grid into, tagged with the physics tick it was copied after
+#[derive(Resource)]
+pub(super) struct ReadbackBuffer(pub StagingReadbacks<u64>);
+
+/// Staging buffers for just the header of the grid, tagged with the frame it was copied at the end of
+#[derive(Resource)]
+pub(super) struct SelectionReadbackBuffer(pub StagingReadbacks<u64>);
+
+impl Default for SelectionReadbackBuffer {
+    fn default() -> Self {
+        Self(StagingReadbacks::new("voxel_grid_selection_readback", VOXEL_GRID_HEADER_SIZE))
+    }
+}
+
+pub(super) fn clear_voxel_grid_readback_request(
+    mut request: ResMut<VoxelGridReadbackRequest>,
+    mut selection_request: ResMut<SelectionReadbackRequest>,
+) {
+    request.requested = false;
+    selection_request.frame = None;
+}
+
+/// Forwards finished readbacks from the render world as events
+pub(super) fn receive_voxel_grid_readback(
+    request: Res<VoxelGridReadbackRequest>,
+    mut readback_events: EventWriter<VoxelGridReadback>,
+) {
+    if let Some(readback) = request.result.lock().unwrap().take() {
+        readback_events.send(readback);
+    }
+}
+
+pub(super) fn receive_selection_readback(
+    request: Res<SelectionReadbackRequest>,
+    mut readback_events: EventWriter<SelectionReadback>,
+) {
+    readback_events.send_batch(request.results.lock().unwrap().drain(..));
+}
+
+pub(super) fn prepare_readback_buffer(
+    mut commands: Commands,
+    request: Res<VoxelGridReadbackRequest>,
+    physics_timer: Res<PhysicsTimer>,
+    readback_buffer: Option<ResMut<ReadbackBuffer>>,
+    voxel_grid: Res<VoxelGridStorage>,
+    render_device: Res<RenderDevice>,
+) {
+    if let Some(mut readback_buffer) = readback_buffer {
+        if request.is_requested() {
+            readback_buffer.0.take(&render_device, physics_timer.tick());
+        }
+        return;
+    }
+    if let Some(buffer) = voxel_grid.0[0].buffer() {
+        commands.insert_resource(ReadbackBuffer(StagingReadbacks::new("voxel_grid_readback", buffer.size())));
+    }
+}
+
+pub(super) fn prepare_selection_readback_buffer(
+    request: Res<SelectionReadbackRequest>,
+    mut readback_buffer: ResMut<SelectionReadbackBuffer>,
+    render_device: Res<RenderDevice>,
+) {
+    if let Some(frame) = request.frame {
+        readback_buffer.0.take(&render_device, frame);
+    }
+}
+
+/// Runs after the frame was submitted. Starts mapping this frame's copy and hands over the grids mapped since,
+/// without waiting for the GPU. Each readback still copies the whole grid, so they should be requested occasionally.
+pub(super) fn map_readback_buffer(
+    request: Res<VoxelGridReadbackRequest>,
+    readback_buffer: Option<ResMut<ReadbackBuffer>>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(mut readback_buffer) = readback_buffer else {
+        return;
+    };
+
+    readback_buffer.0.map(&render_device);
+    for (tick, data) in readback_buffer.0.finished() {
+        match encase::StorageBuffer::new(&data[..]).create::<VoxelGrid>() {
+            Ok(grid) => {
+                *request.result.lock().unwrap() = Some(VoxelGridReadback {
+                    tick,
+                    grid: Arc::new(grid),
+                });
+            }
+            Err(error) => warn!("Failed to read back the voxel grid: {error}"),
+        }
+    }
+}
+
+pub(super) fn map_selection_readback_buffer(
+    request: Res<SelectionReadbackRequest>,
+    mut readback_buffer: ResMut<SelectionReadbackBuffer>,
+    render_device: Res<RenderDevice>,
+) {
+    readback_buffer.0.map(&render_device);
+    for (frame, data) in readback_buffer.0.finished() {
+        let read_vec3 = |offset: usize| {
+            let component = |i: usize| {
+                let start = offset + i * 4;
+                f32::from_le_bytes(data[start..start + 4].try_into().unwrap())
+            };
+            Vec3::new(component(0), component(1), component(2))
+        };
+        request.results.lock().unwrap().push(SelectionReadback {
+            frame,
+            selected: read_vec3(32),
+            normal: read_vec3(48),
+            voxel: Voxel::new(u32::from_le_bytes(data[60..64].try_into().unwrap())),
+        });
+    }
+}
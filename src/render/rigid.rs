@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use crate::voxel::{Voxel, VoxelGrid};
+use crate::voxel::query::VoxelVolume;
+use crate::voxel::rigid::{detached_islands, RigidVoxelBody, MAX_ISLAND_SIZE};
+use super::edits::{VoxelGridEdits, VoxelsEdited};
+use super::mirror::VoxelGridMirror;
+use super::physics::PhysicsTimer;
+use super::readback::{VoxelGridReadback, VoxelGridReadbackRequest};
+
+/// Seconds a voxel written by a rigid body overrides the grids read back, long enough for the mirror to catch up with it
+const WRITTEN_LIFETIME: f32 = 1.0;
+
+/// Bookkeeping for [`RigidVoxelBody`] entities.
+/// Bodies are stamped into the GPU grid as they move, so they render and collide like any other voxels,
+/// but readbacks lag behind those stamps; the voxels written recently are kept here and win over the readback.
+#[derive(Resource, Default)]
+pub struct RigidBodies {
+    // Edited voxels, the readback arriving next frame was taken after they were edited
+    pending: Vec<u32>,
+    // Voxels written by bodies, with the time they were written
+    written: HashMap<IVec3, (Voxel, f32)>,
+}
+
+/// A readback grid with the voxels recently written by bodies on top, and some positions hidden
+struct BodyWorld<'a> {
+    grid: &'a VoxelGrid,
+    written: &'a HashMap<IVec3, (Voxel, f32)>,
+    ignore: &'a HashSet<IVec3>,
+}
+
+impl VoxelVolume for BodyWorld<'_> {
+    fn dim(&self) -> u32 {
+        self.grid.dim()
+    }
+
+    fn voxel(&self, position: IVec3) -> Voxel {
+        if self.ignore.contains(&position) {
+            return Voxel::EMPTY;
+        }
+        match self.written.get(&position) {
+            Some(&(voxel, _)) => voxel,
+            None => self.grid.voxel(position),
+        }
+    }
+}
+
+/// Looks for stone left hanging by edits and cuts it loose as rigid bodies.
+/// Edited voxels wait for the readback of the frame they were edited in, then the islands around them are searched.
+/// Islands stay in the grid as they are, that is where the new bodies are stamped.
+pub(super) fn detach_rigid_islands(
+    mut commands: Commands,
+    mut rigid_bodies: ResMut<RigidBodies>,
+    mut edited_events: EventReader<VoxelsEdited>,
+    mut readback_events: EventReader<VoxelGridReadback>,
+    mut readback_request: ResMut<VoxelGridReadbackRequest>,
+    bodies: Query<&RigidVoxelBody>,
+) {
+    if let Some(readback) = readback_events.iter().last() {
+        let grid = &readback.grid;
+        let seeds: Vec<IVec3> = rigid_bodies.pending.drain(..).map(|index| grid.position(index).as_ivec3()).collect();
+        if !seeds.is_empty() {
+            let body_cells: HashSet<IVec3> = bodies.iter().flat_map(|body| body.cells()).map(|(cell, _)| cell).collect();
+            let world = BodyWorld { grid, written: &rigid_bodies.written, ignore: &body_cells };
+            for island in detached_islands(&world, seeds, &body_cells, MAX_ISLAND_SIZE) {
+                commands.spawn(RigidVoxelBody::from_island(&world, &island));
+            }
+        }
+    }
+
+    for event in edited_events.iter() {
+        rigid_bodies.pending.extend_from_slice(&event.0);
+    }
+    if !rigid_bodies.pending.is_empty() {
+        readback_request.request();
+    }
+}
+
+/// Moves the bodies by the physics ticks of this frame and stamps them into the grid where they moved to.
+/// Bodies that have come to rest are despawned, leaving their voxels behind as part of the world.
+pub(crate) fn step_rigid_bodies(
+    mut commands: Commands,
+    time: Res<Time>,
+    physics_timer: Res<PhysicsTimer>,
+    mirror: Res<VoxelGridMirror>,
+    mut rigid_bodies: ResMut<RigidBodies>,
+    mut bodies: Query<(Entity, &mut RigidVoxelBody)>,
+    mut edits: ResMut<VoxelGridEdits>,
+) {
+    let now = time.elapsed_seconds();
+    rigid_bodies.written.retain(|_, (_, written)| now - *written < WRITTEN_LIFETIME);
+
+    let Some(grid) = mirror.grid() else {
+        return;
+    };
+    let ticks = physics_timer.ticks_this_frame();
+    if ticks == 0 {
+        return;
+    }
+
+    for (entity, mut body) in bodies.iter_mut() {
+        let old = body.cells();
+        let own_cells: HashSet<IVec3> = old.iter().map(|&(cell, _)| cell).collect();
+        let world = BodyWorld { grid, written: &rigid_bodies.written, ignore: &own_cells };
+        let mut resting = false;
+        for _ in 0..ticks {
+            resting = body.step(&world, physics_timer.timestep);
+            if resting {
+                break;
+            }
+        }
+
+        for (cell, voxel) in body.moved_cells(&old) {
+            if grid.in_bounds(cell.x, cell.y, cell.z) {
+                let position = cell.as_uvec3();
+                edits.set(grid.index(position.x, position.y, position.z), voxel);
+                rigid_bodies.written.insert(cell, (voxel, now));
+            }
+        }
+
+        if resting {
+            commands.entity(entity).despawn();
+        }
+    }
+}
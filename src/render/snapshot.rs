@@ -0,0 +1,60 @@
+use bevy::prelude::*;
+
+use crate::voxel::history::SnapshotHistory;
+use super::edits::VoxelGridEdits;
+use super::physics::PhysicsTimer;
+use super::readback::{VoxelGridReadback, VoxelGridReadbackRequest};
+
+/// Rewinds the simulation to the snapshot at `index` in the [`SnapshotHistory`]
+pub struct RewindEvent {
+    pub index: usize,
+}
+
+/// Asks for a readback of the grid whenever the history is due for another snapshot
+pub(crate) fn request_snapshots(
+    history: Res<SnapshotHistory>,
+    physics_timer: Res<PhysicsTimer>,
+    mut readback_request: ResMut<VoxelGridReadbackRequest>,
+    mut last_requested: Local<Option<u64>>,
+) {
+    let tick = physics_timer.tick();
+    // Don't ask again while the last readback is still on its way
+    let in_flight = last_requested.is_some_and(|requested| {
+        requested <= tick && history.latest_tick() < Some(requested) && tick < requested + history.interval
+    });
+    if history.is_due(tick) && !in_flight {
+        readback_request.request();
+        *last_requested = Some(tick);
+    }
+}
+
+pub(super) fn store_snapshots(
+    mut history: ResMut<SnapshotHistory>,
+    physics_timer: Res<PhysicsTimer>,
+    mut readback_events: EventReader<VoxelGridReadback>,
+) {
+    for readback in readback_events.iter() {
+        // Readbacks still in flight during a rewind belong to the discarded timeline
+        if readback.tick > physics_timer.tick() || !history.is_due(readback.tick) {
+            continue;
+        }
+        history.push(readback.tick, &readback.grid);
+    }
+}
+
+pub(super) fn rewind_simulation(
+    mut history: ResMut<SnapshotHistory>,
+    mut physics_timer: ResMut<PhysicsTimer>,
+    mut edits: ResMut<VoxelGridEdits>,
+    mut rewind_events: EventReader<RewindEvent>,
+) {
+    if let Some(rewind) = rewind_events.iter().last() {
+        let Some(snapshot) = history.get(rewind.index) else {
+            return;
+        };
+        let tick = snapshot.tick;
+        edits.replace(snapshot.grid.decompress());
+        physics_timer.rewind_to(tick);
+        history.truncate_after(tick);
+    }
+}
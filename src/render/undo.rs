@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, Buffer, BufferDescriptor, BufferUsages};
+use bevy::render::renderer::RenderDevice;
+
+use crate::voxel::Voxel;
+use crate::voxel::undo::{EditDiff, EditHistory, VoxelChange};
+use super::edits::{VoxelGridEdits, VoxelsEdited};
+use super::readback::StagingReadbacks;
+use super::{ComputePipeline, PlayerData};
+
+/// Most voxel changes a single stroke can record, anything past this can't be undone
+const EDIT_LOG_CAPACITY: u64 = 1 << 18;
+// index, old value and new value
+const EDIT_LOG_ENTRY_SIZE: u64 = 12;
+const EDIT_LOG_SIZE: u64 = 4 + EDIT_LOG_CAPACITY * EDIT_LOG_ENTRY_SIZE;
+
+/// GPU log the brush pass appends its changes to
+#[derive(Resource, Clone, ExtractResource)]
+pub(super) struct EditLog {
+    pub buffer: Buffer,
+}
+
+/// Staging buffers the [`EditLog`] is read back through, in the render world
+#[derive(Resource)]
+pub(super) struct EditLogReadback(pub StagingReadbacks<()>);
+
+impl Default for EditLogReadback {
+    fn default() -> Self {
+        Self(StagingReadbacks::new("edit_log_readback", EDIT_LOG_SIZE))
+    }
+}
+
+/// Clears the log when a stroke starts and reads it back once it ends.
+/// The collapsed stroke arrives a frame or two later and is pushed onto the [`EditHistory`].
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub(super) struct EditLogRequest {
+    pub clear: bool,
+    pub read: bool,
+    result: Arc<Mutex<Option<EditDiff>>>,
+}
+
+#[derive(Resource)]
+pub(super) struct EditLogBindGroup(pub BindGroup);
+
+pub(super) fn setup_edit_log(mut commands: Commands, render_device: Res<RenderDevice>) {
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("edit_log"),
+        size: EDIT_LOG_SIZE,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    commands.insert_resource(EditLog { buffer });
+}
+
+pub(super) fn clear_edit_log_request(mut request: ResMut<EditLogRequest>) {
+    request.clear = false;
+    request.read = false;
+}
+
+/// Strokes follow the buttons the edit pass sees, so replayed input is recorded like live input
+pub(super) fn track_brush_strokes(
+    player_data: Res<PlayerData>,
+    mut request: ResMut<EditLogRequest>,
+    mut was_brushing: Local<bool>,
+) {
+    let brushing = player_data.mouse_click & 0b101 != 0;
+    if brushing && !*was_brushing {
+        request.clear = true;
+    }
+    if !brushing && *was_brushing {
+        request.read = true;
+    }
+    *was_brushing = brushing;
+}
+
+pub(super) fn receive_brush_strokes(
+    request: Res<EditLogRequest>,
+    mut history: ResMut<EditHistory>,
+    mut edited: EventWriter<VoxelsEdited>,
+) {
+    if let Some(diff) = request.result.lock().unwrap().take() {
+        edited.send(VoxelsEdited::from_diff(&diff));
+        history.push(diff);
+    }
+}
+
+/// Ctrl+Z undoes the last stroke, Ctrl+Shift+Z redoes it
+pub(crate) fn undo_controls(
+    keys: Res<Input<KeyCode>>,
+    player_data: Res<PlayerData>,
+    mut history: ResMut<EditHistory>,
+    mut edits: ResMut<VoxelGridEdits>,
+    mut edited: EventWriter<VoxelsEdited>,
+) {
+    let control = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    // The stroke in progress isn't in the history yet
+    if !control || !keys.just_pressed(KeyCode::Z) || player_data.mouse_click & 0b101 != 0 {
+        return;
+    }
+
+    let Some(diff) = (if shift { history.redo() } else { history.undo() }) else {
+        return;
+    };
+    for change in diff.changes() {
+        edits.set(change.index, change.new);
+    }
+    edited.send(VoxelsEdited::from_diff(&diff));
+}
+
+pub(super) fn queue_edit_log_bind_group(
+    mut commands: Commands,
+    pipeline: Res<ComputePipeline>,
+    edit_log: Res<EditLog>,
+    render_device: Res<RenderDevice>,
+) {
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.edit_log_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: edit_log.buffer.as_entire_binding(),
+        }],
+    });
+    commands.insert_resource(EditLogBindGroup(bind_group));
+}
+
+pub(super) fn prepare_edit_log_readback(
+    request: Res<EditLogRequest>,
+    mut readback: ResMut<EditLogReadback>,
+    render_device: Res<RenderDevice>,
+) {
+    if request.read {
+        readback.0.take(&render_device, ());
+    }
+}
+
+/// Runs after the frame was submitted, like the grid readbacks the stroke is picked up once wgpu has mapped it
+pub(super) fn map_edit_log(
+    request: Res<EditLogRequest>,
+    mut readback: ResMut<EditLogReadback>,
+    render_device: Res<RenderDevice>,
+) {
+    readback.0.map(&render_device);
+    for (_, data) in readback.0.finished() {
+        *request.result.lock().unwrap() = Some(decode_edit_log(&data));
+    }
+}
+
+/// Collapses the log entries the brush pass wrote into the stroke's diff
+fn decode_edit_log(data: &[u8]) -> EditDiff {
+    let read_u32 = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let count = read_u32(0) as u64;
+    if count > EDIT_LOG_CAPACITY {
+        warn!("Brush stroke changed {count} voxels, only the first {EDIT_LOG_CAPACITY} can be undone");
+    }
+    let log = (0..count.min(EDIT_LOG_CAPACITY)).map(|i| {
+        let offset = (4 + i * EDIT_LOG_ENTRY_SIZE) as usize;
+        VoxelChange {
+            index: read_u32(offset),
+            old: Voxel::new(read_u32(offset + 4)),
+            new: Voxel::new(read_u32(offset + 8)),
+        }
+    });
+    EditDiff::from_log(log)
+}
@@ -0,0 +1,76 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+use crate::util::flycam::{FlyCam, KeyBindings};
+use crate::voxel::character::{CharacterBody, WalkSettings};
+use super::mirror::VoxelGridMirror;
+
+/// Starts walking from wherever the camera flew to
+pub(super) fn start_walking(
+    settings: Res<WalkSettings>,
+    mut body: ResMut<CharacterBody>,
+    query: Query<&Transform, With<FlyCam>>,
+) {
+    if let Ok(transform) = query.get_single() {
+        *body = CharacterBody::new(transform.translation - Vec3::Y * settings.eye_height);
+    }
+}
+
+/// Keys steering the character, only while the cursor is grabbed
+#[derive(SystemParam)]
+pub(super) struct WalkInput<'w, 's> {
+    keys: Res<'w, Input<KeyCode>>,
+    key_bindings: Res<'w, KeyBindings>,
+    primary_window: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+impl WalkInput<'_, '_> {
+    /// Direction to walk in on the ground relative to the camera, and whether to jump
+    fn read(&self, transform: &Transform) -> (Vec3, bool) {
+        let grabbed = self.primary_window.get_single().is_ok_and(|window| window.cursor.grab_mode != CursorGrabMode::None);
+        if !grabbed {
+            return (Vec3::ZERO, false);
+        }
+
+        let (keys, key_bindings) = (&self.keys, &self.key_bindings);
+        let local_z = transform.local_z();
+        let forward = -Vec3::new(local_z.x, 0., local_z.z).normalize_or_zero();
+        let right = Vec3::new(local_z.z, 0., -local_z.x).normalize_or_zero();
+        let mut walk = Vec3::ZERO;
+        if keys.pressed(key_bindings.move_forward) {
+            walk += forward;
+        }
+        if keys.pressed(key_bindings.move_backward) {
+            walk -= forward;
+        }
+        if keys.pressed(key_bindings.move_left) {
+            walk -= right;
+        }
+        if keys.pressed(key_bindings.move_right) {
+            walk += right;
+        }
+        (walk.normalize_or_zero(), keys.pressed(key_bindings.move_ascend))
+    }
+}
+
+/// Moves the camera as a walking character, colliding against the [`VoxelGridMirror`]
+pub(super) fn walk_player(
+    input: WalkInput,
+    time: Res<Time>,
+    settings: Res<WalkSettings>,
+    mirror: Res<VoxelGridMirror>,
+    mut body: ResMut<CharacterBody>,
+    mut query: Query<&mut Transform, With<FlyCam>>,
+) {
+    let Ok(mut transform) = query.get_single_mut() else {
+        return;
+    };
+    let Some(grid) = mirror.grid() else {
+        return;
+    };
+
+    let (walk, jump) = input.read(&transform);
+    body.update(grid, &settings, walk, jump, time.delta_seconds());
+    transform.translation = body.position + Vec3::Y * settings.eye_height;
+}
@@ -0,0 +1,615 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use crate::render::{PlayerData, VOXEL_GRID_SIZE, request_snapshots, update_physics_timer, update_player_uniform};
+use crate::render::clipboard::run_clipboard_actions;
+use crate::render::edits::VoxelGridEdits;
+use crate::render::physics::PhysicsTimer;
+use crate::render::rigid::step_rigid_bodies;
+use crate::render::readback::{SelectionReadback, SelectionReadbackRequest, VoxelGridReadback, VoxelGridReadbackRequest};
+use crate::render::undo::undo_controls;
+use crate::util::flycam::FlyCam;
+use crate::voxel::{Voxel, VoxelGrid};
+use crate::voxel::generation::{WorldSeed, generate_world};
+use crate::voxel::brush::{Brush, BrushOperation, BrushShape};
+use crate::voxel::simulation::Simulation;
+
+/// Everything the player fed into the simulation during one frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputFrame {
+    /// Physics ticks run during the frame
+    pub ticks: u32,
+    pub camera_matrix: Mat4,
+    /// Inverse projection of the fly camera, with the aspect ratio of the window at the time
+    pub inverse_projection_matrix: Mat4,
+    pub mouse_click: u32,
+    /// The brush as sent to the edit pass, with the operation the mouse buttons resolved to
+    pub brush: Brush,
+    /// Voxel and face normal the raycast left in the grid header at the end of the frame,
+    /// which is what the brush edits on the next frame's ticks
+    pub selected: Vec3,
+    pub normal: Vec3,
+    /// Single voxel edits made on the CPU during the frame, as (flat index, new value),
+    /// by undo and redo, the clipboard and its tools, and rigid bodies moving.
+    /// They are written before the brush and the ticks, like [`VoxelGridEdits`] are.
+    pub edits: Vec<(u32, Voxel)>,
+}
+
+// Ticks, mouse buttons and the five brush settings
+const INTEGER_FIELDS: usize = 7;
+// The integers, then the camera matrix, selection, normal and inverse projection,
+// followed by the edit count and the edits
+const FIXED_FIELDS: usize = INTEGER_FIELDS + 16 + 3 + 3 + 16;
+
+impl InputFrame {
+    fn to_line(&self) -> String {
+        let brush = &self.brush;
+        let integers = [
+            self.ticks,
+            self.mouse_click,
+            brush.radius,
+            brush.shape as u32,
+            brush.operation as u32,
+            brush.material,
+            brush.replace_type,
+        ];
+        let mut fields: Vec<String> = integers.iter().map(u32::to_string).collect();
+        let floats = self.camera_matrix.to_cols_array().into_iter()
+            .chain(self.selected.to_array())
+            .chain(self.normal.to_array())
+            .chain(self.inverse_projection_matrix.to_cols_array());
+        // `Display` for floats prints the shortest string that parses back to the same value
+        fields.extend(floats.map(|value| value.to_string()));
+        fields.push(self.edits.len().to_string());
+        for (index, voxel) in &self.edits {
+            fields.push(index.to_string());
+            fields.push(voxel.value().to_string());
+        }
+        fields.join(" ")
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() <= FIXED_FIELDS {
+            return None;
+        }
+        let integers = fields[..INTEGER_FIELDS].iter()
+            .map(|field| field.parse::<u32>().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        let floats = fields[INTEGER_FIELDS..FIXED_FIELDS].iter()
+            .map(|field| field.parse::<f32>().ok())
+            .collect::<Option<Vec<f32>>>()?;
+        let edit_count: usize = fields[FIXED_FIELDS].parse().ok()?;
+        let edit_fields = &fields[FIXED_FIELDS + 1..];
+        if edit_fields.len() != edit_count * 2 {
+            return None;
+        }
+        let edits = edit_fields.chunks(2)
+            .map(|pair| Some((pair[0].parse::<u32>().ok()?, Voxel::new(pair[1].parse().ok()?))))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            ticks: integers[0],
+            mouse_click: integers[1],
+            brush: Brush {
+                radius: integers[2],
+                shape: BrushShape::from_index(integers[3])?,
+                operation: BrushOperation::from_index(integers[4])?,
+                material: integers[5],
+                replace_type: integers[6],
+            },
+            camera_matrix: Mat4::from_cols_slice(&floats[0..16]),
+            selected: Vec3::from_slice(&floats[16..19]),
+            normal: Vec3::from_slice(&floats[19..22]),
+            inverse_projection_matrix: Mat4::from_cols_slice(&floats[22..38]),
+            edits,
+        })
+    }
+}
+
+/// A recorded session: the world seed followed by one line of input per frame
+#[derive(Clone, Debug, Default)]
+pub struct Replay {
+    pub seed: u64,
+    pub frames: Vec<InputFrame>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines.next().ok_or_else(|| invalid("empty replay file".to_string()))??;
+        let seed = header.strip_prefix("seed ")
+            .and_then(|seed| seed.trim().parse().ok())
+            .ok_or_else(|| invalid(format!("bad replay header: {header}")))?;
+
+        let mut frames = Vec::new();
+        for (number, line) in lines.enumerate() {
+            let line = line?;
+            let frame = InputFrame::parse(&line).ok_or_else(|| invalid(format!("bad replay frame on line {}", number + 2)))?;
+            frames.push(frame);
+        }
+
+        Ok(Self { seed, frames })
+    }
+}
+
+/// Runs a replay on the CPU simulation, without a window or GPU, and returns the final grid.
+/// Each frame writes its CPU edits, applies the brush around the selection the previous frame's raycast left behind,
+/// then runs its ticks, in the same order as the render graph.
+pub fn run_headless(replay: &Replay) -> VoxelGrid {
+    let grid = generate_world(VOXEL_GRID_SIZE, replay.seed);
+    let (mut selected, mut normal) = (grid.selected(), grid.normal());
+    let mut simulation = Simulation::new(grid);
+
+    for frame in &replay.frames {
+        for &(index, voxel) in &frame.edits {
+            let position = simulation.grid().position(index);
+            simulation.set(position.x, position.y, position.z, voxel);
+        }
+        if frame.mouse_click & 0b101 != 0 {
+            simulation.apply_brush(&frame.brush, selected, normal);
+        }
+        for _ in 0..frame.ticks {
+            simulation.step();
+        }
+        (selected, normal) = (frame.selected, frame.normal);
+    }
+
+    simulation.grid().clone()
+}
+
+/// Command line options for recording and replaying sessions
+#[derive(Clone, Debug, Default)]
+pub struct ReplayOptions {
+    pub seed: Option<u64>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    /// Run `replay` on the CPU and exit instead of opening a window
+    pub headless: bool,
+}
+
+impl ReplayOptions {
+    /// Parses `--seed <n>`, `--record <file>`, `--replay <file>` and `--headless`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = Some(seed.parse().map_err(|_| format!("invalid seed: {seed}"))?);
+                }
+                "--record" => options.record = Some(value()?.into()),
+                "--replay" => options.replay = Some(value()?.into()),
+                "--headless" => options.headless = true,
+                _ => return Err(format!("unknown argument: {arg}")),
+            }
+        }
+
+        if options.headless && options.replay.is_none() {
+            return Err("--headless needs --replay".to_string());
+        }
+        if options.record.is_some() && options.replay.is_some() {
+            return Err("--record and --replay can't be used together".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// Writes the player's input to a replay file as the session runs.
+/// Every frame reads the selection back from the GPU, frames are written once theirs arrives a frame or two later.
+/// Rewinding while recording isn't captured, the replay would diverge from that point.
+#[derive(Resource)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+    frame: u64,
+    // Frames still waiting for their selection readback
+    pending: VecDeque<(u64, InputFrame)>,
+    last_selection: (Vec3, Vec3),
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>, seed: u64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "seed {seed}")?;
+        Ok(Self {
+            writer,
+            frame: 0,
+            pending: VecDeque::new(),
+            last_selection: (Vec3::splat(-1.0), Vec3::ZERO),
+        })
+    }
+
+    fn write_frame(&mut self, frame: &InputFrame) {
+        if let Err(error) = writeln!(self.writer, "{}", frame.to_line()) {
+            warn!("Failed to write replay frame: {error}");
+        }
+    }
+}
+
+/// Feeds a loaded replay into the simulation in place of the player's input
+#[derive(Resource)]
+pub struct Playback {
+    frames: Vec<InputFrame>,
+    next: usize,
+    finished: bool,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            frames: replay.frames,
+            next: 0,
+            finished: false,
+        }
+    }
+}
+
+fn record_inputs(
+    mut recorder: ResMut<Recorder>,
+    player_data: Res<PlayerData>,
+    physics_timer: Res<PhysicsTimer>,
+    edits: Res<VoxelGridEdits>,
+    mut selection_request: ResMut<SelectionReadbackRequest>,
+) {
+    let frame = InputFrame {
+        ticks: physics_timer.ticks_this_frame(),
+        camera_matrix: player_data.camera_matrix,
+        inverse_projection_matrix: player_data.inverse_projection_matrix,
+        mouse_click: player_data.mouse_click,
+        brush: Brush {
+            shape: BrushShape::from_index(player_data.brush_shape).unwrap_or_default(),
+            operation: BrushOperation::from_index(player_data.brush_operation).unwrap_or_default(),
+            radius: player_data.brush_size,
+            material: player_data.brush_material,
+            replace_type: player_data.brush_replace_type,
+        },
+        selected: Vec3::splat(-1.0),
+        normal: Vec3::ZERO,
+        edits: edits.voxels().to_vec(),
+    };
+    let number = recorder.frame;
+    recorder.pending.push_back((number, frame));
+    recorder.frame += 1;
+    selection_request.request(number);
+}
+
+fn write_recorded_frames(
+    mut recorder: ResMut<Recorder>,
+    mut selection_events: EventReader<SelectionReadback>,
+) {
+    for selection in selection_events.iter() {
+        // A frame whose readback went missing keeps the last known selection, rather than dropping its ticks
+        while let Some((number, mut frame)) = recorder.pending.pop_front() {
+            if number == selection.frame {
+                recorder.last_selection = (selection.selected, selection.normal);
+            }
+            (frame.selected, frame.normal) = recorder.last_selection;
+            recorder.write_frame(&frame);
+            if number >= selection.frame {
+                break;
+            }
+        }
+    }
+
+    if let Err(error) = recorder.writer.flush() {
+        warn!("Failed to write replay: {error}");
+    }
+}
+
+fn play_inputs(
+    mut playback: ResMut<Playback>,
+    mut player_data: ResMut<PlayerData>,
+    mut physics_timer: ResMut<PhysicsTimer>,
+    mut readback_request: ResMut<VoxelGridReadbackRequest>,
+    mut edits: ResMut<VoxelGridEdits>,
+    mut camera_query: Query<(&mut Transform, &mut Projection), With<FlyCam>>,
+) {
+    let Some(frame) = playback.frames.get(playback.next) else {
+        if !playback.finished {
+            playback.finished = true;
+            physics_timer.paused = true;
+            physics_timer.override_ticks(0);
+            readback_request.request();
+            info!("Replay finished at tick {}", physics_timer.tick());
+        }
+        return;
+    };
+
+    player_data.camera_matrix = frame.camera_matrix;
+    player_data.inverse_projection_matrix = frame.inverse_projection_matrix;
+    player_data.mouse_click = frame.mouse_click;
+    player_data.brush_size = frame.brush.radius;
+    player_data.brush_shape = frame.brush.shape as u32;
+    player_data.brush_operation = frame.brush.operation as u32;
+    player_data.brush_material = frame.brush.material;
+    player_data.brush_replace_type = frame.brush.replace_type;
+    physics_timer.override_ticks(frame.ticks);
+    // Edits made live would land on top of the recorded ones, which already include them
+    edits.clear_voxels();
+    for &(index, voxel) in &frame.edits {
+        edits.set(index, voxel);
+    }
+    if let Ok((mut transform, mut projection)) = camera_query.get_single_mut() {
+        *transform = Transform::from_matrix(frame.camera_matrix);
+        // The meshes composited with the voxels follow the recorded field of view and near plane,
+        // the aspect ratio stays with the window
+        if let Projection::Perspective(perspective) = &mut *projection {
+            let projection_matrix = frame.inverse_projection_matrix.inverse();
+            perspective.fov = 2.0 * (1.0 / projection_matrix.y_axis.y).atan();
+            perspective.near = projection_matrix.w_axis.z;
+        }
+    }
+    playback.next += 1;
+}
+
+fn report_replay_result(
+    playback: Res<Playback>,
+    mut readback_events: EventReader<VoxelGridReadback>,
+) {
+    for readback in readback_events.iter() {
+        if playback.finished {
+            info!("Replay result: tick {}, checksum {:016x}", readback.tick, readback.grid.checksum());
+        }
+    }
+}
+
+/// Records or replays the session depending on the [`ReplayOptions`] it was created with
+pub struct ReplayPlugin {
+    pub options: ReplayOptions,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(path) = &self.options.replay {
+            let replay = Replay::load(path).unwrap_or_else(|error| panic!("Failed to load replay {}: {error}", path.display()));
+            app.insert_resource(WorldSeed(replay.seed));
+            app.insert_resource(Playback::new(replay));
+        } else if let Some(path) = &self.options.record {
+            let seed = self.options.seed.map(WorldSeed).unwrap_or_default();
+            let recorder = Recorder::create(path, seed.0).unwrap_or_else(|error| panic!("Failed to create recording {}: {error}", path.display()));
+            app.insert_resource(seed);
+            app.insert_resource(recorder);
+        } else if let Some(seed) = self.options.seed {
+            app.insert_resource(WorldSeed(seed));
+        }
+
+        app.add_system(
+            record_inputs
+                .run_if(resource_exists::<Recorder>())
+                .after(update_player_uniform)
+                .after(update_physics_timer)
+                .after(undo_controls)
+                .after(run_clipboard_actions)
+                .after(step_rigid_bodies),
+        );
+        app.add_system(write_recorded_frames.run_if(resource_exists::<Recorder>()));
+        app.add_system(
+            play_inputs
+                .run_if(resource_exists::<Playback>())
+                .after(update_player_uniform)
+                .after(update_physics_timer)
+                .after(undo_controls)
+                .after(run_clipboard_actions)
+                .after(step_rigid_bodies)
+                .before(request_snapshots),
+        );
+        app.add_system(report_replay_result.run_if(resource_exists::<Playback>()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::{VOXEL_TYPE_GLASS, VOXEL_TYPE_STONE};
+    use crate::voxel::prefab::Prefab;
+    use crate::voxel::rigid::RigidVoxelBody;
+    use crate::voxel::tools;
+    use super::*;
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = InputFrame {
+            ticks: 2,
+            camera_matrix: Mat4::from_rotation_y(0.3) * Mat4::from_translation(Vec3::new(1.1, 64.0, -0.7)),
+            inverse_projection_matrix: Mat4::perspective_infinite_reverse_rh(1.2, 1.6, 0.1).inverse(),
+            mouse_click: 5,
+            brush: Brush { shape: BrushShape::Cylinder, operation: BrushOperation::Paint, ..Default::default() },
+            selected: Vec3::new(12.0, 40.0, 7.0),
+            normal: Vec3::NEG_Z,
+            edits: vec![(5, Voxel::new(0x1234_5601)), (70_000, Voxel::EMPTY)],
+        };
+        assert_eq!(InputFrame::parse(&frame.to_line()), Some(frame.clone()));
+
+        let no_edits = InputFrame { edits: Vec::new(), ..frame };
+        assert_eq!(InputFrame::parse(&no_edits.to_line()), Some(no_edits));
+    }
+
+    /// A frame that runs no ticks and leaves the selection on `selected`
+    fn still_frame(selected: Vec3) -> InputFrame {
+        InputFrame {
+            ticks: 0,
+            camera_matrix: Mat4::IDENTITY,
+            inverse_projection_matrix: Mat4::IDENTITY,
+            mouse_click: 0,
+            brush: Brush::default(),
+            selected,
+            normal: Vec3::Y,
+            edits: Vec::new(),
+        }
+    }
+
+    /// Voxels of `after` that differ from `before`, as edits that would make `before` match
+    fn changed_voxels(before: &VoxelGrid, after: &VoxelGrid) -> Vec<(u32, Voxel)> {
+        let dim = before.dim();
+        (0..dim * dim * dim)
+            .filter_map(|index| {
+                let position = before.position(index);
+                let voxel = *after.get(position.x, position.y, position.z)?;
+                (before.get(position.x, position.y, position.z) != Some(&voxel)).then_some((index, voxel))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_physics_after_strokes() {
+        let seed = 13;
+        let selected = Vec3::new(64.0, 100.0, 64.0);
+        let mut stroke = still_frame(selected);
+        stroke.mouse_click = 1;
+        stroke.brush.operation = BrushOperation::Remove;
+        stroke.ticks = 2;
+        let mut frames = vec![still_frame(selected), stroke];
+        for _ in 0..10 {
+            let mut frame = still_frame(selected);
+            frame.ticks = 3;
+            frames.push(InputFrame::parse(&frame.to_line()).unwrap());
+        }
+        let replay = Replay { seed, frames };
+        let replayed = run_headless(&replay);
+        assert_eq!(run_headless(&replay).checksum(), replayed.checksum());
+
+        // The sand above the hole falls in while the ticks run
+        let mut simulation = Simulation::new(generate_world(VOXEL_GRID_SIZE, seed));
+        simulation.apply_brush(&replay.frames[1].brush, selected, Vec3::Y);
+        let carved = simulation.grid().checksum();
+        for _ in 0..2 + 10 * 3 {
+            simulation.step();
+        }
+        assert_ne!(simulation.grid().checksum(), carved);
+        assert_eq!(replayed.checksum(), simulation.grid().checksum());
+    }
+
+    #[test]
+    fn replays_undone_strokes() {
+        let seed = 7;
+        let world = generate_world(VOXEL_GRID_SIZE, seed);
+        let selected = Vec3::new(64.0, 120.0, 64.0);
+        let mut stroke = still_frame(selected);
+        stroke.mouse_click = 1;
+        stroke.brush.operation = BrushOperation::Remove;
+        let mut replay = Replay { seed, frames: vec![still_frame(selected), stroke] };
+        let stroked = run_headless(&replay);
+        assert_ne!(stroked.checksum(), world.checksum());
+
+        // Undoing writes the old voxels back on the CPU
+        let mut undo = still_frame(selected);
+        undo.edits = changed_voxels(&stroked, &world);
+        replay.frames.push(InputFrame::parse(&undo.to_line()).unwrap());
+        assert_eq!(run_headless(&replay).checksum(), world.checksum());
+    }
+
+    #[test]
+    fn replays_pastes() {
+        let seed = 3;
+        let world = generate_world(VOXEL_GRID_SIZE, seed);
+        let prefab = Prefab::copy(&world, IVec3::new(10, 10, 10), IVec3::new(13, 13, 13));
+        let placements = prefab.placements(IVec3::new(64, 121, 64), false);
+
+        let mut pasted = world.clone();
+        let mut paste = still_frame(Vec3::splat(-1.0));
+        for (position, voxel) in placements {
+            let position = position.as_uvec3();
+            *pasted.get_mut(position.x, position.y, position.z).unwrap() = voxel;
+            paste.edits.push((world.index(position.x, position.y, position.z), voxel));
+        }
+        assert_ne!(pasted.checksum(), world.checksum());
+
+        let replay = Replay { seed, frames: vec![paste] };
+        assert_eq!(run_headless(&replay).checksum(), pasted.checksum());
+    }
+
+    #[test]
+    fn replays_tools() {
+        let seed = 5;
+        let world = generate_world(VOXEL_GRID_SIZE, seed);
+        let glass = Voxel::material(Vec3::splat(0.9), VOXEL_TYPE_GLASS);
+        let diffs = [
+            tools::line(&world, IVec3::new(4, 100, 4), IVec3::new(40, 120, 30), glass),
+            tools::hollow_box(&world, IVec3::new(60, 60, 60), IVec3::new(70, 66, 64), Voxel::EMPTY),
+        ];
+
+        let mut edited = world.clone();
+        let mut frames = Vec::new();
+        for diff in &diffs {
+            diff.apply(&mut edited);
+            let mut frame = still_frame(Vec3::splat(-1.0));
+            frame.edits = diff.changes().iter().map(|change| (change.index, change.new)).collect();
+            frames.push(frame);
+        }
+        assert_ne!(edited.checksum(), world.checksum());
+
+        let replay = Replay { seed, frames };
+        assert_eq!(run_headless(&replay).checksum(), edited.checksum());
+    }
+
+    #[test]
+    fn replays_rigid_bodies() {
+        let seed = 11;
+        let world = generate_world(VOXEL_GRID_SIZE, seed);
+        let stone = Voxel::material(Vec3::splat(0.5), VOXEL_TYPE_STONE);
+        let index = |cell: IVec3| world.index(cell.x as u32, cell.y as u32, cell.z as u32);
+
+        // A shaft dug into the sand, with a block of stone hanging at the top as if its support was just cut
+        let mut dig = still_frame(Vec3::splat(-1.0));
+        let mut island = Vec::new();
+        for x in 60..68 {
+            for y in 100..VOXEL_GRID_SIZE as i32 {
+                for z in 60..68 {
+                    let cell = IVec3::new(x, y, z);
+                    let in_block = (62..64).contains(&x) && (120..122).contains(&y) && (62..64).contains(&z);
+                    if in_block {
+                        island.push(cell);
+                    }
+                    dig.edits.push((index(cell), if in_block { stone } else { Voxel::EMPTY }));
+                }
+            }
+        }
+        let mut body_world = world.clone();
+        for &(index, voxel) in &dig.edits {
+            let position = world.position(index);
+            *body_world.get_mut(position.x, position.y, position.z).unwrap() = voxel;
+        }
+        let mut body = RigidVoxelBody::from_island(&body_world, &island);
+        for cell in &island {
+            *body_world.get_mut(cell.x as u32, cell.y as u32, cell.z as u32).unwrap() = Voxel::EMPTY;
+        }
+
+        // Each frame stamps where the body moved to, like `step_rigid_bodies`
+        let mut frames = vec![dig];
+        for _ in 0..600 {
+            let old = body.cells();
+            let resting = body.step(&body_world, 1.0 / 60.0);
+            let mut frame = still_frame(Vec3::splat(-1.0));
+            frame.edits = body.moved_cells(&old).into_iter().map(|(cell, voxel)| (index(cell), voxel)).collect();
+            frames.push(InputFrame::parse(&frame.to_line()).unwrap());
+            if resting {
+                break;
+            }
+        }
+
+        let cells = body.cells();
+        assert!(cells.iter().all(|(cell, _)| (100..102).contains(&cell.y)), "{cells:?}");
+        for (cell, voxel) in cells {
+            *body_world.get_mut(cell.x as u32, cell.y as u32, cell.z as u32).unwrap() = voxel;
+        }
+        assert_eq!(run_headless(&Replay { seed, frames }).checksum(), body_world.checksum());
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let args = ["--replay", "session.txt", "--headless"].map(String::from);
+        let options = ReplayOptions::from_args(args).unwrap();
+        assert_eq!(options.replay, Some(PathBuf::from("session.txt")));
+        assert!(options.headless);
+
+        assert!(ReplayOptions::from_args(["--headless".to_string()]).is_err());
+        assert!(ReplayOptions::from_args(["--seed".to_string(), "x".to_string()]).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use bevy::core_pipeline::tonemapping::Tonemapping;
 use bevy::ecs::event::{Events, ManualEventReader};
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
@@ -39,6 +40,8 @@ pub struct KeyBindings {
     pub move_ascend: KeyCode,
     pub move_descend: KeyCode,
     pub toggle_grab_cursor: KeyCode,
+    /// Switches between flying and walking
+    pub toggle_walk: KeyCode,
 }
 
 impl Default for KeyBindings {
@@ -51,10 +54,20 @@ impl Default for KeyBindings {
             move_ascend: KeyCode::Space,
             move_descend: KeyCode::LShift,
             toggle_grab_cursor: KeyCode::Escape,
+            toggle_walk: KeyCode::F,
         }
     }
 }
 
+/// Whether the camera flies freely or walks with collision and gravity.
+/// Walking is driven from outside this module, the flycam only moves while flying.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MovementMode {
+    #[default]
+    Fly,
+    Walk,
+}
+
 /// Used in queries when you want flycams and not other cameras
 /// A marker component used in queries when you want flycams and not other cameras
 #[derive(Component)]
@@ -86,7 +99,24 @@ fn initial_grab_cursor(mut primary_window: Query<&mut Window, With<PrimaryWindow
 /// Spawns the `Camera3dBundle` to be controlled
 fn setup_player(mut commands: Commands) {
     commands.spawn((
-        Transform::from_xyz(-2.0, 0.0, 5.0).looking_at(Vec3::new(20.0, 20.0, 20.0), Vec3::Y),
+        Camera3dBundle {
+            transform: Transform::from_xyz(-2.0, 0.0, 5.0).looking_at(Vec3::new(20.0, 20.0, 20.0), Vec3::Y),
+            // The voxels are raytraced through this projection too, change the FOV here
+            projection: PerspectiveProjection {
+                fov: 60f32.to_radians(),
+                near: 0.1,
+                far: 1000.0,
+                ..default()
+            }
+            .into(),
+            // The voxels are rendered in HDR and tonemapped together with any meshes
+            camera: Camera {
+                hdr: true,
+                ..default()
+            },
+            tonemapping: Tonemapping::AgX,
+            ..default()
+        },
         FlyCam,
     ));
 }
@@ -98,8 +128,12 @@ fn player_move(
     primary_window: Query<&Window, With<PrimaryWindow>>,
     settings: Res<MovementSettings>,
     key_bindings: Res<KeyBindings>,
+    mode: Res<MovementMode>,
     mut query: Query<(&FlyCam, &mut Transform)>, //    mut query: Query<&mut Transform, With<FlyCam>>,
 ) {
+    if *mode != MovementMode::Fly {
+        return;
+    }
     if let Ok(window) = primary_window.get_single() {
         for (_camera, mut transform) in query.iter_mut() {
             let mut velocity = Vec3::ZERO;
@@ -186,6 +220,19 @@ fn cursor_grab(
     }
 }
 
+fn toggle_movement_mode(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut mode: ResMut<MovementMode>,
+) {
+    if keys.just_pressed(key_bindings.toggle_walk) {
+        *mode = match *mode {
+            MovementMode::Fly => MovementMode::Walk,
+            MovementMode::Walk => MovementMode::Fly,
+        };
+    }
+}
+
 // Grab cursor when an entity with FlyCam is added
 fn initial_grab_on_flycam_spawn(
     mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
@@ -209,11 +256,13 @@ impl Plugin for PlayerPlugin {
         app.init_resource::<InputState>()
             .init_resource::<MovementSettings>()
             .init_resource::<KeyBindings>()
+            .init_resource::<MovementMode>()
             .add_system(setup_player.on_startup())
             .add_system(initial_grab_cursor.on_startup())
             .add_system(player_move)
             .add_system(player_look)
-            .add_system(cursor_grab);
+            .add_system(cursor_grab)
+            .add_system(toggle_movement_mode);
     }
 }
 
@@ -224,10 +273,12 @@ impl Plugin for NoCameraPlayerPlugin {
         app.init_resource::<InputState>()
             .init_resource::<MovementSettings>()
             .init_resource::<KeyBindings>()
+            .init_resource::<MovementMode>()
             .add_system(initial_grab_cursor.on_startup())
             .add_system(initial_grab_on_flycam_spawn.on_startup())
             .add_system(player_move)
             .add_system(player_look)
-            .add_system(cursor_grab);
+            .add_system(cursor_grab)
+            .add_system(toggle_movement_mode);
     }
 }
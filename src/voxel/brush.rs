@@ -0,0 +1,148 @@
+use bevy::prelude::{IVec3, Resource, Vec3};
+
+use super::Voxel;
+
+/// Largest radius the mouse wheel can grow the brush to
+pub const MAX_BRUSH_RADIUS: u32 = 16;
+
+// Black sand, the material placed until something else is picked
+const DEFAULT_MATERIAL: u32 = 1 << 9;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrushShape {
+    #[default]
+    Sphere,
+    Cube,
+    /// Upright cylinder, as tall as it is wide
+    Cylinder,
+}
+
+impl BrushShape {
+    pub const ALL: [Self; 3] = [Self::Sphere, Self::Cube, Self::Cylinder];
+
+    /// Inverse of `shape as u32`, which is how the edit shader receives it
+    pub fn from_index(index: u32) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrushOperation {
+    /// Fills empty voxels with the material, on top of the face being pointed at
+    #[default]
+    Add,
+    Remove,
+    /// Recolors solid voxels with the material's color, keeping their type
+    Paint,
+    /// Swaps solid voxels of `replace_type` for the material
+    Replace,
+}
+
+impl BrushOperation {
+    pub const ALL: [Self; 4] = [Self::Add, Self::Remove, Self::Paint, Self::Replace];
+
+    /// Inverse of `operation as u32`, which is how the edit shader receives it
+    pub fn from_index(index: u32) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+/// The brush applied by the edit pass while a mouse button is held, mirrored by `brush.wgsl`.
+/// The left button always removes, the right button applies `operation`.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub shape: BrushShape,
+    pub operation: BrushOperation,
+    pub radius: u32,
+    /// Voxel value written by add, replace and (its color) paint
+    pub material: u32,
+    /// Voxel type affected by replace
+    pub replace_type: u32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            shape: BrushShape::default(),
+            operation: BrushOperation::default(),
+            radius: 3,
+            material: DEFAULT_MATERIAL,
+            replace_type: 0,
+        }
+    }
+}
+
+impl Brush {
+    /// Whether a voxel at `offset` from the brush center is covered by the brush
+    pub fn contains(&self, offset: IVec3) -> bool {
+        let radius = self.radius as i32;
+        if offset.abs().max_element() > radius {
+            return false;
+        }
+        match self.shape {
+            BrushShape::Sphere => offset.dot(offset) <= radius * radius,
+            BrushShape::Cube => true,
+            BrushShape::Cylinder => offset.x * offset.x + offset.z * offset.z <= radius * radius,
+        }
+    }
+
+    /// The voxel left after the brush passes over `voxel`
+    pub fn apply(&self, voxel: Voxel) -> Voxel {
+        let material = Voxel::new(self.material);
+        match self.operation {
+            BrushOperation::Add if voxel.is_empty() => material,
+            BrushOperation::Remove => Voxel::EMPTY,
+            BrushOperation::Paint if !voxel.is_empty() => {
+                Voxel::new((material.value() & !255) | voxel.get_voxel_type())
+            }
+            BrushOperation::Replace if !voxel.is_empty() && voxel.get_voxel_type() == self.replace_type => material,
+            _ => voxel,
+        }
+    }
+
+    /// Where the brush is centered given the raycast selection, adding builds out from the face being pointed at
+    pub fn center(&self, selected: Vec3, normal: Vec3) -> IVec3 {
+        let selected = selected.as_ivec3();
+        match self.operation {
+            BrushOperation::Add => selected + normal.as_ivec3(),
+            _ => selected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_cover_expected_voxels() {
+        let count = |shape| {
+            let brush = Brush { shape, radius: 2, ..Default::default() };
+            let mut count = 0;
+            for x in -3..=3 {
+                for y in -3..=3 {
+                    for z in -3..=3 {
+                        count += brush.contains(IVec3::new(x, y, z)) as u32;
+                    }
+                }
+            }
+            count
+        };
+
+        assert_eq!(count(BrushShape::Cube), 125);
+        assert_eq!(count(BrushShape::Sphere), 33);
+        assert_eq!(count(BrushShape::Cylinder), 13 * 5);
+    }
+
+    #[test]
+    fn paint_keeps_voxel_type() {
+        let mut water = Voxel::default();
+        water.set_voxel_type(1);
+        let brush = Brush { operation: BrushOperation::Paint, ..Default::default() };
+
+        let painted = brush.apply(water);
+        assert_eq!(painted.get_voxel_type(), 1);
+        assert_eq!(painted.value() & !255, brush.material);
+        assert!(brush.apply(Voxel::EMPTY).is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+use bevy::prelude::{Resource, Vec3};
+
+use super::query::{intersects_box, VoxelVolume};
+
+// Gap kept between the collider and the voxels it rests against, so touching never reads as overlapping
+const SKIN: f32 = 1e-3;
+// Longest distance moved in one collision step, shorter than a voxel so nothing is tunneled through
+const MAX_SUBSTEP: f32 = 0.4;
+
+/// How a walking character moves and how big it is
+#[derive(Resource, Clone, Debug)]
+pub struct WalkSettings {
+    /// Horizontal speed, in voxels per second
+    pub speed: f32,
+    pub gravity: f32,
+    pub jump_speed: f32,
+    /// Falling is capped at this speed
+    pub max_fall_speed: f32,
+    /// Ledges up to this high are climbed without jumping
+    pub step_height: f32,
+    /// Half the size of the collider on each axis
+    pub half_extents: Vec3,
+    /// Height of the eyes above the feet
+    pub eye_height: f32,
+}
+
+impl Default for WalkSettings {
+    fn default() -> Self {
+        Self {
+            speed: 5.0,
+            gravity: 30.0,
+            jump_speed: 9.0,
+            max_fall_speed: 50.0,
+            step_height: 1.0,
+            half_extents: Vec3::new(0.3, 0.9, 0.3),
+            eye_height: 1.6,
+        }
+    }
+}
+
+/// An axis aligned box collider moved through the voxels by gravity and walking input.
+/// `position` is the middle of the bottom face, where the feet are.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct CharacterBody {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub on_ground: bool,
+}
+
+impl CharacterBody {
+    pub fn new(position: Vec3) -> Self {
+        Self { position, ..Default::default() }
+    }
+
+    /// Corners of the collider at a given feet position
+    fn bounds(settings: &WalkSettings, position: Vec3) -> (Vec3, Vec3) {
+        let half = settings.half_extents;
+        let min = position - Vec3::new(half.x, 0.0, half.z);
+        (min, min + half * 2.0)
+    }
+
+    fn collides(volume: &(impl VoxelVolume + ?Sized), settings: &WalkSettings, position: Vec3) -> bool {
+        let (min, max) = Self::bounds(settings, position);
+        intersects_box(volume, min, max)
+    }
+
+    /// Advances the body by `delta_seconds`.
+    /// `walk` is the horizontal direction to walk in, its length scales the speed. Jumping only works from the ground.
+    pub fn update(&mut self, volume: &(impl VoxelVolume + ?Sized), settings: &WalkSettings, walk: Vec3, jump: bool, delta_seconds: f32) {
+        let walk = Vec3::new(walk.x, 0.0, walk.z) * settings.speed;
+        self.velocity.x = walk.x;
+        self.velocity.z = walk.z;
+        if jump && self.on_ground {
+            self.velocity.y = settings.jump_speed;
+        }
+        self.velocity.y = (self.velocity.y - settings.gravity * delta_seconds).max(-settings.max_fall_speed);
+
+        let displacement = self.velocity * delta_seconds;
+        let substeps = (displacement.abs().max_element() / MAX_SUBSTEP).ceil().max(1.0) as u32;
+        let step = displacement / substeps as f32;
+        let was_on_ground = self.on_ground;
+        self.on_ground = false;
+        for _ in 0..substeps {
+            self.move_vertically(volume, settings, step.y);
+            let can_step_up = self.on_ground || was_on_ground;
+            self.move_horizontally(volume, settings, 0, step.x, can_step_up);
+            self.move_horizontally(volume, settings, 2, step.z, can_step_up);
+        }
+    }
+
+    fn move_vertically(&mut self, volume: &(impl VoxelVolume + ?Sized), settings: &WalkSettings, distance: f32) {
+        let target = self.position + Vec3::Y * distance;
+        if !Self::collides(volume, settings, target) {
+            self.position = target;
+            return;
+        }
+
+        // Stop against the face of the voxel that was hit
+        let height = settings.half_extents.y * 2.0;
+        if distance < 0.0 {
+            self.position.y = target.y.ceil() + SKIN;
+            self.on_ground = true;
+        } else {
+            self.position.y = (target.y + height).floor() - height - SKIN;
+        }
+        self.velocity.y = 0.0;
+    }
+
+    fn move_horizontally(&mut self, volume: &(impl VoxelVolume + ?Sized), settings: &WalkSettings, axis: usize, distance: f32, can_step_up: bool) {
+        if distance == 0.0 {
+            return;
+        }
+        let mut target = self.position;
+        target[axis] += distance;
+        if !Self::collides(volume, settings, target) {
+            self.position = target;
+            return;
+        }
+
+        // Climb onto ledges low enough to step over, then settle onto them
+        if can_step_up {
+            let lift = Vec3::Y * settings.step_height;
+            if !Self::collides(volume, settings, self.position + lift) && !Self::collides(volume, settings, target + lift) {
+                self.position = target + lift;
+                self.move_vertically(volume, settings, -settings.step_height);
+                return;
+            }
+        }
+
+        // Slide along the wall, stopping against it on this axis
+        let half = settings.half_extents[axis];
+        self.position[axis] = if distance > 0.0 {
+            (target[axis] + half).floor() - half - SKIN
+        } else {
+            (target[axis] - half).ceil() + half + SKIN
+        };
+        self.velocity[axis] = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::IVec3;
+
+    use crate::voxel::{Voxel, VoxelGrid, VOXEL_TYPE_STONE};
+    use crate::voxel::tools::{plane, line};
+    use super::*;
+
+    fn stone() -> Voxel {
+        Voxel::material(Vec3::splat(0.5), VOXEL_TYPE_STONE)
+    }
+
+    /// A grid with a stone floor at y = 0
+    fn floor() -> VoxelGrid {
+        let mut grid = VoxelGrid::new(16, Vec3::ZERO);
+        plane(&grid, IVec3::ZERO, IVec3::new(15, 0, 15), stone()).apply(&mut grid);
+        grid
+    }
+
+    fn run(body: &mut CharacterBody, grid: &VoxelGrid, walk: Vec3, seconds: f32) {
+        let settings = WalkSettings::default();
+        let frames = (seconds * 60.0) as u32;
+        for _ in 0..frames {
+            body.update(grid, &settings, walk, false, 1.0 / 60.0);
+        }
+    }
+
+    #[test]
+    fn lands_on_floor() {
+        let grid = floor();
+        let mut body = CharacterBody::new(Vec3::new(8.0, 10.0, 8.0));
+        run(&mut body, &grid, Vec3::ZERO, 2.0);
+        assert!(body.on_ground);
+        assert!((body.position.y - 1.0).abs() < 0.01, "{:?}", body.position);
+        assert_eq!(body.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let mut grid = floor();
+        // A wall two voxels high along z at x = 10
+        plane(&grid, IVec3::new(10, 1, 0), IVec3::new(10, 2, 15), stone()).apply(&mut grid);
+
+        let mut body = CharacterBody::new(Vec3::new(8.0, 1.0, 4.0));
+        run(&mut body, &grid, Vec3::new(1.0, 0.0, 1.0).normalize(), 1.0);
+        assert!(body.position.x < 10.0 - WalkSettings::default().half_extents.x, "{:?}", body.position);
+        assert!(body.position.x > 9.6, "{:?}", body.position);
+        // Still moved along the wall
+        assert!(body.position.z > 6.5, "{:?}", body.position);
+        assert!(body.on_ground);
+    }
+
+    #[test]
+    fn steps_up_single_ledges() {
+        let mut grid = floor();
+        // One voxel high step at x = 10, and a two voxel wall at x = 13
+        plane(&grid, IVec3::new(10, 1, 0), IVec3::new(15, 1, 15), stone()).apply(&mut grid);
+        line(&grid, IVec3::new(13, 2, 0), IVec3::new(13, 2, 15), stone()).apply(&mut grid);
+        line(&grid, IVec3::new(13, 3, 0), IVec3::new(13, 3, 15), stone()).apply(&mut grid);
+
+        let mut body = CharacterBody::new(Vec3::new(8.0, 1.0, 8.5));
+        run(&mut body, &grid, Vec3::X, 2.0);
+        assert!((body.position.y - 2.0).abs() < 0.01, "{:?}", body.position);
+        assert!(body.position.x > 12.5 && body.position.x < 13.0, "{:?}", body.position);
+    }
+
+    #[test]
+    fn jumps_from_the_ground_only() {
+        let grid = floor();
+        let settings = WalkSettings::default();
+        let mut body = CharacterBody::new(Vec3::new(8.0, 1.0 + SKIN, 8.0));
+        body.update(&grid, &settings, Vec3::ZERO, false, 1.0 / 60.0);
+        assert!(body.on_ground);
+
+        body.update(&grid, &settings, Vec3::ZERO, true, 1.0 / 60.0);
+        assert!(!body.on_ground);
+        assert!(body.velocity.y > 0.0);
+        let height = body.position.y;
+        body.update(&grid, &settings, Vec3::ZERO, true, 1.0 / 60.0);
+        assert!(body.position.y > height);
+        assert!(body.velocity.y < settings.jump_speed - settings.gravity / 60.0);
+    }
+}
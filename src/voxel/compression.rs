@@ -0,0 +1,73 @@
+use bevy::prelude::Vec3;
+
+use super::{Voxel, VoxelGrid};
+
+/// Run-length encoded voxel data, the format simulation snapshots are kept in.
+/// Voxels are stored in the same x, y, z order as [`VoxelGrid`], so large empty or uniform areas collapse into single runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressedGrid {
+    dim: u32,
+    pos: Vec3,
+    // (run length, voxel value)
+    runs: Vec<(u32, u32)>,
+}
+
+impl CompressedGrid {
+    pub fn compress(grid: &VoxelGrid) -> Self {
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for voxel in &grid.voxels {
+            match runs.last_mut() {
+                Some((length, value)) if *value == voxel.value() => *length += 1,
+                _ => runs.push((1, voxel.value())),
+            }
+        }
+
+        Self {
+            dim: grid.dim,
+            pos: grid.pos,
+            runs,
+        }
+    }
+
+    pub fn decompress(&self) -> VoxelGrid {
+        let mut grid = VoxelGrid::new(self.dim, self.pos);
+        let mut index = 0;
+        for &(length, value) in &self.runs {
+            let end = index + length as usize;
+            grid.voxels[index..end].fill(Voxel::new(value));
+            index = end;
+        }
+        grid
+    }
+
+    pub fn dim(&self) -> u32 {
+        self.dim
+    }
+
+    /// Approximate memory used by the compressed data
+    pub fn size_in_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.runs.len() * std::mem::size_of::<(u32, u32)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut grid = VoxelGrid::new(8, Vec3::new(1.0, 2.0, 3.0));
+        for i in 0..8 {
+            let mut voxel = Voxel::default();
+            voxel.set_color(Vec3::new(0.5, 0.3, 0.1));
+            voxel.set_voxel_type(i % 2);
+            *grid.get_mut(i, 0, i).unwrap() = voxel;
+        }
+
+        let compressed = CompressedGrid::compress(&grid);
+        let decompressed = compressed.decompress();
+        assert_eq!(decompressed.voxels, grid.voxels);
+        assert_eq!(decompressed.pos, grid.pos);
+        assert!(compressed.runs.len() <= 17);
+    }
+}
@@ -0,0 +1,43 @@
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::util::vary_color;
+use super::VoxelGrid;
+
+/// Seed the world is generated from, recordings store it so a replay starts from the same grid
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct WorldSeed(pub u64);
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        Self(rand::thread_rng().gen())
+    }
+}
+
+pub fn generate_world(n: u32, seed: u64) -> VoxelGrid {
+    let pos = Vec3 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    let mut voxels = VoxelGrid::new(n, pos);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Fill the whole grid with sand, each voxel's color varied a little
+    for i in 0..n {
+        for j in 0..n {
+            for k in 0..n {
+                if let Some(voxel) = voxels.get_mut(i, j, k) {
+                    let variance = rng.gen_range(-0.02..0.02);
+                    let sand_color = Vec3::new(0.5, 0.3, 0.1);
+                    voxel.set_color(vary_color(sand_color, variance));
+                }
+            }
+        }
+    }
+
+    voxels
+}
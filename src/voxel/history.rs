@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::Resource;
+
+use super::VoxelGrid;
+use super::compression::CompressedGrid;
+
+/// A compressed copy of the grid as it was after a given physics tick
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub tick: u64,
+    pub grid: CompressedGrid,
+}
+
+/// Bounded ring buffer of grid snapshots, for rewinding the simulation.
+/// The oldest snapshots are dropped once the total size goes over `max_bytes`.
+#[derive(Resource, Debug)]
+pub struct SnapshotHistory {
+    /// Physics ticks between two snapshots
+    pub interval: u64,
+    pub max_bytes: usize,
+    snapshots: VecDeque<Snapshot>,
+    bytes: usize,
+}
+
+impl Default for SnapshotHistory {
+    fn default() -> Self {
+        Self::new(30, 64 * 1024 * 1024)
+    }
+}
+
+impl SnapshotHistory {
+    pub fn new(interval: u64, max_bytes: usize) -> Self {
+        Self {
+            interval,
+            max_bytes,
+            snapshots: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Snapshot> {
+        self.snapshots.get(index)
+    }
+
+    pub fn latest_tick(&self) -> Option<u64> {
+        self.snapshots.back().map(|snapshot| snapshot.tick)
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Whether a snapshot is due, given the current physics tick
+    pub fn is_due(&self, tick: u64) -> bool {
+        match self.latest_tick() {
+            Some(latest) => tick >= latest + self.interval,
+            None => true,
+        }
+    }
+
+    pub fn push(&mut self, tick: u64, grid: &VoxelGrid) {
+        let snapshot = Snapshot {
+            tick,
+            grid: CompressedGrid::compress(grid),
+        };
+        self.bytes += snapshot.grid.size_in_bytes();
+        self.snapshots.push_back(snapshot);
+
+        // Always keep the newest snapshot, even if it alone is over budget
+        while self.bytes > self.max_bytes && self.snapshots.len() > 1 {
+            let oldest = self.snapshots.pop_front().unwrap();
+            self.bytes -= oldest.grid.size_in_bytes();
+        }
+    }
+
+    /// Drops every snapshot taken after `tick`, so the timeline can continue from there
+    pub fn truncate_after(&mut self, tick: u64) {
+        while self.latest_tick().is_some_and(|latest| latest > tick) {
+            let newest = self.snapshots.pop_back().unwrap();
+            self.bytes -= newest.grid.size_in_bytes();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::Vec3;
+
+    #[test]
+    fn evicts_oldest_over_budget() {
+        let grid = VoxelGrid::new(8, Vec3::ZERO);
+        let snapshot_size = CompressedGrid::compress(&grid).size_in_bytes();
+        let mut history = SnapshotHistory::new(10, snapshot_size * 3);
+
+        for tick in (0..50).step_by(10) {
+            assert!(history.is_due(tick));
+            history.push(tick, &grid);
+            assert!(!history.is_due(tick + 5));
+        }
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.get(0).unwrap().tick, 20);
+        assert!(history.size_in_bytes() <= history.max_bytes);
+    }
+
+    #[test]
+    fn truncates_after_rewind() {
+        let grid = VoxelGrid::new(8, Vec3::ZERO);
+        let mut history = SnapshotHistory::default();
+        for tick in [0, 30, 60, 90] {
+            history.push(tick, &grid);
+        }
+
+        history.truncate_after(30);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.latest_tick(), Some(30));
+    }
+}
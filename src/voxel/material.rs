@@ -0,0 +1,171 @@
+use bevy::prelude::{FromReflect, Reflect, ReflectResource, Resource, Vec3};
+use bevy::render::extract_resource::ExtractResource;
+
+use super::{Voxel, VoxelGrid, VOXEL_TYPE_GLASS, VOXEL_TYPE_LAMP, VOXEL_TYPE_METAL, VOXEL_TYPE_SAND, VOXEL_TYPE_STONE, VOXEL_TYPE_WATER};
+
+/// How every voxel of one type renders, on top of its own color
+#[derive(Clone, Debug, Reflect, FromReflect)]
+pub struct VoxelMaterial {
+    pub name: String,
+    /// Emissive voxels glow in their own color and light up their surroundings
+    pub emissive: bool,
+    /// Brightness of the glow, 1 is about as bright as the sun
+    pub emission: f32,
+    /// Transparent voxels let rays through, tinted by their color
+    pub transparent: bool,
+    /// How much of the surface of a transparent voxel is drawn over what is seen through it
+    pub opacity: f32,
+    /// How strongly a transparent voxel absorbs the light its color doesn't let through, per voxel travelled
+    pub density: f32,
+    /// 1 for no refraction, about 1.33 for water and 1.5 for glass
+    pub refractive_index: f32,
+    /// 0 reflects like a mirror, 1 doesn't reflect at all
+    pub roughness: f32,
+    /// Metals reflect in their own color and have no diffuse light
+    pub metalness: f32,
+}
+
+impl Default for VoxelMaterial {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            emissive: false,
+            emission: 0.0,
+            transparent: false,
+            opacity: 1.0,
+            density: 0.0,
+            refractive_index: 1.0,
+            roughness: 1.0,
+            metalness: 0.0,
+        }
+    }
+}
+
+/// Materials indexed by voxel type, shown in the inspector. Types past the end render as plain diffuse voxels.
+#[derive(Resource, Clone, Debug, Reflect, ExtractResource)]
+#[reflect(Resource)]
+pub struct VoxelMaterials {
+    pub materials: Vec<VoxelMaterial>,
+}
+
+impl Default for VoxelMaterials {
+    fn default() -> Self {
+        let named = |name: &str| VoxelMaterial { name: name.to_string(), ..Default::default() };
+        let mut materials = vec![VoxelMaterial::default(); VOXEL_TYPE_METAL as usize + 1];
+        materials[VOXEL_TYPE_SAND as usize] = named("Sand");
+        materials[VOXEL_TYPE_WATER as usize] = VoxelMaterial {
+            transparent: true,
+            opacity: 0.2,
+            density: 0.3,
+            refractive_index: 1.33,
+            roughness: 0.05,
+            ..named("Water")
+        };
+        materials[VOXEL_TYPE_STONE as usize] = named("Stone");
+        materials[VOXEL_TYPE_LAMP as usize] = VoxelMaterial {
+            emissive: true,
+            emission: 4.0,
+            ..named("Lamp")
+        };
+        materials[VOXEL_TYPE_GLASS as usize] = VoxelMaterial {
+            transparent: true,
+            opacity: 0.05,
+            density: 0.05,
+            refractive_index: 1.5,
+            roughness: 0.0,
+            ..named("Glass")
+        };
+        materials[VOXEL_TYPE_METAL as usize] = VoxelMaterial {
+            roughness: 0.2,
+            metalness: 1.0,
+            ..named("Metal")
+        };
+        Self { materials }
+    }
+}
+
+impl VoxelMaterials {
+    pub fn get(&self, voxel_type: u32) -> Option<&VoxelMaterial> {
+        self.materials.get(voxel_type as usize)
+    }
+
+    /// Brightness a voxel glows with, 0 unless its material is emissive
+    pub fn emission(&self, voxel: Voxel) -> f32 {
+        match self.get(voxel.get_voxel_type()) {
+            Some(material) if material.emissive && !voxel.is_empty() => material.emission,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The emissive voxels in one cell of a coarse grid, lighting the world as a single point light
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmissiveCluster {
+    /// Middle of the emissive voxels, weighted by their brightness
+    pub position: Vec3,
+    /// Summed color times emission of the voxels
+    pub color: Vec3,
+    pub count: u32,
+}
+
+/// Groups the emissive voxels of a grid by the `cluster_size` wide cell they fall in, brightest clusters first
+pub fn emissive_clusters(grid: &VoxelGrid, materials: &VoxelMaterials, cluster_size: u32) -> Vec<EmissiveCluster> {
+    if !materials.materials.iter().any(|material| material.emissive && material.emission > 0.0) {
+        return Vec::new();
+    }
+
+    let cells = grid.dim().div_ceil(cluster_size);
+    // Weighted position sum, color sum, voxel count
+    let mut sums = vec![(Vec3::ZERO, Vec3::ZERO, 0.0, 0u32); (cells * cells * cells) as usize];
+    for index in 0..grid.dim().pow(3) {
+        let position = grid.position(index);
+        let voxel = grid.get(position.x, position.y, position.z).copied().unwrap_or_default();
+        let brightness = materials.emission(voxel);
+        if brightness <= 0.0 {
+            continue;
+        }
+        let cell = position / cluster_size;
+        let sum = &mut sums[((cell.x * cells + cell.y) * cells + cell.z) as usize];
+        sum.0 += (position.as_vec3() + 0.5) * brightness;
+        sum.1 += voxel.get_color() * brightness;
+        sum.2 += brightness;
+        sum.3 += 1;
+    }
+
+    let mut clusters: Vec<EmissiveCluster> = sums
+        .into_iter()
+        .filter(|&(_, _, weight, _)| weight > 0.0)
+        .map(|(position, color, weight, count)| EmissiveCluster { position: position / weight, color, count })
+        .collect();
+    clusters.sort_by(|a, b| b.color.length_squared().total_cmp(&a.color.length_squared()));
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::UVec3;
+
+    use super::*;
+
+    #[test]
+    fn clusters_group_emissive_voxels() {
+        let materials = VoxelMaterials::default();
+        let lamp = Voxel::material(Vec3::ONE, VOXEL_TYPE_LAMP);
+        let sand = Voxel::material(Vec3::ONE, VOXEL_TYPE_SAND);
+        assert_eq!(materials.emission(sand), 0.0);
+        assert!(materials.emission(lamp) > 0.0);
+
+        let mut grid = VoxelGrid::new(16, Vec3::ZERO);
+        for position in [UVec3::new(1, 1, 1), UVec3::new(2, 1, 1), UVec3::new(12, 12, 12)] {
+            *grid.get_mut(position.x, position.y, position.z).unwrap() = lamp;
+        }
+        *grid.get_mut(3, 3, 3).unwrap() = sand;
+
+        let clusters = emissive_clusters(&grid, &materials, 8);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].count, 2);
+        assert_eq!(clusters[0].position, Vec3::new(2.0, 1.5, 1.5));
+        assert_eq!(clusters[1].position, Vec3::splat(12.5));
+        assert!(clusters[0].color.x > clusters[1].color.x);
+    }
+}
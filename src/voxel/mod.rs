@@ -1,5 +1,29 @@
-use bevy::{prelude::Vec3, render::render_resource::ShaderType};
-use bevy::prelude::*;
+use bevy::{prelude::{UVec3, Vec3}, render::render_resource::ShaderType};
+
+pub mod brush;
+pub mod character;
+pub mod compression;
+pub mod generation;
+pub mod history;
+pub mod material;
+pub mod prefab;
+pub mod query;
+pub mod regions;
+pub mod rigid;
+pub mod simulation;
+pub mod tools;
+pub mod undo;
+
+pub const VOXEL_TYPE_SAND: u32 = 0;
+pub const VOXEL_TYPE_WATER: u32 = 1;
+/// Static in the cellular automata, unsupported pieces fall as rigid bodies instead
+pub const VOXEL_TYPE_STONE: u32 = 2;
+/// Static and glowing, see [`material::VoxelMaterials`]
+pub const VOXEL_TYPE_LAMP: u32 = 3;
+/// Static and transparent
+pub const VOXEL_TYPE_GLASS: u32 = 4;
+/// Static and shiny
+pub const VOXEL_TYPE_METAL: u32 = 5;
 
 #[derive(Clone, Copy, Debug, Default)]
 pub enum VoxelType {
@@ -8,12 +32,40 @@ pub enum VoxelType {
     Dirt
 }
 
-#[derive(Clone, Copy, Debug, Default, ShaderType)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ShaderType)]
 pub struct Voxel {
     value: u32
 }
 
+// Black would encode as an empty voxel, so solid black gets a spare bit below the color
+const BLACK_BIT: u32 = 1 << 9;
+
 impl Voxel {
+    pub const EMPTY: Voxel = Voxel { value: 0 };
+
+    pub fn new(value: u32) -> Self {
+        Self { value }
+    }
+
+    /// A solid voxel of the given color and type
+    pub fn material(color: Vec3, voxel_type: u32) -> Self {
+        let mut voxel = Voxel::default();
+        voxel.set_color(color);
+        voxel.set_voxel_type(voxel_type);
+        if voxel.is_empty() {
+            voxel = Voxel::new(BLACK_BIT);
+        }
+        voxel
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::EMPTY
+    }
+
     pub fn get_color(&self) -> Vec3 {
         let voxel_data = self.value;
         let mask_5 = 31;
@@ -54,6 +106,8 @@ pub struct VoxelGrid {
     pub pos: Vec3,
     selected: Vec3,
     normal: Vec3,
+    // Value of the selected voxel, fits in the padding after `normal`
+    selected_voxel: u32,
     #[size(runtime)]
     voxels: Vec<Voxel>,
 }
@@ -65,12 +119,54 @@ impl VoxelGrid {
             pos,
             normal: Vec3::new(0.0, 0.0, 0.0),
             selected: Vec3::new(-1.0, -1.0, -1.0),
+            selected_voxel: 0,
             voxels: vec![Voxel::default(); (dim*dim*dim) as usize],
         }
 	}
 
+    pub fn dim(&self) -> u32 {
+        self.dim
+    }
+
+    /// Voxel under the crosshair as of the last raycast, (-1, -1, -1) when nothing is hit
+    pub fn selected(&self) -> Vec3 {
+        self.selected
+    }
+
+    /// Normal of the face hit by the last raycast
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32, z: i32) -> bool {
+        let dim = self.dim as i32;
+        x >= 0 && x < dim && y >= 0 && y < dim && z >= 0 && z < dim
+    }
+
+    /// FNV-1a hash of the voxel data, for checking that two runs ended up with the same grid
+    pub fn checksum(&self) -> u64 {
+        let mut hash = 0xcbf29ce484222325u64;
+        for voxel in &self.voxels {
+            for byte in voxel.value.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    /// Flat index of a voxel, as used by the GPU buffers and [`undo::VoxelChange`]
+    pub fn index(&self, x: u32, y: u32, z: u32) -> u32 {
+        (x * self.dim * self.dim) + (y * self.dim) + z
+    }
+
+    /// Position of a voxel from its flat index
+    pub fn position(&self, index: u32) -> UVec3 {
+        UVec3::new(index / (self.dim * self.dim), (index / self.dim) % self.dim, index % self.dim)
+    }
+
     pub fn get(&self, x: u32, y: u32, z: u32) -> Option<&Voxel> {
-        let index = (x * self.dim * self.dim) + (y * self.dim) + z;
+        let index = self.index(x, y, z);
         if index >= self.voxels.len() as u32 {
             return None;
         }
@@ -78,7 +174,7 @@ impl VoxelGrid {
     }
 
     pub fn get_mut(&mut self, x: u32, y: u32, z: u32) -> Option<&mut Voxel> {
-        let index = (x * self.dim * self.dim) + (y * self.dim) + z;
+        let index = self.index(x, y, z);
         if index >= self.voxels.len()  as u32{
             return None;
         }
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use bevy::prelude::{IVec3, UVec3, Vec3};
+
+use super::{Voxel, VoxelGrid, VOXEL_TYPE_SAND};
+
+// MagicaVoxel's format version, the one its current releases write
+const VOX_VERSION: u32 = 150;
+// Color indices run from 1 to 255, 0 means empty
+const VOX_PALETTE_SIZE: usize = 255;
+// What voxels get colored when a file has no palette chunk
+const VOX_DEFAULT_COLOR: [u8; 4] = [128, 128, 128, 255];
+// The largest model side MagicaVoxel supports, positions have to fit in a byte
+const VOX_MAX_SIZE: u32 = 256;
+
+/// A box of voxels copied out of a grid, to be pasted elsewhere or saved as a `.vox` model.
+/// The voxels are stored in the same x, y, z order as a [`VoxelGrid`], but only `size` of them.
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    size: UVec3,
+    voxels: Vec<Voxel>,
+}
+
+impl Prefab {
+    pub fn new(size: UVec3) -> Self {
+        Self {
+            size,
+            voxels: vec![Voxel::default(); (size.x * size.y * size.z) as usize],
+        }
+    }
+
+    /// Copies the box between two corners, both included, clamped to the grid
+    pub fn copy(grid: &VoxelGrid, corner_a: IVec3, corner_b: IVec3) -> Self {
+        let last = IVec3::splat(grid.dim() as i32 - 1);
+        let min = corner_a.min(corner_b).clamp(IVec3::ZERO, last).as_uvec3();
+        let max = corner_a.max(corner_b).clamp(IVec3::ZERO, last).as_uvec3();
+
+        let mut prefab = Self::new(max - min + UVec3::ONE);
+        for position in prefab.positions() {
+            let voxel = grid.get(min.x + position.x, min.y + position.y, min.z + position.z).copied().unwrap_or_default();
+            prefab.set(position, voxel);
+        }
+        prefab
+    }
+
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    fn index(&self, position: UVec3) -> Option<usize> {
+        position
+            .cmplt(self.size)
+            .all()
+            .then(|| ((position.x * self.size.y + position.y) * self.size.z + position.z) as usize)
+    }
+
+    pub fn get(&self, position: UVec3) -> Voxel {
+        self.index(position).map(|index| self.voxels[index]).unwrap_or_default()
+    }
+
+    fn set(&mut self, position: UVec3, voxel: Voxel) {
+        if let Some(index) = self.index(position) {
+            self.voxels[index] = voxel;
+        }
+    }
+
+    /// Every position inside the prefab, in grid order
+    fn positions(&self) -> impl Iterator<Item = UVec3> {
+        let size = self.size;
+        (0..size.x).flat_map(move |x| (0..size.y).flat_map(move |y| (0..size.z).map(move |z| UVec3::new(x, y, z))))
+    }
+
+    pub fn solid_count(&self) -> usize {
+        self.positions().filter(|&position| !self.get(position).is_empty()).count()
+    }
+
+    /// The prefab turned a quarter turn about the vertical axis
+    pub fn rotated(&self) -> Self {
+        let mut rotated = Self::new(UVec3::new(self.size.z, self.size.y, self.size.x));
+        for position in self.positions() {
+            let target = UVec3::new(self.size.z - 1 - position.z, position.y, position.x);
+            rotated.set(target, self.get(position));
+        }
+        rotated
+    }
+
+    /// The prefab flipped along x
+    pub fn mirrored(&self) -> Self {
+        let mut mirrored = Self::new(self.size);
+        for position in self.positions() {
+            let target = UVec3::new(self.size.x - 1 - position.x, position.y, position.z);
+            mirrored.set(target, self.get(position));
+        }
+        mirrored
+    }
+
+    /// Grid positions and values the prefab writes when placed at `anchor`.
+    /// The prefab stands on the anchor, centered on it in x and z.
+    /// Pasting writes the whole box including its empty voxels, stamping only writes the solid ones.
+    pub fn placements(&self, anchor: IVec3, stamp: bool) -> Vec<(IVec3, Voxel)> {
+        let min = anchor - IVec3::new(self.size.x as i32 / 2, 0, self.size.z as i32 / 2);
+        self.positions()
+            .map(|position| (min + position.as_ivec3(), self.get(position)))
+            .filter(|(_, voxel)| !stamp || !voxel.is_empty())
+            .collect()
+    }
+
+    /// Encodes the prefab as a MagicaVoxel `.vox` file.
+    /// The file's z axis is up, and colors past the 255 a palette holds are matched to the nearest one.
+    /// Fails for prefabs with a side longer than MagicaVoxel allows.
+    pub fn to_vox(&self) -> Result<Vec<u8>, String> {
+        if self.size.max_element() > VOX_MAX_SIZE {
+            return Err(format!("a .vox model can be at most {VOX_MAX_SIZE} voxels wide"));
+        }
+        let mut palette: Vec<[u8; 4]> = Vec::new();
+        let mut indices: HashMap<u32, u8> = HashMap::new();
+        let mut voxels = Vec::new();
+        for position in self.positions() {
+            let voxel = self.get(position);
+            if voxel.is_empty() {
+                continue;
+            }
+            let color = vox_color(voxel);
+            let index = *indices.entry(voxel.value()).or_insert_with(|| {
+                if palette.len() < VOX_PALETTE_SIZE {
+                    palette.push(color);
+                    palette.len() as u8
+                } else {
+                    nearest_color(&palette, color)
+                }
+            });
+            voxels.extend_from_slice(&[position.x as u8, position.z as u8, position.y as u8, index]);
+        }
+        palette.resize(256, [0; 4]);
+
+        let mut size = Vec::new();
+        for extent in [self.size.x, self.size.z, self.size.y] {
+            size.extend_from_slice(&extent.to_le_bytes());
+        }
+        let mut xyzi = ((voxels.len() / 4) as u32).to_le_bytes().to_vec();
+        xyzi.extend_from_slice(&voxels);
+
+        let mut children = Vec::new();
+        write_chunk(&mut children, b"SIZE", &size, &[]);
+        write_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        write_chunk(&mut children, b"RGBA", &palette.concat(), &[]);
+
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&VOX_VERSION.to_le_bytes());
+        write_chunk(&mut bytes, b"MAIN", &[], &children);
+        Ok(bytes)
+    }
+
+    /// Decodes the first model of a MagicaVoxel `.vox` file, its voxels are loaded as sand
+    pub fn from_vox(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 8 || &bytes[0..4] != b"VOX " {
+            return Err("not a .vox file".to_string());
+        }
+        let main = Chunk::read(bytes, 8)?;
+        if &main.id != b"MAIN" {
+            return Err("missing MAIN chunk".to_string());
+        }
+
+        let mut size = None;
+        let mut xyzi = None;
+        let mut palette = None;
+        let mut offset = main.children.start;
+        while offset < main.children.end {
+            let chunk = Chunk::read(bytes, offset)?;
+            let content = &bytes[chunk.content.clone()];
+            match &chunk.id {
+                b"SIZE" if size.is_none() => size = Some(content),
+                b"XYZI" if xyzi.is_none() => xyzi = Some(content),
+                b"RGBA" => palette = Some(content),
+                _ => {}
+            }
+            offset = chunk.children.end;
+        }
+
+        let (Some(size), Some(xyzi)) = (size, xyzi) else {
+            return Err("missing SIZE or XYZI chunk".to_string());
+        };
+        let read_u32 = |data: &[u8], offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                .ok_or_else(|| "truncated chunk".to_string())
+        };
+        let size = UVec3::new(read_u32(size, 0)?, read_u32(size, 8)?, read_u32(size, 4)?);
+        if size.max_element() > VOX_MAX_SIZE {
+            return Err(format!("model size {size} is larger than {VOX_MAX_SIZE}"));
+        }
+        let mut prefab = Self::new(size);
+
+        let count = read_u32(xyzi, 0)? as usize;
+        let voxels = xyzi.get(4..4 + count * 4).ok_or_else(|| "truncated XYZI chunk".to_string())?;
+        for voxel in voxels.chunks_exact(4) {
+            let &[x, y, z, index] = voxel else { unreachable!() };
+            if index == 0 {
+                continue;
+            }
+            let color = palette
+                .and_then(|palette| palette.get(index as usize * 4 - 4..index as usize * 4))
+                .map_or(VOX_DEFAULT_COLOR, |color| color.try_into().unwrap());
+            prefab.set(UVec3::new(x as u32, z as u32, y as u32), Voxel::material(color_from_vox(color), VOXEL_TYPE_SAND));
+        }
+        Ok(prefab)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let bytes = self.to_vox().map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))?;
+        std::fs::write(path, bytes)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::from_vox(&bytes).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Byte ranges of a chunk's content and of its children, which end where the chunk ends
+struct Chunk {
+    id: [u8; 4],
+    content: std::ops::Range<usize>,
+    children: std::ops::Range<usize>,
+}
+
+impl Chunk {
+    fn read(bytes: &[u8], offset: usize) -> Result<Self, String> {
+        let header = bytes.get(offset..offset + 12).ok_or_else(|| "truncated chunk header".to_string())?;
+        let content_size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let children_size = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let content = offset + 12..offset + 12 + content_size;
+        let children = content.end..content.end + children_size;
+        if children.end > bytes.len() {
+            return Err("chunk runs past the end of the file".to_string());
+        }
+        Ok(Self {
+            id: header[0..4].try_into().unwrap(),
+            content,
+            children,
+        })
+    }
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(content);
+    bytes.extend_from_slice(children);
+}
+
+fn vox_color(voxel: Voxel) -> [u8; 4] {
+    let color = (voxel.get_color() * 255.0).round();
+    [color.x as u8, color.y as u8, color.z as u8, 255]
+}
+
+// Half a step up, so the truncation in `Voxel::set_color` gives back the color `vox_color` started from
+fn color_from_vox(color: [u8; 4]) -> Vec3 {
+    (Vec3::new(color[0] as f32, color[1] as f32, color[2] as f32) + 0.5) / 255.0
+}
+
+fn nearest_color(palette: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    let distance = |other: &[u8; 4]| (0..3).map(|i| (other[i] as i32 - color[i] as i32).pow(2)).sum::<i32>();
+    let nearest = (0..palette.len()).min_by_key(|&i| distance(&palette[i])).unwrap_or(0);
+    nearest as u8 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::VOXEL_TYPE_STONE;
+
+    fn sample() -> Prefab {
+        let mut prefab = Prefab::new(UVec3::new(3, 2, 1));
+        prefab.set(UVec3::new(0, 0, 0), Voxel::material(Vec3::new(1.0, 0.0, 0.0), VOXEL_TYPE_SAND));
+        prefab.set(UVec3::new(2, 1, 0), Voxel::material(Vec3::new(0.2, 0.4, 0.9), VOXEL_TYPE_SAND));
+        prefab.set(UVec3::new(1, 0, 0), Voxel::material(Vec3::ZERO, VOXEL_TYPE_SAND));
+        prefab
+    }
+
+    fn assert_same(a: &Prefab, b: &Prefab) {
+        assert_eq!(a.size(), b.size());
+        for position in a.positions() {
+            assert_eq!(a.get(position), b.get(position), "at {position}");
+        }
+    }
+
+    #[test]
+    fn rotations_and_mirrors_come_back_around() {
+        let prefab = sample();
+        let rotated = prefab.rotated();
+        assert_eq!(rotated.size(), UVec3::new(1, 2, 3));
+        assert_eq!(rotated.get(UVec3::new(0, 1, 2)), prefab.get(UVec3::new(2, 1, 0)));
+        assert_same(&rotated.rotated().rotated().rotated(), &prefab);
+
+        let mirrored = prefab.mirrored();
+        assert_eq!(mirrored.get(UVec3::new(0, 1, 0)), prefab.get(UVec3::new(2, 1, 0)));
+        assert_same(&mirrored.mirrored(), &prefab);
+    }
+
+    #[test]
+    fn copy_and_stamp() {
+        let stone = Voxel::material(Vec3::splat(0.5), VOXEL_TYPE_STONE);
+        let mut grid = VoxelGrid::new(8, Vec3::ZERO);
+        *grid.get_mut(2, 3, 4).unwrap() = stone;
+        let prefab = Prefab::copy(&grid, IVec3::new(3, 4, 5), IVec3::new(1, 3, 4));
+        assert_eq!(prefab.size(), UVec3::new(3, 2, 2));
+        assert_eq!(prefab.solid_count(), 1);
+
+        let stamp = prefab.placements(IVec3::new(5, 0, 5), true);
+        assert_eq!(stamp, vec![(IVec3::new(5, 0, 4), stone)]);
+        assert_eq!(prefab.placements(IVec3::ZERO, false).len(), 12);
+    }
+
+    #[test]
+    fn vox_round_trip() {
+        let prefab = sample();
+        let loaded = Prefab::from_vox(&prefab.to_vox().unwrap()).unwrap();
+        assert_same(&loaded, &prefab);
+    }
+
+    #[test]
+    fn rejects_oversized_models() {
+        let mut bytes = sample().to_vox().unwrap();
+        // The SIZE chunk's x follows the file header, the MAIN header and the SIZE header
+        bytes[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Prefab::from_vox(&bytes).is_err());
+        assert!(Prefab::new(UVec3::new(257, 1, 1)).to_vox().is_err());
+    }
+
+    #[test]
+    fn loads_magicavoxel_files() {
+        let prefab = Prefab::load("assets/models/earth.vox").unwrap();
+        assert_eq!(prefab.size(), UVec3::splat(40));
+        assert_eq!(prefab.solid_count(), 49872);
+    }
+}
@@ -0,0 +1,325 @@
+use bevy::prelude::{IVec3, Vec3};
+
+use super::{Voxel, VoxelGrid};
+
+/// Something spatial queries can look voxels up in.
+/// Positions are in voxel units, the same space `raytrace.wgsl` casts its rays in.
+pub trait VoxelVolume {
+    /// Voxels lie between 0 and `dim` on every axis
+    fn dim(&self) -> u32;
+
+    /// The voxel at a position, empty outside of the volume
+    fn voxel(&self, position: IVec3) -> Voxel;
+
+    fn contains(&self, position: IVec3) -> bool {
+        position.cmpge(IVec3::ZERO).all() && position.cmplt(IVec3::splat(self.dim() as i32)).all()
+    }
+}
+
+impl VoxelVolume for VoxelGrid {
+    fn dim(&self) -> u32 {
+        self.dim
+    }
+
+    fn voxel(&self, position: IVec3) -> Voxel {
+        if !VoxelVolume::contains(self, position) {
+            return Voxel::EMPTY;
+        }
+        let position = position.as_uvec3();
+        self.get(position.x, position.y, position.z).copied().unwrap_or_default()
+    }
+}
+
+/// A solid voxel found by a query
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// Grid position of the voxel
+    pub position: IVec3,
+    /// Normal of the face the query came in through, zero if it started inside the voxel
+    pub normal: IVec3,
+    /// Distance from the query's origin to the face
+    pub distance: f32,
+    pub voxel: Voxel,
+}
+
+/// Walks the volume voxel by voxel from `origin` along `direction` with the Amanatides-Woo DDA `raymarch` in `raytrace.wgsl` uses.
+/// Returns the first solid voxel entered within `max_distance`.
+pub fn raycast(volume: &(impl VoxelVolume + ?Sized), origin: Vec3, direction: Vec3, max_distance: f32) -> Option<Hit> {
+    let direction = direction.try_normalize()?;
+    let dim = volume.dim() as i32;
+
+    // Start where the ray enters the volume
+    let (enter, exit, enter_axis) = ray_box(origin, direction, Vec3::ZERO, Vec3::splat(dim as f32))?;
+    let mut distance = enter.max(0.0);
+    if exit < distance || distance > max_distance {
+        return None;
+    }
+    let entry = origin + direction * distance;
+    let mut position = entry.floor().as_ivec3().clamp(IVec3::ZERO, IVec3::splat(dim - 1));
+    let step = IVec3::new(step_sign(direction.x), step_sign(direction.y), step_sign(direction.z));
+    let mut normal = IVec3::ZERO;
+    if enter > 0.0 {
+        normal[enter_axis] = -step[enter_axis];
+    }
+
+    // Distance to the next voxel boundary on each axis, and between two boundaries
+    let delta = direction.recip().abs();
+    let mut boundary = Vec3::ZERO;
+    for axis in 0..3 {
+        boundary[axis] = match step[axis] {
+            1 => (position[axis] as f32 + 1.0 - origin[axis]) / direction[axis],
+            -1 => (position[axis] as f32 - origin[axis]) / direction[axis],
+            _ => f32::INFINITY,
+        };
+    }
+
+    while distance <= max_distance && volume.contains(position) {
+        let voxel = volume.voxel(position);
+        if !voxel.is_empty() {
+            return Some(Hit { position, normal, distance, voxel });
+        }
+
+        let axis = if boundary.x < boundary.y {
+            if boundary.x < boundary.z { 0 } else { 2 }
+        } else if boundary.y < boundary.z {
+            1
+        } else {
+            2
+        };
+        distance = boundary[axis];
+        boundary[axis] += delta[axis];
+        position[axis] += step[axis];
+        normal = IVec3::ZERO;
+        normal[axis] = -step[axis];
+    }
+    None
+}
+
+/// Solid voxels overlapping the box between `min` and `max`. Touching faces don't count as overlapping.
+#[allow(dead_code)]
+pub fn overlap_box(volume: &(impl VoxelVolume + ?Sized), min: Vec3, max: Vec3) -> Vec<IVec3> {
+    voxels_touching(volume, min, max)
+        .filter(|&position| {
+            let voxel_min = position.as_vec3();
+            (voxel_min.cmplt(max) & (voxel_min + Vec3::ONE).cmpgt(min)).all()
+        })
+        .collect()
+}
+
+/// Whether any solid voxel overlaps the box, cheaper than [`overlap_box`] for collision checks
+pub fn intersects_box(volume: &(impl VoxelVolume + ?Sized), min: Vec3, max: Vec3) -> bool {
+    voxels_touching(volume, min, max).any(|position| {
+        let voxel_min = position.as_vec3();
+        (voxel_min.cmplt(max) & (voxel_min + Vec3::ONE).cmpgt(min)).all()
+    })
+}
+
+/// Solid voxels with some part closer than `radius` to `center`
+#[allow(dead_code)]
+pub fn overlap_sphere(volume: &(impl VoxelVolume + ?Sized), center: Vec3, radius: f32) -> Vec<IVec3> {
+    voxels_touching(volume, center - radius, center + radius)
+        .filter(|&position| {
+            let closest = center.clamp(position.as_vec3(), position.as_vec3() + Vec3::ONE);
+            closest.distance_squared(center) < radius * radius
+        })
+        .collect()
+}
+
+/// The highest solid voxel in the column under `point`, including the voxel `point` is in.
+/// The hit's distance is how far `point` is above the voxel's top face.
+#[allow(dead_code)]
+pub fn first_solid_below(volume: &(impl VoxelVolume + ?Sized), point: Vec3) -> Option<Hit> {
+    let start = point.floor().as_ivec3();
+    let top = start.y.min(volume.dim() as i32 - 1);
+    (0..=top).rev()
+        .map(|y| IVec3::new(start.x, y, start.z))
+        .find_map(|position| {
+            let voxel = volume.voxel(position);
+            (!voxel.is_empty()).then(|| Hit {
+                position,
+                normal: IVec3::Y,
+                distance: (point.y - (position.y + 1) as f32).max(0.0),
+                voxel,
+            })
+        })
+}
+
+/// Solid voxels in the cells the box between `min` and `max` touches, clipped to the volume
+fn voxels_touching(volume: &(impl VoxelVolume + ?Sized), min: Vec3, max: Vec3) -> impl Iterator<Item = IVec3> + '_ {
+    let last = IVec3::splat(volume.dim() as i32 - 1);
+    let min = min.floor().as_ivec3().max(IVec3::ZERO);
+    let max = max.floor().as_ivec3().min(last);
+    (min.x..=max.x)
+        .flat_map(move |x| (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z))))
+        .filter(|&position| !volume.voxel(position).is_empty())
+}
+
+fn step_sign(direction: f32) -> i32 {
+    if direction > 0.0 {
+        1
+    } else if direction < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
+
+/// Slab test, returns the entry and exit distances and the axis of the face the ray enters through
+fn ray_box(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<(f32, f32, usize)> {
+    let mut enter = f32::NEG_INFINITY;
+    let mut exit = f32::INFINITY;
+    let mut enter_axis = 0;
+    for axis in 0..3 {
+        if direction[axis] == 0.0 {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (min[axis] - origin[axis]) / direction[axis];
+        let t1 = (max[axis] - origin[axis]) / direction[axis];
+        let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+        if near > enter {
+            enter = near;
+            enter_axis = axis;
+        }
+        exit = exit.min(far);
+    }
+    (enter <= exit).then_some((enter, exit, enter_axis))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::voxel::VOXEL_TYPE_STONE;
+
+    fn random_grid(rng: &mut StdRng, dim: u32, fill: f64) -> VoxelGrid {
+        let mut grid = VoxelGrid::new(dim, Vec3::ZERO);
+        for voxel in grid.voxels.iter_mut() {
+            if rng.gen_bool(fill) {
+                *voxel = Voxel::new(rng.gen_range(1..u32::MAX));
+            }
+        }
+        grid
+    }
+
+    fn all_solid(grid: &VoxelGrid) -> impl Iterator<Item = IVec3> + '_ {
+        let dim = grid.dim() as i32;
+        (0..dim)
+            .flat_map(move |x| (0..dim).flat_map(move |y| (0..dim).map(move |z| IVec3::new(x, y, z))))
+            .filter(|&position| !grid.voxel(position).is_empty())
+    }
+
+    #[test]
+    fn hits_first_voxel_from_outside() {
+        let stone = Voxel::material(Vec3::splat(0.5), VOXEL_TYPE_STONE);
+        let mut grid = VoxelGrid::new(8, Vec3::ZERO);
+        *grid.get_mut(3, 2, 5).unwrap() = stone;
+        *grid.get_mut(6, 2, 5).unwrap() = stone;
+
+        let hit = raycast(&grid, Vec3::new(-4.0, 2.5, 5.5), Vec3::X, 100.0).unwrap();
+        assert_eq!(hit.position, IVec3::new(3, 2, 5));
+        assert_eq!(hit.normal, IVec3::NEG_X);
+        assert_eq!(hit.voxel, stone);
+        assert!((hit.distance - 7.0).abs() < 1e-5);
+
+        let hit = raycast(&grid, Vec3::new(10.0, 2.5, 5.5), Vec3::NEG_X, 100.0).unwrap();
+        assert_eq!(hit.position, IVec3::new(6, 2, 5));
+        assert_eq!(hit.normal, IVec3::X);
+
+        assert!(raycast(&grid, Vec3::new(-4.0, 2.5, 5.5), Vec3::X, 6.0).is_none());
+        assert!(raycast(&grid, Vec3::new(-4.0, 2.5, 5.5), Vec3::NEG_X, 100.0).is_none());
+    }
+
+    #[test]
+    fn raycast_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let grid = random_grid(&mut rng, 16, 0.02);
+        for _ in 0..500 {
+            let origin = Vec3::new(rng.gen_range(-8.0..24.0), rng.gen_range(-8.0..24.0), rng.gen_range(-8.0..24.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize();
+
+            // Nearest voxel box the ray enters
+            let expected = all_solid(&grid)
+                .filter_map(|position| {
+                    let (enter, exit, _) = ray_box(origin, direction, position.as_vec3(), position.as_vec3() + Vec3::ONE)?;
+                    (exit >= 0.0).then_some((position, enter.max(0.0)))
+                })
+                .filter(|&(_, distance)| distance <= 40.0)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            let hit = raycast(&grid, origin, direction, 40.0);
+            match (hit, expected) {
+                (None, None) => {}
+                (Some(hit), Some((position, distance))) => {
+                    assert!((hit.distance - distance).abs() < 1e-3, "{origin} {direction}: {hit:?}, expected {position} at {distance}");
+                    // Rays through an edge can enter two voxels at once, either is the right answer
+                    let (enter, _, _) = ray_box(origin, direction, hit.position.as_vec3(), hit.position.as_vec3() + Vec3::ONE).unwrap();
+                    assert!((enter.max(0.0) - distance).abs() < 1e-3);
+                }
+                (hit, expected) => panic!("{origin} {direction}: {hit:?}, expected {expected:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn overlaps_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let grid = random_grid(&mut rng, 12, 0.3);
+        for _ in 0..200 {
+            let a = Vec3::new(rng.gen_range(-2.0..14.0), rng.gen_range(-2.0..14.0), rng.gen_range(-2.0..14.0));
+            let b = a + Vec3::new(rng.gen_range(0.0..4.0), rng.gen_range(0.0..4.0), rng.gen_range(0.0..4.0));
+            let expected: Vec<IVec3> = all_solid(&grid)
+                .filter(|position| {
+                    let voxel_min = position.as_vec3();
+                    let voxel_max = voxel_min + Vec3::ONE;
+                    voxel_min.x < b.x && voxel_max.x > a.x && voxel_min.y < b.y && voxel_max.y > a.y && voxel_min.z < b.z && voxel_max.z > a.z
+                })
+                .collect();
+            assert_eq!(overlap_box(&grid, a, b), expected);
+            assert_eq!(intersects_box(&grid, a, b), !expected.is_empty());
+
+            let radius = rng.gen_range(0.1..3.0);
+            let expected: Vec<IVec3> = all_solid(&grid)
+                .filter(|position| {
+                    // Sample the voxel densely and keep it if any sample is inside the sphere
+                    let samples = 4;
+                    (0..=samples).any(|i| (0..=samples).any(|j| (0..=samples).any(|k| {
+                        let point = position.as_vec3() + Vec3::new(i as f32, j as f32, k as f32) / samples as f32;
+                        point.distance(a) < radius
+                    })))
+                })
+                .collect();
+            let overlap = overlap_sphere(&grid, a, radius);
+            // Sampling can miss a sliver of a voxel, but never finds one that isn't there
+            assert!(expected.iter().all(|position| overlap.contains(position)), "sphere at {a} radius {radius}");
+            for position in &overlap {
+                let closest = a.clamp(position.as_vec3(), position.as_vec3() + Vec3::ONE);
+                assert!(closest.distance(a) < radius);
+            }
+        }
+    }
+
+    #[test]
+    fn first_solid_below_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let grid = random_grid(&mut rng, 12, 0.1);
+        for _ in 0..200 {
+            let point = Vec3::new(rng.gen_range(0.0..12.0), rng.gen_range(0.0..20.0), rng.gen_range(0.0..12.0));
+            let expected = all_solid(&grid)
+                .filter(|position| {
+                    position.x == point.x.floor() as i32 && position.z == point.z.floor() as i32 && position.y as f32 <= point.y
+                })
+                .max_by_key(|position| position.y);
+
+            let hit = first_solid_below(&grid, point);
+            assert_eq!(hit.map(|hit| hit.position), expected, "below {point}");
+            if let Some(hit) = hit {
+                assert_eq!(hit.distance, (point.y - hit.position.y as f32 - 1.0).max(0.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+/// Edge length of a region in voxels, matching the physics workgroup size
+pub const REGION_SIZE: u32 = 8;
+
+/// How far a change can affect the next tick, sand looks at the neighbours of its neighbours
+pub const WAKE_RADIUS: i32 = 2;
+
+/// Tracks which `REGION_SIZE`³ blocks of a grid need to be simulated on the next tick.
+/// A region is awake when any voxel within `WAKE_RADIUS` of it changed on the previous tick.
+#[derive(Clone, Debug)]
+pub struct ActiveRegions {
+    dim: u32,
+    flags: Vec<bool>,
+}
+
+impl ActiveRegions {
+    /// Every region starts awake so the first tick settles whatever the grid was created with
+    pub fn new(grid_dim: u32) -> Self {
+        let dim = grid_dim.div_ceil(REGION_SIZE);
+        Self {
+            dim,
+            flags: vec![true; (dim * dim * dim) as usize],
+        }
+    }
+
+    /// Every region starts asleep, for collecting the regions woken by a set of edits
+    pub fn asleep(grid_dim: u32) -> Self {
+        let mut regions = Self::new(grid_dim);
+        regions.flags.fill(false);
+        regions
+    }
+
+    pub fn region_index(&self, x: u32, y: u32, z: u32) -> u32 {
+        (x * self.dim * self.dim) + (y * self.dim) + z
+    }
+
+    pub fn region_position(&self, index: u32) -> (u32, u32, u32) {
+        let x = index / (self.dim * self.dim);
+        let y = (index / self.dim) % self.dim;
+        let z = index % self.dim;
+        (x, y, z)
+    }
+
+    /// Wakes the regions within `WAKE_RADIUS` of the voxel at (x, y, z)
+    pub fn wake(&mut self, x: i32, y: i32, z: i32) {
+        let cells = (self.dim * REGION_SIZE) as i32;
+        let size = REGION_SIZE as i32;
+        let min = |v: i32| (v - WAKE_RADIUS).max(0) / size;
+        let max = |v: i32| (v + WAKE_RADIUS).min(cells - 1) / size;
+        if x < 0 || y < 0 || z < 0 || x >= cells || y >= cells || z >= cells {
+            return;
+        }
+
+        for i in min(x)..=max(x) {
+            for j in min(y)..=max(y) {
+                for k in min(z)..=max(z) {
+                    let index = self.region_index(i as u32, j as u32, k as u32);
+                    self.flags[index as usize] = true;
+                }
+            }
+        }
+    }
+
+    /// Indices of the awake regions, in ascending order
+    pub fn active(&self) -> Vec<u32> {
+        (0..self.flags.len() as u32).filter(|&i| self.flags[i as usize]).collect()
+    }
+
+    #[cfg(test)]
+    pub fn active_count(&self) -> usize {
+        self.flags.iter().filter(|&&awake| awake).count()
+    }
+
+    /// Returns the awake regions and puts every region to sleep
+    pub fn take_active(&mut self) -> Vec<u32> {
+        let active = self.active();
+        self.flags.fill(false);
+        active
+    }
+
+    /// Flags laid out the way the physics shaders expect them, one `u32` per region
+    pub fn flags(&self) -> Vec<u32> {
+        self.flags.iter().map(|&awake| awake as u32).collect()
+    }
+}
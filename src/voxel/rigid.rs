@@ -0,0 +1,307 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::prelude::{Component, IVec3, Quat, UVec3, Vec3};
+
+use super::query::VoxelVolume;
+use super::{Voxel, VoxelGrid, VOXEL_TYPE_STONE};
+
+/// Islands bigger than this are left hanging, so cutting under a mountain doesn't drop the whole world
+pub const MAX_ISLAND_SIZE: usize = 4096;
+
+const GRAVITY: f32 = 30.0;
+// Slow enough that a body moves less than a voxel per physics tick, so it can't fall through thin floors
+const MAX_FALL_SPEED: f32 = 15.0;
+// Angular speed a body tips over an edge with, in radians per second
+const TIP_SPEED: f32 = 2.0;
+// Seconds a body has to stay blocked before it counts as resting
+const REST_TIME: f32 = 0.5;
+
+const NEIGHBORS: [IVec3; 6] = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z];
+
+/// Voxels that hold together as one piece instead of falling like sand
+pub fn is_rigid(voxel: Voxel) -> bool {
+    !voxel.is_empty() && voxel.get_voxel_type() == VOXEL_TYPE_STONE
+}
+
+/// Finds the islands of rigid voxels containing or next to `seeds` that nothing holds up.
+/// An island is held up by the bottom of the grid or any non-rigid voxel right below it.
+/// Positions in `ignore` are treated as empty.
+pub fn detached_islands(
+    volume: &(impl VoxelVolume + ?Sized),
+    seeds: impl IntoIterator<Item = IVec3>,
+    ignore: &HashSet<IVec3>,
+    max_size: usize,
+) -> Vec<Vec<IVec3>> {
+    let rigid = |position: IVec3| !ignore.contains(&position) && is_rigid(volume.voxel(position));
+    let mut visited = HashSet::new();
+    let mut islands = Vec::new();
+
+    let starts = seeds.into_iter().flat_map(|seed| std::iter::once(seed).chain(NEIGHBORS.map(|offset| seed + offset)));
+    for start in starts {
+        if !rigid(start) || !visited.insert(start) {
+            continue;
+        }
+
+        let mut island = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        let mut supported = false;
+        while let Some(position) = queue.pop_front() {
+            island.push(position);
+            let below = position - IVec3::Y;
+            if position.y == 0 || (!ignore.contains(&below) && !volume.voxel(below).is_empty() && !is_rigid(volume.voxel(below))) {
+                supported = true;
+            }
+            for offset in NEIGHBORS {
+                let neighbor = position + offset;
+                if rigid(neighbor) && visited.insert(neighbor) {
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        if !supported && island.len() <= max_size {
+            islands.push(island);
+        }
+    }
+    islands
+}
+
+/// A piece of rigid voxels cut free from the grid, falling and tumbling until it comes to rest.
+/// The voxels live in their own small grid, posed in the world by the position of their center of mass and a rotation.
+#[derive(Component, Clone, Debug)]
+pub struct RigidVoxelBody {
+    pub grid: VoxelGrid,
+    /// Center of mass in the body's grid
+    pub center_of_mass: Vec3,
+    /// Center of mass in the world
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub velocity: Vec3,
+    pub angular_velocity: Vec3,
+    resting_time: f32,
+}
+
+impl RigidVoxelBody {
+    /// Lifts an island out of `grid`, the caller is responsible for clearing it there
+    pub fn from_island(volume: &(impl VoxelVolume + ?Sized), island: &[IVec3]) -> Self {
+        let min = island.iter().copied().reduce(IVec3::min).unwrap_or_default();
+        let max = island.iter().copied().reduce(IVec3::max).unwrap_or_default();
+        let size = (max - min + IVec3::ONE).as_uvec3();
+
+        let mut grid = VoxelGrid::new(size.max_element(), Vec3::ZERO);
+        let mut center_of_mass = Vec3::ZERO;
+        for &position in island {
+            let local = (position - min).as_uvec3();
+            if let Some(voxel) = grid.get_mut(local.x, local.y, local.z) {
+                *voxel = volume.voxel(position);
+            }
+            center_of_mass += local.as_vec3() + 0.5;
+        }
+        center_of_mass /= island.len().max(1) as f32;
+
+        Self {
+            grid,
+            center_of_mass,
+            position: min.as_vec3() + center_of_mass,
+            rotation: Quat::IDENTITY,
+            velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+            resting_time: 0.0,
+        }
+    }
+
+    /// The world cells the body covers in a pose, with the voxel in each
+    pub fn cells_at(&self, position: Vec3, rotation: Quat) -> Vec<(IVec3, Voxel)> {
+        // World bounds of the rotated grid
+        let dim = self.grid.dim() as f32;
+        let corners = (0..8).map(|i| {
+            let corner = Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32) * dim;
+            position + rotation * (corner - self.center_of_mass)
+        });
+        let (min, max) = corners.fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), corner| (min.min(corner), max.max(corner)));
+        let min = min.floor().as_ivec3();
+        let max = max.ceil().as_ivec3();
+
+        // Sample the body at the middle of every cell, so a rotated body has no holes
+        let inverse = rotation.inverse();
+        let mut cells = Vec::new();
+        for x in min.x..max.x {
+            for y in min.y..max.y {
+                for z in min.z..max.z {
+                    let cell = IVec3::new(x, y, z);
+                    let local = inverse * (cell.as_vec3() + 0.5 - position) + self.center_of_mass;
+                    if local.cmplt(Vec3::ZERO).any() || local.cmpge(Vec3::splat(dim)).any() {
+                        continue;
+                    }
+                    let local = local.as_uvec3();
+                    let voxel = self.voxel(local);
+                    if !voxel.is_empty() {
+                        cells.push((cell, voxel));
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    pub fn cells(&self) -> Vec<(IVec3, Voxel)> {
+        self.cells_at(self.position, self.rotation)
+    }
+
+    /// What to write into the world after the body moved off the `old` cells it covered:
+    /// the cells it left are emptied and the ones it now covers get its voxels, unchanged cells are left out
+    pub fn moved_cells(&self, old: &[(IVec3, Voxel)]) -> Vec<(IVec3, Voxel)> {
+        let old: HashMap<IVec3, Voxel> = old.iter().copied().collect();
+        let new = self.cells();
+        let vacated = old.keys()
+            .filter(|cell| !new.iter().any(|(new_cell, _)| new_cell == *cell))
+            .map(|&cell| (cell, Voxel::EMPTY));
+        let covered = new.iter().filter(|(cell, voxel)| old.get(cell) != Some(voxel)).copied();
+        vacated.chain(covered).collect()
+    }
+
+    fn voxel(&self, local: UVec3) -> Voxel {
+        self.grid.get(local.x, local.y, local.z).copied().unwrap_or_default()
+    }
+
+    fn collides(&self, world: &(impl VoxelVolume + ?Sized), position: Vec3, rotation: Quat) -> bool {
+        self.cells_at(position, rotation).iter().any(|&(cell, _)| cell.y < 0 || !world.voxel(cell).is_empty())
+    }
+
+    /// Advances the body by `delta_seconds` against a world it isn't part of, returns whether it has come to rest
+    pub fn step(&mut self, world: &(impl VoxelVolume + ?Sized), delta_seconds: f32) -> bool {
+        self.velocity.y = (self.velocity.y - GRAVITY * delta_seconds).max(-MAX_FALL_SPEED);
+        let position = self.position + self.velocity * delta_seconds;
+        let rotation = (Quat::from_scaled_axis(self.angular_velocity * delta_seconds) * self.rotation).normalize();
+
+        if !self.collides(world, position, rotation) {
+            self.position = position;
+            self.rotation = rotation;
+            self.resting_time = 0.0;
+            return false;
+        }
+
+        // Tip over the edge it landed on if the center of mass isn't above it, gravity pulls the far side down
+        let contacts: Vec<Vec3> = self.cells_at(position, rotation)
+            .into_iter()
+            .filter(|&(cell, _)| cell.y < 0 || !world.voxel(cell).is_empty())
+            .map(|(cell, _)| cell.as_vec3() + 0.5)
+            .collect();
+        let contact = contacts.iter().sum::<Vec3>() / contacts.len() as f32;
+        let lever = Vec3::new(self.position.x - contact.x, 0.0, self.position.z - contact.z);
+        self.angular_velocity = if lever.length() > 0.5 {
+            lever.cross(Vec3::NEG_Y).normalize_or_zero() * TIP_SPEED
+        } else {
+            Vec3::ZERO
+        };
+        self.velocity = Vec3::ZERO;
+
+        let tipped = (Quat::from_scaled_axis(self.angular_velocity * delta_seconds) * self.rotation).normalize();
+        if self.angular_velocity != Vec3::ZERO && !self.collides(world, self.position, tipped) {
+            self.rotation = tipped;
+            self.resting_time = 0.0;
+            return false;
+        }
+        self.angular_velocity = Vec3::ZERO;
+        self.resting_time += delta_seconds;
+        self.resting_time >= REST_TIME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::tools::plane;
+    use crate::voxel::VOXEL_TYPE_SAND;
+    use super::*;
+
+    fn stone() -> Voxel {
+        Voxel::material(Vec3::splat(0.5), VOXEL_TYPE_STONE)
+    }
+
+    fn sand() -> Voxel {
+        Voxel::material(Vec3::new(0.8, 0.7, 0.4), VOXEL_TYPE_SAND)
+    }
+
+    fn set(grid: &mut VoxelGrid, position: IVec3, voxel: Voxel) {
+        *grid.get_mut(position.x as u32, position.y as u32, position.z as u32).unwrap() = voxel;
+    }
+
+    #[test]
+    fn finds_only_unsupported_islands() {
+        let mut grid = VoxelGrid::new(16, Vec3::ZERO);
+        // A pillar standing on the bottom of the grid with an arm reaching out
+        for y in 0..6 {
+            set(&mut grid, IVec3::new(2, y, 2), stone());
+        }
+        set(&mut grid, IVec3::new(3, 5, 2), stone());
+        // A floating block next to the arm but not touching it
+        plane(&grid, IVec3::new(5, 5, 2), IVec3::new(6, 5, 3), stone()).apply(&mut grid);
+        // A block resting on sand
+        set(&mut grid, IVec3::new(10, 0, 10), sand());
+        set(&mut grid, IVec3::new(10, 1, 10), stone());
+
+        let seeds = [IVec3::new(4, 5, 2), IVec3::new(10, 2, 10)];
+        let islands = detached_islands(&grid, seeds, &HashSet::new(), MAX_ISLAND_SIZE);
+        assert_eq!(islands.len(), 1);
+        assert_eq!(islands[0].len(), 4);
+        assert!(islands[0].contains(&IVec3::new(6, 5, 3)));
+
+        // Cutting the pillar frees its top and the arm
+        let ignore = HashSet::from([IVec3::new(2, 3, 2)]);
+        let islands = detached_islands(&grid, [IVec3::new(2, 3, 2)], &ignore, MAX_ISLAND_SIZE);
+        assert_eq!(islands.len(), 1);
+        assert_eq!(islands[0].len(), 3);
+        assert!(detached_islands(&grid, [IVec3::new(2, 3, 2)], &ignore, 2).is_empty());
+    }
+
+    #[test]
+    fn falls_and_rests_on_the_floor() {
+        let mut grid = VoxelGrid::new(16, Vec3::ZERO);
+        plane(&grid, IVec3::ZERO, IVec3::new(15, 0, 15), sand()).apply(&mut grid);
+        let mut world = grid.clone();
+        plane(&grid, IVec3::new(4, 10, 4), IVec3::new(6, 10, 5), stone()).apply(&mut grid);
+
+        let island: Vec<IVec3> = detached_islands(&grid, [IVec3::new(4, 10, 4)], &HashSet::new(), MAX_ISLAND_SIZE).remove(0);
+        let mut body = RigidVoxelBody::from_island(&grid, &island);
+        assert_eq!(body.cells().len(), 6);
+        assert!(body.cells().iter().all(|(cell, _)| island.contains(cell)));
+
+        let mut rested = false;
+        for _ in 0..600 {
+            if body.step(&world, 1.0 / 60.0) {
+                rested = true;
+                break;
+            }
+        }
+        assert!(rested);
+        let cells = body.cells();
+        assert_eq!(cells.len(), 6);
+        assert!(cells.iter().all(|(cell, voxel)| cell.y == 1 && *voxel == stone()), "{cells:?}");
+
+        // Resting bodies merge back, and are then held up like the rest of the world
+        for (cell, voxel) in cells {
+            set(&mut world, cell, voxel);
+        }
+        assert!(detached_islands(&world, [IVec3::new(4, 1, 4)], &HashSet::new(), MAX_ISLAND_SIZE).is_empty());
+    }
+
+    #[test]
+    fn overhanging_bodies_tip_over() {
+        let mut world = VoxelGrid::new(16, Vec3::ZERO);
+        // A one voxel post to land on
+        for y in 0..4 {
+            set(&mut world, IVec3::new(5, y, 5), sand());
+        }
+        let mut grid = world.clone();
+        // A beam with its center of mass well off the post
+        plane(&grid, IVec3::new(5, 8, 5), IVec3::new(9, 8, 5), stone()).apply(&mut grid);
+        let island = detached_islands(&grid, [IVec3::new(5, 8, 5)], &HashSet::new(), MAX_ISLAND_SIZE).remove(0);
+        let mut body = RigidVoxelBody::from_island(&grid, &island);
+
+        for _ in 0..600 {
+            if body.step(&world, 1.0 / 60.0) {
+                break;
+            }
+        }
+        assert!(body.rotation.angle_between(Quat::IDENTITY) > 0.3, "{:?}", body.rotation);
+    }
+}
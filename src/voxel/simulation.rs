@@ -0,0 +1,292 @@
+use bevy::prelude::{IVec3, Vec3};
+
+use super::brush::Brush;
+use super::regions::{ActiveRegions, REGION_SIZE};
+use super::{Voxel, VoxelGrid, VOXEL_TYPE_SAND, VOXEL_TYPE_WATER};
+
+const SAND_TARGET_COUNT: i32 = 9;
+
+type Index = (i32, i32, i32);
+
+/// CPU port of `physics.wgsl`, for running the cellular automaton without a GPU.
+/// Like the shader it only visits awake regions, so a settled grid costs next to nothing per tick,
+/// and it ping-pongs between two grids instead of copying the result back.
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    grids: [VoxelGrid; 2],
+    // Which of `grids` holds the most recent tick
+    front: usize,
+    regions: ActiveRegions,
+}
+
+impl Simulation {
+    pub fn new(grid: VoxelGrid) -> Self {
+        let regions = ActiveRegions::new(grid.dim());
+        Self {
+            grids: [grid.clone(), grid],
+            front: 0,
+            regions,
+        }
+    }
+
+    pub fn grid(&self) -> &VoxelGrid {
+        &self.grids[self.front]
+    }
+
+    #[cfg(test)]
+    pub fn regions(&self) -> &ActiveRegions {
+        &self.regions
+    }
+
+    #[cfg(test)]
+    pub fn is_settled(&self) -> bool {
+        self.regions.active_count() == 0
+    }
+
+    /// Edits a single voxel and wakes the regions around it
+    pub fn set(&mut self, x: u32, y: u32, z: u32, voxel: Voxel) {
+        if !self.grid().in_bounds(x as i32, y as i32, z as i32) {
+            return;
+        }
+        for grid in &mut self.grids {
+            *grid.get_mut(x, y, z).unwrap() = voxel;
+        }
+        self.regions.wake(x as i32, y as i32, z as i32);
+    }
+
+    /// Applies the brush around the raycast selection like the edit pass does, returning how many voxels changed.
+    /// Nothing is edited when the selection is outside of the grid.
+    pub fn apply_brush(&mut self, brush: &Brush, selected: Vec3, normal: Vec3) -> usize {
+        let grid = self.grid();
+        let selected_index = selected.as_ivec3();
+        if !grid.in_bounds(selected_index.x, selected_index.y, selected_index.z) {
+            return 0;
+        }
+
+        let center = brush.center(selected, normal);
+        let radius = brush.radius as i32;
+        let mut edits = Vec::new();
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    let offset = IVec3::new(x, y, z);
+                    let index = center + offset;
+                    if !brush.contains(offset) || !grid.in_bounds(index.x, index.y, index.z) {
+                        continue;
+                    }
+                    let voxel = *grid.get(index.x as u32, index.y as u32, index.z as u32).unwrap();
+                    let next_voxel = brush.apply(voxel);
+                    if next_voxel != voxel {
+                        edits.push((index, next_voxel));
+                    }
+                }
+            }
+        }
+
+        for &(index, voxel) in &edits {
+            self.set(index.x as u32, index.y as u32, index.z as u32, voxel);
+        }
+        edits.len()
+    }
+
+    /// Runs a single physics tick, returning how many regions were simulated
+    pub fn step(&mut self) -> usize {
+        let active = self.regions.take_active();
+        let (a, b) = self.grids.split_at_mut(1);
+        let (front, back) = if self.front == 0 {
+            (&a[0], &mut b[0])
+        } else {
+            (&b[0], &mut a[0])
+        };
+
+        for &region in &active {
+            let (rx, ry, rz) = self.regions.region_position(region);
+            for i in 0..REGION_SIZE {
+                for j in 0..REGION_SIZE {
+                    for k in 0..REGION_SIZE {
+                        let (x, y, z) = (rx * REGION_SIZE + i, ry * REGION_SIZE + j, rz * REGION_SIZE + k);
+                        if !front.in_bounds(x as i32, y as i32, z as i32) {
+                            continue;
+                        }
+                        let voxel = *front.get(x, y, z).unwrap();
+                        let index = (x as i32, y as i32, z as i32);
+                        let next_voxel = handle_voxel_physics(front, index, voxel);
+                        *back.get_mut(x, y, z).unwrap() = next_voxel;
+                        if next_voxel != voxel {
+                            self.regions.wake(x as i32, y as i32, z as i32);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.front = 1 - self.front;
+        active.len()
+    }
+}
+
+fn handle_voxel_physics(grid: &VoxelGrid, index: Index, voxel: Voxel) -> Voxel {
+    if voxel.is_empty() {
+        return handle_falling_sand(grid, index);
+    }
+
+    match voxel.get_voxel_type() {
+        VOXEL_TYPE_SAND => handle_sand(grid, index, voxel),
+        VOXEL_TYPE_WATER => voxel,
+        _ => voxel,
+    }
+}
+
+fn voxel_at(grid: &VoxelGrid, (x, y, z): Index) -> Option<Voxel> {
+    if !grid.in_bounds(x, y, z) {
+        return None;
+    }
+    grid.get(x as u32, y as u32, z as u32).copied()
+}
+
+/// Where sand tries to move, in order of preference: straight down, then the rest of the 3x3 below
+fn sand_target_offset(i: i32) -> Index {
+    if i == 0 {
+        return (0, -1, 0);
+    }
+    // Skip the centre of the 3x3, it was tried first
+    let mut j = i - 1;
+    if j >= 4 {
+        j += 1;
+    }
+    (j / 3 - 1, -1, j % 3 - 1)
+}
+
+fn is_sand(grid: &VoxelGrid, index: Index) -> bool {
+    voxel_at(grid, index).is_some_and(|voxel| !voxel.is_empty() && voxel.get_voxel_type() == VOXEL_TYPE_SAND)
+}
+
+/// The cell a sand voxel wants to move into this tick, or its own cell if it can't move
+fn sand_destination(grid: &VoxelGrid, (x, y, z): Index) -> Index {
+    for i in 0..SAND_TARGET_COUNT {
+        let (dx, dy, dz) = sand_target_offset(i);
+        let destination = (x + dx, y + dy, z + dz);
+        if voxel_at(grid, destination).is_some_and(|voxel| voxel.is_empty()) {
+            return destination;
+        }
+    }
+    (x, y, z)
+}
+
+/// The sand voxel that moves into an empty cell this tick, or the cell itself if none does.
+/// When several want the same cell, the one moving in the most preferred direction wins.
+fn sand_origin(grid: &VoxelGrid, (x, y, z): Index) -> Index {
+    for i in 0..SAND_TARGET_COUNT {
+        let (dx, dy, dz) = sand_target_offset(i);
+        let origin = (x - dx, y - dy, z - dz);
+        if is_sand(grid, origin) && sand_destination(grid, origin) == (x, y, z) {
+            return origin;
+        }
+    }
+    (x, y, z)
+}
+
+fn handle_sand(grid: &VoxelGrid, index: Index, voxel: Voxel) -> Voxel {
+    let destination = sand_destination(grid, index);
+    if destination == index || sand_origin(grid, destination) != index {
+        return voxel;
+    }
+    Voxel::EMPTY
+}
+
+fn handle_falling_sand(grid: &VoxelGrid, index: Index) -> Voxel {
+    let origin = sand_origin(grid, index);
+    if origin == index {
+        return Voxel::EMPTY;
+    }
+    voxel_at(grid, origin).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::brush::{BrushOperation, BrushShape};
+
+    fn sand() -> Voxel {
+        let mut voxel = Voxel::default();
+        voxel.set_color(Vec3::new(0.5, 0.3, 0.1));
+        voxel
+    }
+
+    #[test]
+    fn sand_column_settles() {
+        let mut grid = VoxelGrid::new(16, Vec3::ZERO);
+        for y in 4..12 {
+            *grid.get_mut(8, y, 8).unwrap() = sand();
+        }
+        let mut simulation = Simulation::new(grid);
+
+        for _ in 0..64 {
+            simulation.step();
+        }
+
+        assert!(simulation.is_settled());
+        assert_eq!(simulation.step(), 0);
+        let count = simulation.grid().voxels.iter().filter(|v| !v.is_empty()).count();
+        assert_eq!(count, 8);
+        assert!(!simulation.grid().get(8, 0, 8).unwrap().is_empty());
+    }
+
+    #[test]
+    fn buffers_agree_once_settled() {
+        let mut grid = VoxelGrid::new(16, Vec3::ZERO);
+        for x in 2..14 {
+            for y in 6..10 {
+                *grid.get_mut(x, y, 3).unwrap() = sand();
+            }
+        }
+        let mut simulation = Simulation::new(grid);
+        while !simulation.is_settled() {
+            simulation.step();
+        }
+
+        let [a, b] = &simulation.grids;
+        assert_eq!(a.voxels, b.voxels);
+        assert_eq!(a.voxels.iter().filter(|v| !v.is_empty()).count(), 12 * 4);
+    }
+
+    #[test]
+    fn brush_edits_settled_grid() {
+        let mut grid = VoxelGrid::new(16, Vec3::ZERO);
+        for x in 0..16 {
+            for z in 0..16 {
+                *grid.get_mut(x, 0, z).unwrap() = sand();
+            }
+        }
+        let mut simulation = Simulation::new(grid);
+        simulation.step();
+        assert!(simulation.is_settled());
+
+        // A cube dug out of the floor, only the bottom layer of it was solid
+        let brush = Brush { shape: BrushShape::Cube, operation: BrushOperation::Remove, radius: 1, ..Default::default() };
+        assert_eq!(simulation.apply_brush(&brush, Vec3::new(3.0, 0.0, 3.0), Vec3::Y), 9);
+        assert!(!simulation.is_settled());
+
+        // Adding builds on top of the face pointed at
+        let brush = Brush { radius: 0, ..Default::default() };
+        assert_eq!(simulation.apply_brush(&brush, Vec3::new(8.0, 0.0, 8.0), Vec3::Y), 1);
+        assert!(!simulation.grid().get(8, 1, 8).unwrap().is_empty());
+
+        // Nothing under the crosshair
+        assert_eq!(simulation.apply_brush(&brush, Vec3::splat(-1.0), Vec3::ZERO), 0);
+    }
+
+    #[test]
+    fn edits_wake_only_nearby_regions() {
+        let mut simulation = Simulation::new(VoxelGrid::new(32, Vec3::ZERO));
+        simulation.step();
+        assert!(simulation.is_settled());
+
+        simulation.set(20, 20, 20, sand());
+        assert_eq!(simulation.regions().active_count(), 1);
+
+        // Neighbours across a region boundary are woken as well
+        simulation.set(8, 8, 8, sand());
+        assert_eq!(simulation.regions().active_count(), 1 + 8);
+    }
+}
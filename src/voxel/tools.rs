@@ -0,0 +1,222 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::{IVec3, UVec3};
+
+use super::undo::{EditDiff, VoxelChange};
+use super::{Voxel, VoxelGrid};
+
+/// Most voxels a flood fill may change unless told otherwise, filling an open area would otherwise run over the whole grid
+pub const DEFAULT_FILL_LIMIT: usize = 1 << 16;
+
+const NEIGHBORS: [IVec3; 6] = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z];
+
+// The editing tools read the grid and return the changes they make, for uploading to the GPU and the undo history.
+// `EditDiff::apply` makes them on a CPU grid.
+
+/// Sets every voxel connected to `start` by faces and of the same material as it to `voxel`.
+/// Returns `None` without changing anything if more than `limit` voxels would change.
+pub fn flood_fill(grid: &VoxelGrid, start: UVec3, voxel: Voxel, limit: usize) -> Option<EditDiff> {
+    let target = *grid.get(start.x, start.y, start.z)?;
+    if target == voxel {
+        return Some(EditDiff::default());
+    }
+
+    let mut visited = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    let mut changes = Vec::new();
+    while let Some(position) = queue.pop_front() {
+        if changes.len() == limit {
+            return None;
+        }
+        changes.push(change(grid, position.as_ivec3(), voxel));
+
+        for offset in NEIGHBORS {
+            let neighbor = position.as_ivec3() + offset;
+            if !grid.in_bounds(neighbor.x, neighbor.y, neighbor.z) {
+                continue;
+            }
+            let neighbor = neighbor.as_uvec3();
+            if grid.get(neighbor.x, neighbor.y, neighbor.z).is_some_and(|&other| same_material(other, target)) && visited.insert(neighbor) {
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    Some(EditDiff::from_log(changes))
+}
+
+/// Swaps every voxel of the same material as `from` in the box between two corners for `to`
+pub fn replace(grid: &VoxelGrid, corner_a: IVec3, corner_b: IVec3, from: Voxel, to: Voxel) -> EditDiff {
+    let changes = box_positions(corner_a, corner_b)
+        .filter(|&position| voxel_at(grid, position).is_some_and(|voxel| same_material(voxel, from)))
+        .map(|position| change(grid, position, to));
+    edits(grid, changes)
+}
+
+/// A one voxel thick line from `a` to `b`, both ends included
+pub fn line(grid: &VoxelGrid, a: IVec3, b: IVec3, voxel: Voxel) -> EditDiff {
+    let delta = b - a;
+    let steps = delta.abs().max_element();
+    let changes = (0..=steps).map(|step| {
+        let t = if steps == 0 { 0.0 } else { step as f32 / steps as f32 };
+        let position = a + (delta.as_vec3() * t).round().as_ivec3();
+        change(grid, position, voxel)
+    });
+    edits(grid, changes)
+}
+
+/// A filled rectangle between two corners.
+/// If the corners don't share a coordinate, the box between them is flattened along its thinnest side, at `a`.
+pub fn plane(grid: &VoxelGrid, a: IVec3, b: IVec3, voxel: Voxel) -> EditDiff {
+    let extent = (b - a).abs();
+    let axis = (0..3).min_by_key(|&axis| extent[axis]).unwrap();
+    let mut b = b;
+    b[axis] = a[axis];
+    let changes = box_positions(a, b).map(|position| change(grid, position, voxel));
+    edits(grid, changes)
+}
+
+/// The one voxel thick shell of the box between two corners
+pub fn hollow_box(grid: &VoxelGrid, a: IVec3, b: IVec3, voxel: Voxel) -> EditDiff {
+    let min = a.min(b);
+    let max = a.max(b);
+    let changes = box_positions(a, b)
+        .filter(|&position| position.cmpeq(min).any() || position.cmpeq(max).any())
+        .map(|position| change(grid, position, voxel));
+    edits(grid, changes)
+}
+
+/// Whether two voxels are both empty or both solid with the same type, whatever their colors
+fn same_material(a: Voxel, b: Voxel) -> bool {
+    a.is_empty() == b.is_empty() && (a.is_empty() || a.get_voxel_type() == b.get_voxel_type())
+}
+
+fn voxel_at(grid: &VoxelGrid, position: IVec3) -> Option<Voxel> {
+    if !grid.in_bounds(position.x, position.y, position.z) {
+        return None;
+    }
+    let position = position.as_uvec3();
+    grid.get(position.x, position.y, position.z).copied()
+}
+
+/// The change setting `position` to `voxel`, out of bounds positions get an index past the end of the grid
+fn change(grid: &VoxelGrid, position: IVec3, voxel: Voxel) -> VoxelChange {
+    let old = voxel_at(grid, position);
+    let index = match old {
+        Some(_) => {
+            let position = position.as_uvec3();
+            grid.index(position.x, position.y, position.z)
+        }
+        None => u32::MAX,
+    };
+    VoxelChange { index, old: old.unwrap_or_default(), new: voxel }
+}
+
+/// Drops the changes that fell outside the grid
+fn edits(grid: &VoxelGrid, changes: impl Iterator<Item = VoxelChange>) -> EditDiff {
+    let len = grid.dim().pow(3);
+    EditDiff::from_log(changes.filter(|change| change.index < len))
+}
+
+/// Every position in the box between two corners, both included
+fn box_positions(a: IVec3, b: IVec3) -> impl Iterator<Item = IVec3> {
+    let min = a.min(b);
+    let max = a.max(b);
+    (min.x..=max.x).flat_map(move |x| (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z))))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Vec3;
+
+    use super::*;
+    use crate::voxel::{VOXEL_TYPE_METAL, VOXEL_TYPE_SAND, VOXEL_TYPE_STONE};
+
+    fn stone() -> Voxel {
+        Voxel::material(Vec3::splat(0.5), VOXEL_TYPE_STONE)
+    }
+
+    fn gold() -> Voxel {
+        Voxel::material(Vec3::new(1.0, 0.8, 0.2), VOXEL_TYPE_METAL)
+    }
+
+    /// Sand with a different color at every position, like the generated world's
+    fn speckled_sand(position: IVec3) -> Voxel {
+        Voxel::material(Vec3::new(0.8, 0.7, 0.4) + position.as_vec3() * 0.01, VOXEL_TYPE_SAND)
+    }
+
+    fn solid_count(grid: &VoxelGrid) -> usize {
+        grid.voxels.iter().filter(|voxel| !voxel.is_empty()).count()
+    }
+
+    #[test]
+    fn flood_fill_stays_in_connected_region() {
+        let mut grid = VoxelGrid::new(8, Vec3::ZERO);
+        // A wall at x = 4 splits the empty grid in two
+        plane(&grid, IVec3::new(4, 0, 0), IVec3::new(4, 7, 7), stone()).apply(&mut grid);
+
+        let diff = flood_fill(&grid, UVec3::ZERO, gold(), DEFAULT_FILL_LIMIT).unwrap();
+        assert_eq!(diff.changes().len(), 4 * 8 * 8);
+        diff.apply(&mut grid);
+        assert_eq!(grid.get(3, 7, 7), Some(&gold()));
+        assert_eq!(grid.get(5, 0, 0), Some(&Voxel::EMPTY));
+
+        // The wall is connected to itself only
+        let diff = flood_fill(&grid, UVec3::new(4, 2, 2), gold(), DEFAULT_FILL_LIMIT).unwrap();
+        assert_eq!(diff.changes().len(), 8 * 8);
+
+        assert!(flood_fill(&grid, UVec3::new(7, 0, 0), stone(), 100).is_none());
+        assert!(flood_fill(&grid, UVec3::new(7, 0, 0), stone(), 3 * 8 * 8).is_some());
+    }
+
+    #[test]
+    fn replace_only_touches_box() {
+        let mut grid = VoxelGrid::new(8, Vec3::ZERO);
+        line(&grid, IVec3::ZERO, IVec3::new(7, 0, 0), stone()).apply(&mut grid);
+
+        let diff = replace(&grid, IVec3::new(2, -1, -1), IVec3::new(4, 1, 1), stone(), gold());
+        assert_eq!(diff.changes().len(), 3);
+        diff.apply(&mut grid);
+        assert_eq!(grid.get(1, 0, 0), Some(&stone()));
+        assert_eq!(grid.get(3, 0, 0), Some(&gold()));
+    }
+
+    #[test]
+    fn fill_and_replace_match_material_not_color() {
+        let mut grid = VoxelGrid::new(8, Vec3::ZERO);
+        for position in box_positions(IVec3::ZERO, IVec3::new(7, 1, 7)) {
+            let cell = position.as_uvec3();
+            *grid.get_mut(cell.x, cell.y, cell.z).unwrap() = speckled_sand(position);
+        }
+        assert_ne!(grid.get(0, 0, 0), grid.get(1, 0, 0));
+
+        // Every shade of sand is filled, the empty space above it isn't
+        let diff = flood_fill(&grid, UVec3::new(3, 1, 3), stone(), DEFAULT_FILL_LIMIT).unwrap();
+        assert_eq!(diff.changes().len(), 8 * 2 * 8);
+
+        let diff = replace(&grid, IVec3::new(0, 0, 0), IVec3::new(3, 3, 3), speckled_sand(IVec3::new(7, 0, 7)), stone());
+        assert_eq!(diff.changes().len(), 4 * 2 * 4);
+        diff.apply(&mut grid);
+        assert_eq!(grid.get(2, 1, 2), Some(&stone()));
+        assert_eq!(grid.get(2, 2, 2), Some(&Voxel::EMPTY));
+        assert_eq!(grid.get(4, 0, 4), Some(&speckled_sand(IVec3::new(4, 0, 4))));
+    }
+
+    #[test]
+    fn shapes_cover_expected_voxels() {
+        let grid = VoxelGrid::new(8, Vec3::ZERO);
+        assert_eq!(line(&grid, IVec3::new(0, 0, 0), IVec3::new(6, 3, 2), stone()).changes().len(), 7);
+        // Clipped to the grid
+        assert_eq!(line(&grid, IVec3::new(-4, 0, 0), IVec3::new(3, 0, 0), stone()).changes().len(), 4);
+
+        let mut grid = VoxelGrid::new(8, Vec3::ZERO);
+        plane(&grid, IVec3::new(1, 1, 1), IVec3::new(5, 2, 4), stone()).apply(&mut grid);
+        assert_eq!(solid_count(&grid), 5 * 4);
+        assert_eq!(grid.get(1, 1, 1), Some(&stone()));
+        assert_eq!(grid.get(1, 2, 1), Some(&Voxel::EMPTY));
+
+        let mut grid = VoxelGrid::new(8, Vec3::ZERO);
+        hollow_box(&grid, IVec3::new(1, 1, 1), IVec3::new(5, 5, 5), stone()).apply(&mut grid);
+        assert_eq!(solid_count(&grid), 5 * 5 * 5 - 3 * 3 * 3);
+        assert_eq!(grid.get(3, 3, 3), Some(&Voxel::EMPTY));
+    }
+}
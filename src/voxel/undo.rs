@@ -0,0 +1,158 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::Resource;
+
+use super::{Voxel, VoxelGrid};
+
+/// A single voxel going from `old` to `new`, `index` is the flat index into the grid's voxels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxelChange {
+    pub index: u32,
+    pub old: Voxel,
+    pub new: Voxel,
+}
+
+/// The voxels changed by one brush stroke or editing tool
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EditDiff {
+    changes: Vec<VoxelChange>,
+}
+
+impl EditDiff {
+    /// Collapses a log of changes in the order they happened into one change per voxel.
+    /// A voxel edited several times keeps its first old value and its last new value.
+    pub fn from_log(log: impl IntoIterator<Item = VoxelChange>) -> Self {
+        let mut merged: HashMap<u32, VoxelChange> = HashMap::new();
+        for change in log {
+            merged
+                .entry(change.index)
+                .and_modify(|merged| merged.new = change.new)
+                .or_insert(change);
+        }
+
+        let mut changes: Vec<VoxelChange> = merged.into_values().filter(|change| change.old != change.new).collect();
+        changes.sort_by_key(|change| change.index);
+        Self { changes }
+    }
+
+    pub fn changes(&self) -> &[VoxelChange] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Makes the changes on a CPU grid
+    #[allow(dead_code)]
+    pub fn apply(&self, grid: &mut VoxelGrid) {
+        for change in &self.changes {
+            if let Some(voxel) = grid.voxels.get_mut(change.index as usize) {
+                *voxel = change.new;
+            }
+        }
+    }
+
+    /// The diff that takes the voxels back to their old values
+    pub fn inverse(&self) -> Self {
+        let changes = self.changes.iter()
+            .map(|change| VoxelChange { index: change.index, old: change.new, new: change.old })
+            .collect();
+        Self { changes }
+    }
+}
+
+/// Undo and redo stacks of brush strokes, keeping at most `depth` strokes to undo
+#[derive(Resource, Debug)]
+pub struct EditHistory {
+    pub depth: usize,
+    undo: VecDeque<EditDiff>,
+    redo: Vec<EditDiff>,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+impl EditHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    #[cfg(test)]
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    /// Records a finished stroke, a new stroke can't be redone over so the redo stack is dropped
+    pub fn push(&mut self, diff: EditDiff) {
+        if diff.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.undo.push_back(diff);
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Returns the diff to apply to undo the last stroke
+    pub fn undo(&mut self) -> Option<EditDiff> {
+        let diff = self.undo.pop_back()?;
+        let inverse = diff.inverse();
+        self.redo.push(diff);
+        Some(inverse)
+    }
+
+    /// Returns the diff to apply to redo the last undone stroke
+    pub fn redo(&mut self) -> Option<EditDiff> {
+        let diff = self.redo.pop()?;
+        self.undo.push_back(diff.clone());
+        Some(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(index: u32, old: u32, new: u32) -> VoxelChange {
+        VoxelChange { index, old: Voxel::new(old), new: Voxel::new(new) }
+    }
+
+    #[test]
+    fn log_collapses_per_voxel() {
+        let diff = EditDiff::from_log([change(5, 1, 0), change(2, 0, 3), change(5, 0, 7), change(9, 4, 0), change(9, 0, 4)]);
+        assert_eq!(diff.changes(), &[change(2, 0, 3), change(5, 1, 7)]);
+        assert_eq!(diff.inverse().changes(), &[change(2, 3, 0), change(5, 7, 1)]);
+    }
+
+    #[test]
+    fn undo_redo_and_depth() {
+        let mut history = EditHistory::new(2);
+        for i in 0..3 {
+            history.push(EditDiff::from_log([change(i, 0, 1)]));
+        }
+        assert_eq!(history.undo_len(), 2);
+
+        assert_eq!(history.undo().unwrap().changes(), &[change(2, 1, 0)]);
+        assert_eq!(history.undo().unwrap().changes(), &[change(1, 1, 0)]);
+        assert!(history.undo().is_none());
+
+        assert_eq!(history.redo().unwrap().changes(), &[change(1, 0, 1)]);
+        history.push(EditDiff::from_log([change(8, 0, 1)]));
+        assert_eq!(history.redo_len(), 0);
+        assert_eq!(history.undo_len(), 2);
+    }
+}